@@ -1,29 +1,199 @@
-use glam::UVec2;
+use glam::{Quat, UVec2, Vec2, Vec3};
 use egui::Context;
 
 use std::string::String;
 
 use crate::renderer;
+use crate::renderer::shape::ShapeKind;
+use crate::sdf::brush::{Brush, BrushKind, BrushShape, MAX_POLYGON_POINTS};
+use crate::sdf::generate::{self, CombineOp, Layer, Source};
+use crate::sdf::history::{History, HistoryConfig};
+use crate::sdf::import::{ImportOptions, MaskChannel};
+use crate::sdf::material::{self, BevelProfile, Material, MAX_MATERIALS};
+use crate::sdf::query::RayHit;
+use crate::sdf::tiles::Topology;
+
+// What the left mouse button does in the view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Terrain,
+    // Raises and lowers the terrain under the brush instead of adding and removing it
+    Height,
+    Select,
+}
+
+// Selected shapes as the selection window and gizmo show them, the material is the first shape's
+#[derive(Debug, Clone, Copy)]
+pub struct SelectionInfo {
+    pub count: usize,
+    pub centre: Vec2,
+    pub view_size: Vec2,
+    pub color: [f32; 3],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    pub emissive_intensity: f32,
+}
+
+// Outlines drawn over the view, boxes are min and max corners in view space
+#[derive(Debug, Clone, Default)]
+pub struct Highlights {
+    pub hovered: Option<[Vec2; 2]>,
+    pub selected: Vec<[Vec2; 2]>,
+    pub selection_box: Option<[Vec2; 2]>,
+}
+
+// Sizes of the viewport gizmo in points
+const GIZMO_RING_RADIUS: f32 = 64.;
+const GIZMO_ARROW_LENGTH: f32 = 44.;
+const GIZMO_SCALE_OFFSET: f32 = 30.;
+const GIZMO_HANDLE_SIZE: f32 = 10.;
+
+// Part of the viewport gizmo a drag started on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GizmoHandle {
+    Move,
+    MoveX,
+    MoveY,
+    Rotate,
+    Scale,
+}
+
+impl GizmoHandle {
+    // Handle under `offset` from the gizmo's centre, in points with y down
+    fn at(offset: Vec2) -> Option<Self> {
+        let near = |p: Vec2| (offset - p).abs().max_element() <= GIZMO_HANDLE_SIZE;
+        let distance = offset.length();
+        if near(Vec2::ZERO) {
+            Some(Self::Move)
+        } else if near(Vec2::splat(GIZMO_SCALE_OFFSET)) {
+            Some(Self::Scale)
+        } else if (distance - GIZMO_RING_RADIUS).abs() <= 0.5 * GIZMO_HANDLE_SIZE {
+            Some(Self::Rotate)
+        } else if offset.y.abs() <= 0.5 * GIZMO_HANDLE_SIZE && offset.x > 0. && offset.x <= GIZMO_ARROW_LENGTH + 0.5 * GIZMO_HANDLE_SIZE {
+            Some(Self::MoveX)
+        } else if offset.x.abs() <= 0.5 * GIZMO_HANDLE_SIZE && offset.y < 0. && -offset.y <= GIZMO_ARROW_LENGTH + 0.5 * GIZMO_HANDLE_SIZE {
+            Some(Self::MoveY)
+        } else {
+            None
+        }
+    }
+
+    fn cursor(self) -> egui::CursorIcon {
+        match self {
+            Self::Move => egui::CursorIcon::Move,
+            Self::MoveX => egui::CursorIcon::ResizeHorizontal,
+            Self::MoveY => egui::CursorIcon::ResizeVertical,
+            Self::Rotate => egui::CursorIcon::Grab,
+            Self::Scale => egui::CursorIcon::ResizeNwSe,
+        }
+    }
+}
+
+// Change to the selected shapes, each moves to `translation + rotation * (scale * offset)` for
+// its offset from the selection's centre
+#[derive(Debug, Clone, Copy)]
+pub struct SelectionEdit {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: f32,
+    pub material: Option<([f32; 3], f32, f32)>,
+    pub emissive: Option<([f32; 3], f32)>,
+}
+
+impl Default for SelectionEdit {
+    fn default() -> Self {
+        Self {
+            translation: Vec3::ZERO,
+            rotation: Quat::IDENTITY,
+            scale: 1.,
+            material: None,
+            emissive: None,
+        }
+    }
+}
 
 pub struct GUI {
     pub cursor_size: f32,
+    pub tool: Tool,
+    brush_kind: BrushKind,
+    brush_aspect: f32,
+    brush_rotation: f32,
+    brush_sides: u32,
+    brush_thickness: f32,
+    brush_smoothness: f32,
+    brush_height: f32,
+    polygon_points: Vec<[f32; 2]>,
+    brush_material: usize,
+    palette: Vec<Material>,
+    pub palette_changed: bool,
     light_hue: f32,
     light_saturation: f32,
     light_intensity: f32,
     pub light_radius: f32,
     pub light_range: f32,
     pub exposure: f32,
+    pub shape_kind: ShapeKind,
     pub shape_color: [f32; 3],
     pub shape_metallic: f32,
     pub shape_roughness: f32,
+    pub shape_emissive: [f32; 3],
+    pub shape_emissive_intensity: f32,
     pub shape_radius: f32,
+    shape_rotation: f32,
+    shape_tilt: f32,
+    shape_aspect: f32,
+    selection: Option<SelectionInfo>,
+    selection_edit: Option<SelectionEdit>,
+    gizmo_drag: Option<GizmoHandle>,
+    highlights: Highlights,
+    pub deselect_pressed: bool,
+    pub delete_selection_pressed: bool,
     pub upsampler: renderer::Upsampler,
+    pub bvh_builder: renderer::BvhBuilder,
     pub renderer_scale: f32,
+    pub undo_pressed: bool,
+    pub redo_pressed: bool,
+    can_undo: bool,
+    can_redo: bool,
+    history_config: HistoryConfig,
+    pub redistance_pressed: bool,
+    redistance_interval: usize,
+    pub terrain_path: String,
+    pub save_pressed: bool,
+    pub load_pressed: bool,
+    sdf_size: u32,
+    world_size: f32,
+    topology: Topology,
+    pub terrain_config_pressed: bool,
+    pub image_path: String,
+    import_options: ImportOptions,
+    pub import_pressed: bool,
+    generate_layers: Vec<Layer>,
+    keep_terrain: bool,
+    pub generate_pressed: bool,
+    file_str: String,
     v_sync: bool,
     fps_str: String,
     res_str: String,
     lights_str: String,
     shapes_str: String,
+    tiles_str: String,
+    probe_str: String,
+    history_str: String,
+}
+
+// View space runs from -0.5 to 0.5 across the view with y up, like the mouse position
+fn to_screen(screen: egui::Rect, p: Vec2) -> egui::Pos2 {
+    screen.center() + egui::vec2(p.x * screen.width(), -p.y * screen.height())
+}
+
+// A handle to drag sideways, how far it was dragged this frame times `speed`. DragValues would
+// hold on to the value dragged so far instead.
+fn drag_handle(ui: &mut egui::Ui, text: &str, speed: f32) -> f32 {
+    let response = ui.add(egui::Button::new(text).sense(egui::Sense::drag()))
+    .on_hover_cursor(egui::CursorIcon::ResizeHorizontal);
+    response.drag_delta().x * speed
 }
 
 fn res_str(render_resolution: UVec2, output_resolution: UVec2) -> String {
@@ -36,23 +206,72 @@ impl GUI {
     ) -> Self {
         return Self {
             cursor_size: 1.0,
+            tool: Tool::Terrain,
+            brush_kind: BrushKind::Circle,
+            brush_aspect: 0.5,
+            brush_rotation: 0.0,
+            brush_sides: 6,
+            brush_thickness: 0.25,
+            brush_smoothness: 1.0,
+            brush_height: 0.1,
+            polygon_points: vec![[-1.0, -1.0], [1.0, -0.5], [0.5, 1.0], [-0.75, 0.75]],
+            brush_material: 0,
+            palette: material::default_palette(),
+            palette_changed: false,
             light_hue: 0.0,
             light_saturation: 0.0,
             light_intensity: 100.0,
             light_radius: 0.1,
             light_range: 1.0,
             exposure: 1.0,
+            shape_kind: ShapeKind::default(),
             shape_color: [0.5, 1.0, 0.5],
             shape_metallic: 0.,
             shape_roughness: 0.1,
+            shape_emissive: [1., 1., 1.],
+            shape_emissive_intensity: 0.,
             shape_radius: 0.5,
+            shape_rotation: 0.0,
+            shape_tilt: 0.0,
+            shape_aspect: 1.0,
+            selection: None,
+            selection_edit: None,
+            gizmo_drag: None,
+            highlights: Highlights::default(),
+            deselect_pressed: false,
+            delete_selection_pressed: false,
             upsampler: renderer::Upsampler::BLIT,
+            bvh_builder: renderer::BvhBuilder::Cpu,
             renderer_scale: 1.0 / (window.scale_factor() as f32), 
+            undo_pressed: false,
+            redo_pressed: false,
+            can_undo: false,
+            can_redo: false,
+            history_config: HistoryConfig::default(),
+            redistance_pressed: false,
+            redistance_interval: 0,
+            terrain_path: String::from("terrain.sdf"),
+            save_pressed: false,
+            load_pressed: false,
+            sdf_size: 1024,
+            world_size: 1280.,
+            topology: Topology::default(),
+            terrain_config_pressed: false,
+            image_path: String::from("terrain.png"),
+            import_options: ImportOptions::default(),
+            import_pressed: false,
+            generate_layers: vec![Layer::new(Source::defaults()[0])],
+            keep_terrain: false,
+            generate_pressed: false,
+            file_str: String::new(),
             v_sync: true,
             fps_str: format!("FPS: -"),
             res_str: format!("R. - O: -"),
             lights_str: format!("LIGHTS: -"),
             shapes_str: format!("SHAPES: -"),
+            tiles_str: String::from("TILES: -"),
+            probe_str: String::from("PROBE: -"),
+            history_str: String::from("HISTORY: -"),
         }
     }
 
@@ -64,10 +283,58 @@ impl GUI {
         self.lights_str = format!("LIGHTS: {}", num_lights);
     }
 
+    // Orientation and scale of the cursor shape, turned around z and then tilted
+    pub fn shape_transform(&self) -> (Quat, Vec3) {
+        let rotation = Quat::from_rotation_z(self.shape_rotation.to_radians()) * Quat::from_rotation_x(self.shape_tilt.to_radians());
+        (rotation, Vec3::new(1., self.shape_aspect, 1.))
+    }
+
     pub fn update_shapes(&mut self, num_shapes: usize) {
         self.shapes_str = format!("SHAPES: {}", num_shapes);
     }
 
+    pub fn update_selection(&mut self, selection: Option<SelectionInfo>, highlights: Highlights) {
+        self.selection = selection;
+        self.highlights = highlights;
+    }
+
+    // What the gizmo and selection window changed since the last call
+    pub fn take_selection_edit(&mut self) -> Option<SelectionEdit> {
+        self.selection_edit.take()
+    }
+
+    fn edit_selection(&mut self) -> &mut SelectionEdit {
+        self.selection_edit.get_or_insert_with(SelectionEdit::default)
+    }
+
+    pub fn update_tiles(&mut self, num_stored: usize) {
+        self.tiles_str = format!("TILES: {}", num_stored);
+    }
+
+    // Terrain distance under the cursor and the first hit on the way there from the view center
+    pub fn update_probe(&mut self, distance: f32, hit: Option<RayHit>) {
+        self.probe_str = match hit {
+            Some(hit) => format!("PROBE: {:.2} HIT: {:.2} ({:.2}, {:.2})", distance, hit.t, hit.normal.x, hit.normal.y),
+            None => format!("PROBE: {:.2}", distance),
+        };
+    }
+
+    pub fn update_history(&mut self, history: &History) {
+        self.can_undo = history.can_undo();
+        self.can_redo = history.can_redo();
+        self.history_str = format!("HISTORY: {}/{} ({} tiles, {} MB)", history.position(), history.len(), history.num_snapshots(), history.snapshot_bytes() >> 20);
+    }
+
+    pub fn update_terrain_config(&mut self, sdf_size: u32, world_size: f32, topology: Topology) {
+        self.sdf_size = sdf_size;
+        self.world_size = world_size;
+        self.topology = topology;
+    }
+
+    pub fn update_file_status(&mut self, status: String) {
+        self.file_str = status;
+    }
+
     pub fn update_res(&mut self, render_resolution: UVec2, output_resolution: UVec2) {
         self.res_str = res_str(render_resolution, output_resolution);
     }
@@ -85,30 +352,534 @@ impl GUI {
                 ui.label(self.res_str.as_str());
                 ui.label(self.lights_str.as_str());
                 ui.label(self.shapes_str.as_str());
+                ui.label(self.tiles_str.as_str());
+                ui.label(self.probe_str.as_str());
             });
         });
 
         egui::Window::new("Tools")
         .resizable(false)
         .show(ctx, |ui| {
+            egui::ComboBox::from_label("tool")
+            .selected_text(format!("{:?}", self.tool))
+            .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.tool, Tool::Terrain, format!("{:?}", Tool::Terrain));
+                        ui.selectable_value(&mut self.tool, Tool::Height, format!("{:?}", Tool::Height));
+                        ui.selectable_value(&mut self.tool, Tool::Select, format!("{:?}", Tool::Select));
+                    });
             ui.add(egui::Slider::new(&mut self.cursor_size, 1.0..=10.0).text("cursor size"));
+            self.draw_brush(ui);
+            self.draw_materials(ui);
+            ui.horizontal(|ui| {
+                if ui.add_enabled(self.can_undo, egui::Button::new("Undo")).clicked() {
+                    self.undo_pressed = true;
+                }
+                if ui.add_enabled(self.can_redo, egui::Button::new("Redo")).clicked() {
+                    self.redo_pressed = true;
+                }
+                ui.label(self.history_str.as_str());
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::Slider::new(&mut self.history_config.max_steps, 1..=256).text("max steps"));
+                let mut megabytes = self.history_config.max_snapshot_bytes >> 20;
+                if ui.add(egui::Slider::new(&mut megabytes, 16..=4096).logarithmic(true).text("max MB")).changed() {
+                    self.history_config.max_snapshot_bytes = megabytes << 20;
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("Redistance").clicked() {
+                    self.redistance_pressed = true;
+                }
+                ui.add(egui::Slider::new(&mut self.redistance_interval, 0..=256).text("redistance interval"));
+            });
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut self.terrain_path);
+                if ui.button("Save").clicked() {
+                    self.save_pressed = true;
+                }
+                if ui.button("Load").clicked() {
+                    self.load_pressed = true;
+                }
+            });
+            ui.horizontal(|ui| {
+                egui::ComboBox::from_label("sdf size")
+                .selected_text(format!("{}", self.sdf_size))
+                .show_ui(ui, |ui| {
+                            for size in [256, 512, 1024, 2048, 4096] {
+                                ui.selectable_value(&mut self.sdf_size, size, format!("{}", size));
+                            }
+                        });
+                ui.add(egui::Slider::new(&mut self.world_size, 256.0..=8192.0).step_by(256.0).text("world size"));
+                egui::ComboBox::from_label("topology")
+                .selected_text(self.topology.name())
+                .show_ui(ui, |ui| {
+                            for topology in Topology::ALL {
+                                ui.selectable_value(&mut self.topology, topology, topology.name());
+                            }
+                        });
+                if ui.button("Apply").clicked() {
+                    self.terrain_config_pressed = true;
+                }
+            });
+            self.draw_import(ui);
+            if !self.file_str.is_empty() {
+                ui.label(self.file_str.as_str());
+            }
             ui.add(egui::Slider::new(&mut self.light_hue, 0.0..=1.0).text("light hue"));
             ui.add(egui::Slider::new(&mut self.light_saturation, 0.0..=1.0).text("light saturation"));
             ui.add(egui::Slider::new(&mut self.light_intensity, 0.0..=1000.0).text("light intensity"));
             ui.add(egui::Slider::new(&mut self.light_radius, 0.0..=1.0).text("light radius"));
             ui.add(egui::Slider::new(&mut self.light_range, 0.0..=1.0).text("light range"));
             ui.add(egui::Slider::new(&mut self.exposure, 0.0..=100.0).text("exposure"));
+            egui::ComboBox::from_label("shape")
+            .selected_text(self.shape_kind.name())
+            .show_ui(ui, |ui| {
+                        for kind in ShapeKind::ALL {
+                            ui.selectable_value(&mut self.shape_kind, kind, kind.name());
+                        }
+                    });
             egui::widgets::color_picker::color_edit_button_rgb(ui, &mut self.shape_color);
             ui.add(egui::Slider::new(&mut self.shape_metallic, 0.0..=1.0).text("shape metallic"));
             ui.add(egui::Slider::new(&mut self.shape_roughness, 0.0..=1.0).text("shape roughness"));
+            ui.horizontal(|ui| {
+                egui::widgets::color_picker::color_edit_button_rgb(ui, &mut self.shape_emissive);
+                ui.add(egui::Slider::new(&mut self.shape_emissive_intensity, 0.0..=10.0).text("shape emissive"));
+            });
             ui.add(egui::Slider::new(&mut self.shape_radius, 0.0..=1.0).text("shape radius"));
+            ui.add(egui::Slider::new(&mut self.shape_rotation, -180.0..=180.0).text("shape rotation"));
+            ui.add(egui::Slider::new(&mut self.shape_tilt, -90.0..=90.0).text("shape tilt"));
+            ui.add(egui::Slider::new(&mut self.shape_aspect, 0.25..=4.0).text("shape aspect"));
             egui::ComboBox::from_label("upsampler")
             .selected_text(format!("{:?}", self.upsampler))
             .show_ui(ui, |ui| {
                         ui.selectable_value(&mut self.upsampler, renderer::Upsampler::TAA, format!("{:?}", renderer::Upsampler::TAA));
                         ui.selectable_value(&mut self.upsampler, renderer::Upsampler::BLIT, format!("{:?}", renderer::Upsampler::BLIT));
                     });
+            egui::ComboBox::from_label("bvh builder")
+            .selected_text(format!("{:?}", self.bvh_builder))
+            .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.bvh_builder, renderer::BvhBuilder::Cpu, format!("{:?}", renderer::BvhBuilder::Cpu));
+                        ui.selectable_value(&mut self.bvh_builder, renderer::BvhBuilder::Gpu, format!("{:?}", renderer::BvhBuilder::Gpu));
+                    });
         });
+
+        egui::Window::new("Generate")
+        .resizable(false)
+        .default_open(false)
+        .show(ctx, |ui| {
+            self.draw_generate(ui);
+        });
+
+        self.draw_highlights(ctx);
+        if let Some(selection) = self.selection.filter(|_| self.tool == Tool::Select) {
+            self.draw_gizmo(ctx, &selection);
+            egui::Window::new("Selection")
+            .resizable(false)
+            .show(ctx, |ui| {
+                self.draw_selection(ui, &selection);
+            });
+        }
+    }
+
+    fn draw_highlights(&self, ctx: &Context) {
+        let screen = ctx.screen_rect();
+        let painter = ctx.layer_painter(egui::LayerId::background());
+        let rect = |[min, max]: [Vec2; 2]| egui::Rect::from_two_pos(to_screen(screen, min), to_screen(screen, max));
+        for bounds in self.highlights.selected.iter().copied() {
+            painter.rect_stroke(rect(bounds), 0., egui::Stroke::new(2., egui::Color32::from_rgb(255, 192, 0)));
+        }
+        if let Some(bounds) = self.highlights.hovered {
+            painter.rect_stroke(rect(bounds), 0., egui::Stroke::new(1., egui::Color32::WHITE));
+        }
+        if let Some(bounds) = self.highlights.selection_box {
+            painter.rect_filled(rect(bounds), 0., egui::Color32::from_white_alpha(16));
+            painter.rect_stroke(rect(bounds), 0., egui::Stroke::new(1., egui::Color32::WHITE));
+        }
+    }
+
+    // Handles drawn around the selection's centre: the square moves it with the mouse, the arrows
+    // along one axis, the ring turns it around z and the corner square scales it
+    fn draw_gizmo(&mut self, ctx: &Context, selection: &SelectionInfo) {
+        let screen = ctx.screen_rect();
+        let world_per_point = Vec2::new(selection.view_size.x / screen.width(), -selection.view_size.y / screen.height());
+        let centre = to_screen(screen, selection.centre);
+        let size = egui::Vec2::splat(2. * (GIZMO_RING_RADIUS + GIZMO_HANDLE_SIZE));
+        egui::Area::new(egui::Id::new("gizmo"))
+        .fixed_pos(centre)
+        .pivot(egui::Align2::CENTER_CENTER)
+        .show(ctx, |ui| {
+            let (rect, response) = ui.allocate_exact_size(size, egui::Sense::drag());
+            let centre = rect.center();
+            let offset = |p: egui::Pos2| Vec2::new(p.x - centre.x, p.y - centre.y);
+            if response.drag_started() {
+                self.gizmo_drag = ctx.input(|i| i.pointer.press_origin()).and_then(|p| GizmoHandle::at(offset(p)));
+            }
+            let active = self.gizmo_drag.or_else(|| response.hover_pos().and_then(|p| GizmoHandle::at(offset(p))));
+            if let Some(handle) = active {
+                ctx.set_cursor_icon(handle.cursor());
+            }
+            if let (Some(handle), Some(pointer)) = (self.gizmo_drag, response.interact_pointer_pos()) {
+                let delta = response.drag_delta();
+                if delta != egui::Vec2::ZERO {
+                    let to = offset(pointer);
+                    let from = to - Vec2::new(delta.x, delta.y);
+                    self.drag_gizmo(handle, from, to, world_per_point);
+                }
+            }
+            if response.drag_stopped() {
+                self.gizmo_drag = None;
+            }
+
+            let painter = ui.painter();
+            let stroke = |handle: GizmoHandle, color: egui::Color32| {
+                let width = if active == Some(handle) { 3. } else { 2. };
+                egui::Stroke::new(width, color)
+            };
+            let red = egui::Color32::from_rgb(230, 70, 70);
+            let green = egui::Color32::from_rgb(90, 200, 90);
+            let blue = egui::Color32::from_rgb(80, 140, 240);
+            let white = egui::Color32::WHITE;
+            painter.circle_stroke(centre, GIZMO_RING_RADIUS, stroke(GizmoHandle::Rotate, blue));
+            painter.arrow(centre, egui::vec2(GIZMO_ARROW_LENGTH, 0.), stroke(GizmoHandle::MoveX, red));
+            painter.arrow(centre, egui::vec2(0., -GIZMO_ARROW_LENGTH), stroke(GizmoHandle::MoveY, green));
+            let square = |p: egui::Pos2| egui::Rect::from_center_size(p, egui::Vec2::splat(GIZMO_HANDLE_SIZE));
+            painter.rect_stroke(square(centre), 0., stroke(GizmoHandle::Move, white));
+            let corner = centre + egui::Vec2::splat(GIZMO_SCALE_OFFSET);
+            painter.line_segment([centre, corner], egui::Stroke::new(1., white));
+            painter.rect_filled(square(corner), 0., if active == Some(GizmoHandle::Scale) { white } else { egui::Color32::from_gray(180) });
+        });
+    }
+
+    // Turns a drag of `handle` from `from` to `to`, in points from the gizmo's centre with y down,
+    // into an edit of the selection
+    fn drag_gizmo(&mut self, handle: GizmoHandle, from: Vec2, to: Vec2, world_per_point: Vec2) {
+        let moved = (to - from) * world_per_point;
+        match handle {
+            GizmoHandle::Move => self.edit_selection().translation += moved.extend(0.),
+            GizmoHandle::MoveX => self.edit_selection().translation.x += moved.x,
+            GizmoHandle::MoveY => self.edit_selection().translation.y += moved.y,
+            GizmoHandle::Rotate => {
+                // Flipped to have y up like the world, so that angles turn the same way
+                let flip = Vec2::new(1., -1.);
+                let angle = (from * flip).angle_to(to * flip);
+                if angle.is_finite() {
+                    let edit = self.edit_selection();
+                    edit.rotation = Quat::from_rotation_z(angle) * edit.rotation;
+                }
+            }
+            GizmoHandle::Scale => {
+                let ratio = to.length() / from.length();
+                if ratio.is_finite() && ratio > 0. {
+                    self.edit_selection().scale *= ratio;
+                }
+            }
+        }
+    }
+
+    fn draw_selection(&mut self, ui: &mut egui::Ui, selection: &SelectionInfo) {
+        ui.label(format!("{} selected", selection.count));
+        ui.horizontal(|ui| {
+            ui.label("move");
+            let translation = Vec3::new(drag_handle(ui, "x", 0.02), drag_handle(ui, "y", 0.02), drag_handle(ui, "z", 0.02));
+            if translation != Vec3::ZERO {
+                self.edit_selection().translation += translation;
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("rotate");
+            let angles = Vec3::new(drag_handle(ui, "x", 1.0f32.to_radians()), drag_handle(ui, "y", 1.0f32.to_radians()), drag_handle(ui, "z", 1.0f32.to_radians()));
+            if angles != Vec3::ZERO {
+                let edit = self.edit_selection();
+                edit.rotation = Quat::from_rotation_z(angles.z) * Quat::from_rotation_y(angles.y) * Quat::from_rotation_x(angles.x) * edit.rotation;
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("scale");
+            let scale = drag_handle(ui, "xyz", 0.01);
+            if scale != 0. {
+                self.edit_selection().scale *= scale.exp();
+            }
+        });
+        let (mut color, mut metallic, mut roughness) = (selection.color, selection.metallic, selection.roughness);
+        let mut changed = egui::widgets::color_picker::color_edit_button_rgb(ui, &mut color).changed();
+        changed |= ui.add(egui::Slider::new(&mut metallic, 0.0..=1.0).text("metallic")).changed();
+        changed |= ui.add(egui::Slider::new(&mut roughness, 0.0..=1.0).text("roughness")).changed();
+        if changed {
+            self.edit_selection().material = Some((color, metallic, roughness));
+        }
+        ui.horizontal(|ui| {
+            let (mut emissive, mut intensity) = (selection.emissive, selection.emissive_intensity);
+            let mut changed = egui::widgets::color_picker::color_edit_button_rgb(ui, &mut emissive).changed();
+            changed |= ui.add(egui::Slider::new(&mut intensity, 0.0..=10.0).text("emissive")).changed();
+            if changed {
+                self.edit_selection().emissive = Some((emissive, intensity));
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Deselect").clicked() {
+                self.deselect_pressed = true;
+            }
+            if ui.button("Delete").clicked() {
+                self.delete_selection_pressed = true;
+            }
+        });
+    }
+
+    fn draw_brush(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("brush")
+        .selected_text(format!("{:?}", self.brush_kind))
+        .show_ui(ui, |ui| {
+                    for kind in BrushKind::ALL {
+                        ui.selectable_value(&mut self.brush_kind, kind, format!("{:?}", kind));
+                    }
+                });
+        match self.brush_kind {
+            BrushKind::Circle => {},
+            BrushKind::Box | BrushKind::Capsule => {
+                ui.add(egui::Slider::new(&mut self.brush_aspect, 0.05..=4.0).text("brush aspect"));
+            },
+            BrushKind::NGon => {
+                ui.add(egui::Slider::new(&mut self.brush_sides, 3..=12).text("brush sides"));
+            },
+            BrushKind::Polygon => {
+                let mut remove = None;
+                for (i, point) in self.polygon_points.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut point[0]).speed(0.01).range(-1.0..=1.0));
+                        ui.add(egui::DragValue::new(&mut point[1]).speed(0.01).range(-1.0..=1.0));
+                        if ui.small_button("-").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = remove {
+                    if self.polygon_points.len() > 3 {
+                        self.polygon_points.remove(i);
+                    }
+                }
+                if self.polygon_points.len() < MAX_POLYGON_POINTS && ui.small_button("+").clicked() {
+                    let last = self.polygon_points[self.polygon_points.len() - 1];
+                    self.polygon_points.push([0.5 * (last[0] + self.polygon_points[0][0]), 0.5 * (last[1] + self.polygon_points[0][1])]);
+                }
+            },
+            BrushKind::Annulus => {
+                ui.add(egui::Slider::new(&mut self.brush_thickness, 0.05..=1.0).text("brush thickness"));
+            },
+        }
+        if self.brush_kind != BrushKind::Circle && self.brush_kind != BrushKind::Annulus {
+            ui.add(egui::Slider::new(&mut self.brush_rotation, -180.0..=180.0).text("brush rotation"));
+        }
+        ui.add(egui::Slider::new(&mut self.brush_smoothness, 0.0..=1.0).text("brush smoothness"));
+        if self.tool == Tool::Height {
+            ui.add(egui::Slider::new(&mut self.brush_height, 0.01..=0.5).text("brush height"));
+        }
+    }
+
+    fn draw_materials(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("brush material")
+        .selected_text(self.palette[self.brush_material].name.as_str())
+        .show_ui(ui, |ui| {
+                    for (i, material) in self.palette.iter().enumerate() {
+                        ui.selectable_value(&mut self.brush_material, i, material.name.as_str());
+                    }
+                });
+        egui::CollapsingHeader::new("materials").show(ui, |ui| {
+            let mut changed = false;
+            for (i, material) in self.palette.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(format!("{}", i));
+                    changed |= ui.text_edit_singleline(&mut material.name).changed();
+                });
+                ui.horizontal(|ui| {
+                    changed |= egui::widgets::color_picker::color_edit_button_rgb(ui, &mut material.albedo).changed();
+                    changed |= ui.add(egui::Slider::new(&mut material.metallic, 0.0..=1.0).text("metallic")).changed();
+                    changed |= ui.add(egui::Slider::new(&mut material.roughness, 0.0..=1.0).text("roughness")).changed();
+                });
+                ui.horizontal(|ui| {
+                    let mut strength = material.emissive.iter().fold(0.0f32, |a, b| a.max(*b));
+                    let mut color = if strength > 0. { material.emissive.map(|c| c / strength) } else { [1., 1., 1.] };
+                    let color_changed = egui::widgets::color_picker::color_edit_button_rgb(ui, &mut color).changed();
+                    let strength_changed = ui.add(egui::Slider::new(&mut strength, 0.0..=10.0).text("emissive")).changed();
+                    if color_changed || strength_changed {
+                        material.emissive = color.map(|c| c * strength);
+                        changed = true;
+                    }
+                });
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source(("bevel profile", i))
+                    .selected_text(material.bevel_profile.name())
+                    .show_ui(ui, |ui| {
+                                for profile in BevelProfile::ALL {
+                                    changed |= ui.selectable_value(&mut material.bevel_profile, profile, profile.name()).changed();
+                                }
+                            });
+                    changed |= ui.add(egui::Slider::new(&mut material.bevel_width, 0.0..=64.0).text("bevel")).changed();
+                });
+            }
+            ui.horizontal(|ui| {
+                if self.palette.len() < MAX_MATERIALS && ui.small_button("+").clicked() {
+                    let mut material = self.palette[self.palette.len() - 1].clone();
+                    material.name = format!("material {}", self.palette.len());
+                    self.palette.push(material);
+                    changed = true;
+                }
+                if self.palette.len() > 1 && ui.small_button("-").clicked() {
+                    self.palette.pop();
+                    self.brush_material = self.brush_material.min(self.palette.len() - 1);
+                    changed = true;
+                }
+            });
+            self.palette_changed |= changed;
+        });
+    }
+
+    fn draw_import(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.image_path);
+            if ui.button("Import").clicked() {
+                self.import_pressed = true;
+            }
+        });
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("mask")
+            .selected_text(format!("{:?}", self.import_options.channel))
+            .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut self.import_options.channel, MaskChannel::Luminance, format!("{:?}", MaskChannel::Luminance));
+                        ui.selectable_value(&mut self.import_options.channel, MaskChannel::Alpha, format!("{:?}", MaskChannel::Alpha));
+                    });
+            ui.checkbox(&mut self.import_options.resize, "resize");
+        });
+        ui.add(egui::Slider::new(&mut self.import_options.inside, 0.0..=1.0).text("mask inside"));
+        ui.add(egui::Slider::new(&mut self.import_options.outside, 0.0..=1.0).text("mask outside"));
+    }
+
+    fn draw_generate(&mut self, ui: &mut egui::Ui) {
+        let palette = &self.palette;
+        let mut remove = None;
+        for (i, layer) in self.generate_layers.iter_mut().enumerate() {
+            ui.push_id(i, |ui| {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_id_source("source")
+                    .selected_text(layer.source.name())
+                    .show_ui(ui, |ui| {
+                                for source in Source::defaults() {
+                                    if ui.selectable_label(layer.source.name() == source.name(), source.name()).clicked() && layer.source.name() != source.name() {
+                                        layer.source = source;
+                                    }
+                                }
+                            });
+                    egui::ComboBox::from_id_source("op")
+                    .selected_text(format!("{:?}", layer.op))
+                    .show_ui(ui, |ui| {
+                                for op in [CombineOp::Union, CombineOp::Subtract, CombineOp::Intersect] {
+                                    ui.selectable_value(&mut layer.op, op, format!("{:?}", op));
+                                }
+                            });
+                    if ui.small_button("-").clicked() {
+                        remove = Some(i);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("seed");
+                    ui.add(egui::DragValue::new(&mut layer.seed));
+                    let mut material = (layer.material as usize).min(palette.len() - 1);
+                    egui::ComboBox::from_id_source("material")
+                    .selected_text(palette[material].name.as_str())
+                    .show_ui(ui, |ui| {
+                                for (i, m) in palette.iter().enumerate() {
+                                    ui.selectable_value(&mut material, i, m.name.as_str());
+                                }
+                            });
+                    layer.material = material as u32;
+                });
+                match &mut layer.source {
+                    Source::Caves { scale, octaves, threshold } => {
+                        ui.add(egui::Slider::new(scale, 1.0..=128.0).text("scale"));
+                        ui.add(egui::Slider::new(octaves, 1..=8).text("octaves"));
+                        ui.add(egui::Slider::new(threshold, -1.0..=1.0).text("threshold"));
+                    },
+                    Source::Caverns { cell_size, wall } => {
+                        ui.add(egui::Slider::new(cell_size, 4.0..=128.0).text("cell size"));
+                        ui.add(egui::Slider::new(wall, 0.0..=16.0).text("wall"));
+                    },
+                    Source::CellularCaves { cell_size, fill, iterations } => {
+                        ui.add(egui::Slider::new(cell_size, 0.25..=16.0).text("cell size"));
+                        ui.add(egui::Slider::new(fill, 0.0..=1.0).text("fill"));
+                        ui.add(egui::Slider::new(iterations, 0..=generate::MAX_ITERATIONS).text("iterations"));
+                    },
+                    Source::Islands { spacing, density, min_radius, max_radius, roughness } => {
+                        ui.add(egui::Slider::new(spacing, 4.0..=128.0).text("spacing"));
+                        ui.add(egui::Slider::new(density, 0.0..=1.0).text("density"));
+                        ui.add(egui::Slider::new(min_radius, 0.5..=64.0).text("min radius"));
+                        ui.add(egui::Slider::new(max_radius, 0.5..=64.0).text("max radius"));
+                        ui.add(egui::Slider::new(roughness, 0.0..=1.0).text("roughness"));
+                    },
+                }
+                ui.separator();
+            });
+        }
+        if let Some(i) = remove {
+            self.generate_layers.remove(i);
+        }
+        ui.horizontal(|ui| {
+            if ui.small_button("+").clicked() {
+                self.generate_layers.push(Layer::new(Source::defaults()[0]));
+            }
+            ui.checkbox(&mut self.keep_terrain, "keep terrain");
+            if ui.button("Generate").clicked() {
+                self.generate_pressed = true;
+            }
+        });
+    }
+
+    pub fn brush(&self) -> Brush {
+        let radius = 0.25 * self.cursor_size;
+        let shape = match self.brush_kind {
+            BrushKind::Circle => BrushShape::Circle { radius },
+            BrushKind::Box => BrushShape::Box { half_size: Vec2::new(radius, radius * self.brush_aspect) },
+            BrushKind::Capsule => BrushShape::Capsule { half_length: radius, radius: radius * self.brush_aspect },
+            BrushKind::NGon => BrushShape::NGon { sides: self.brush_sides, radius },
+            BrushKind::Polygon => BrushShape::Polygon {
+                points: self.polygon_points.iter().map(|p| radius * Vec2::from(*p)).collect(),
+            },
+            BrushKind::Annulus => BrushShape::Annulus { radius, thickness: radius * self.brush_thickness },
+        };
+        Brush {
+            shape,
+            rotation: self.brush_rotation.to_radians(),
+            smoothness: radius * self.brush_smoothness,
+            material: self.brush_material as u32,
+            height: self.brush_height,
+        }
+    }
+
+    pub fn history_config(&self) -> HistoryConfig {
+        self.history_config
+    }
+
+    pub fn palette(&self) -> &[Material] {
+        &self.palette
+    }
+
+    pub fn redistance_interval(&self) -> usize {
+        self.redistance_interval
+    }
+
+    // Resident window texels along each axis and world size in world units
+    pub fn terrain_config(&self) -> (u32, f32, Topology) {
+        (self.sdf_size, self.world_size, self.topology)
+    }
+
+    pub fn import_options(&self) -> ImportOptions {
+        self.import_options
+    }
+
+    pub fn generate_layers(&self) -> &[Layer] {
+        &self.generate_layers
+    }
+
+    pub fn keep_terrain(&self) -> bool {
+        self.keep_terrain
     }
 
     pub fn light_color(&self) -> [f32; 3] {
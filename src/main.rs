@@ -1,4 +1,5 @@
 mod gui;
+mod prefab;
 pub mod sdf;
 mod renderer;
 mod egui_renderer;
@@ -9,14 +10,80 @@ use egui_wgpu::ScreenDescriptor;
 use wgpu::{Device, Queue, TextureFormat, TextureView};
 use std::{sync::Arc, time::Instant};
 use winit::{
-    event::*, event_loop::{ControlFlow, EventLoop}, keyboard::{KeyCode, PhysicalKey}, window::{Window, WindowBuilder}
+    event::*, event_loop::{ControlFlow, EventLoop}, keyboard::{KeyCode, ModifiersState, PhysicalKey}, window::{Window, WindowBuilder}
 };
 use renderer::light::LightData;
+use bvh::aabb::Bounded;
+use renderer::scene::{Scene, ShapeHandle};
 use renderer::shape::ShapeData;
+use prefab::{PrefabFile, PrefabInstance};
+use sdf::tiles::{TileConfig, Topology};
 
 const WINDOW_SIZE: winit::dpi::LogicalSize<u32> = winit::dpi::LogicalSize::new(1280, 720);
+// Defaults, all can be changed with --world-size, --sdf-size and --topology or at runtime from
+// the GUI
 const WORLD_SIZE: Vec2 = Vec2::new(256.0, 256.0);
 const SDF_SIZE: UVec2 = UVec2::new(1024, 1024);
+// Resident part of the terrain around the camera, streamed in tiles from the whole world
+const SDF_WINDOW_SIZE: Vec2 = Vec2::new(256.0, 256.0);
+const SDF_RESIDENT_TILES: UVec2 = UVec2::new(8, 8);
+// Spawned with E, can be changed with --prefab
+const PREFAB_PATH: &str = "assets/prefabs/creature.ron";
+
+// Whole world made of resident windows, at least one
+fn tile_config(world_size: Vec2, topology: Topology) -> TileConfig {
+    let windows = (world_size / SDF_WINDOW_SIZE).round().max(Vec2::ONE).as_uvec2();
+    TileConfig {
+        resident: SDF_RESIDENT_TILES,
+        world: windows * SDF_RESIDENT_TILES,
+        topology,
+    }
+}
+
+struct Options {
+    world_size: Vec2,
+    sdf_size: UVec2,
+    topology: Topology,
+    prefab: std::path::PathBuf,
+}
+
+impl Options {
+    fn from_args() -> Self {
+        match Self::parse(std::env::args().skip(1)) {
+            Ok(options) => options,
+            Err(e) => {
+                eprintln!("{}", e);
+                eprintln!("Usage: [--world-size <units>] [--sdf-size <texels>] [--topology <{}>] [--prefab <path>]",
+                    Topology::ALL.map(|t| t.name()).join("|"));
+                std::process::exit(2);
+            }
+        }
+    }
+
+    // Flags and their values, anything unknown or malformed is an error
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self {
+            world_size: WORLD_SIZE,
+            sdf_size: SDF_SIZE,
+            topology: Topology::default(),
+            prefab: PREFAB_PATH.into(),
+        };
+        while let Some(arg) = args.next() {
+            if !["--world-size", "--sdf-size", "--topology", "--prefab"].contains(&arg.as_str()) {
+                return Err(format!("Unknown argument {}", arg));
+            }
+            let value = args.next().ok_or_else(|| format!("Missing value for {}", arg))?;
+            let size = || value.parse::<u32>().ok().filter(|v| *v > 0).ok_or_else(|| format!("Invalid value {} for {}", value, arg));
+            match arg.as_str() {
+                "--world-size" => options.world_size = Vec2::splat(size()? as f32),
+                "--sdf-size" => options.sdf_size = UVec2::splat(size()?).max(SDF_RESIDENT_TILES) / SDF_RESIDENT_TILES * SDF_RESIDENT_TILES,
+                "--topology" => options.topology = Topology::from_name(&value).ok_or_else(|| format!("Invalid value {} for {}", value, arg))?,
+                _ => options.prefab = value.into(),
+            }
+        }
+        Ok(options)
+    }
+}
 
 struct State {
     size: winit::dpi::PhysicalSize<u32>,
@@ -27,10 +94,23 @@ struct State {
     renderer: renderer::Renderer,
     egui_renderer: EguiRenderer,
     lights: Vec<LightData>,
-    shapes: Vec<ShapeData>,
+    shapes: Scene,
+    // Follows the mouse while editing the terrain, copied into the scene by O
+    cursor_shape: Option<ShapeHandle>,
+    // What the cursor shape was last set to, the scene only hears about it when that changes
+    cursor_data: ShapeData,
+    // Picked with the select tool, shift adds to or toggles them
+    selection: Vec<ShapeHandle>,
+    hovered: Option<ShapeHandle>,
+    // Where the left mouse button went down with the select tool, a click or a box on release
+    select_from: Option<Vec2>,
+    prefabs: Vec<PrefabFile>,
+    instances: Vec<PrefabInstance>,
     mouse_pos: Vec2,
+    stroke_pos: Option<Vec2>,
     add_pressed: bool,
     subtract_pressed: bool,
+    select_pressed: bool,
     up_pressed: bool,
     left_pressed: bool,
     right_pressed: bool,
@@ -40,30 +120,44 @@ struct State {
     add_light_pressed: bool,
     add_shape_pressed: bool,
     add_entity_pressed: bool,
+    remove_shape_pressed: bool,
+    undo_pressed: bool,
+    redo_pressed: bool,
+    modifiers: ModifiersState,
 }
 
 impl State {
-    fn new(window: &Window, device: &Device, queue: &Queue, surface_format: TextureFormat) -> Self {
+    fn new(window: &Window, device: &Device, queue: &Queue, surface_format: TextureFormat, options: &Options) -> Self {
         let size = window.inner_size();
         let scale_factor = window.scale_factor();
-        let sdf = sdf::SDF::new(SDF_SIZE, WORLD_SIZE, &device, &queue);
+        let sdf = sdf::SDF::new(options.sdf_size, SDF_WINDOW_SIZE, tile_config(options.world_size, options.topology), device, queue);
 
         let mut lights = Vec::new();
-        lights.push(LightData::new([1., 1., 1.], [0., 0.], 10., 10. / 40. * 0.5 * WORLD_SIZE.x));
-        let mut shapes = Vec::new();
-        shapes.push(ShapeData::new());
+        lights.push(LightData::new([1., 1., 1.], [0., 0.], 10., 10. / 40. * 0.5 * SDF_WINDOW_SIZE.x));
+        let mut shapes = Scene::new();
+        let cursor_shape = Some(shapes.insert(ShapeData::new()));
 
         let mut gui = gui::GUI::new(&window);
+        gui.update_terrain_config(sdf.size().x, sdf.world_size().x, sdf.topology());
         gui.update_lights(lights.len());
         gui.update_shapes(shapes.len());
 
+        let mut prefabs = Vec::new();
+        match PrefabFile::load(&options.prefab) {
+            Ok(prefab) => prefabs.push(prefab),
+            Err(e) => gui.update_file_status(format!("Failed to load prefab {}: {}", options.prefab.display(), e)),
+        }
+
         let renderer_scale = gui.renderer_scale;
         let render_resolution = UVec2::new(
             ((size.width as f32 * renderer_scale).ceil() as u32).clamp(16, size.width),
             ((size.height as f32 * renderer_scale).ceil() as u32).clamp(16, size.height),
         );
         let output_resolution = UVec2::new(size.width, size.height);
-        let renderer = renderer::Renderer::new(render_resolution, output_resolution, WORLD_SIZE, device, queue, &sdf, &surface_format);
+        let world_size = sdf.world_size();
+        let mut renderer = renderer::Renderer::new(render_resolution, output_resolution, world_size, device, queue, &sdf, &surface_format);
+        // Larger worlds start with the view a default world gets, they are streamed around it
+        renderer.view_size *= (WORLD_SIZE.y / world_size.y).min(1.);
 
         let egui_renderer = EguiRenderer::new(&device, surface_format, None, 1, &window);
 
@@ -79,9 +173,18 @@ impl State {
             egui_renderer,
             lights,
             shapes,
+            cursor_shape,
+            cursor_data: ShapeData::new(),
+            selection: Vec::new(),
+            hovered: None,
+            select_from: None,
+            prefabs,
+            instances: Vec::new(),
             mouse_pos: Vec2::ZERO,
+            stroke_pos: None,
             add_pressed: false,
             subtract_pressed: false,
+            select_pressed: false,
             up_pressed: false,
             left_pressed: false,
             right_pressed: false,
@@ -91,6 +194,10 @@ impl State {
             add_light_pressed: false,
             add_shape_pressed: false,
             add_entity_pressed: false,
+            remove_shape_pressed: false,
+            undo_pressed: false,
+            redo_pressed: false,
+            modifiers: ModifiersState::empty(),
         }
     }
 
@@ -114,6 +221,16 @@ impl State {
         if gui_captured {
             self.add_pressed = false;
             self.subtract_pressed = false;
+            self.select_pressed = false;
+            // Keys typed into the GUI don't reach the view, neither do their releases
+            self.up_pressed = false;
+            self.left_pressed = false;
+            self.right_pressed = false;
+            self.down_pressed = false;
+            self.zoom_in_pressed = false;
+            self.zoom_out_pressed = false;
+            // Nor does Delete, the selection stays when a text field deletes text
+            self.remove_shape_pressed = false;
         }
         match event {
             WindowEvent::CursorMoved { position, .. } => {
@@ -128,18 +245,32 @@ impl State {
             }
             WindowEvent::MouseInput { state, button, ..} => if !gui_captured {
                 let pressed = *state == ElementState::Pressed;
-                match *button {
-                    MouseButton::Left => self.add_pressed = pressed,
-                    MouseButton::Right => self.subtract_pressed = pressed,
+                match (*button, self.gui.tool) {
+                    (MouseButton::Left, gui::Tool::Terrain | gui::Tool::Height) => self.add_pressed = pressed,
+                    (MouseButton::Right, gui::Tool::Terrain | gui::Tool::Height) => self.subtract_pressed = pressed,
+                    (MouseButton::Left, gui::Tool::Select) => self.select_pressed = pressed,
                     _ => (),
                 }
                 true
             } else {
                 false
             }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+                true
+            }
+            WindowEvent::KeyboardInput { .. } if gui_captured => false,
             WindowEvent::KeyboardInput { event, ..} => {
                 let pressed = event.state == ElementState::Pressed;
                 match event.physical_key {
+                    PhysicalKey::Code(KeyCode::KeyZ) if pressed && self.modifiers.control_key() => {
+                        if self.modifiers.shift_key() {
+                            self.redo_pressed = true;
+                        } else {
+                            self.undo_pressed = true;
+                        }
+                        true
+                    },
                     PhysicalKey::Code(KeyCode::KeyW) => { self.up_pressed = pressed; true},
                     PhysicalKey::Code(KeyCode::KeyA) => { self.left_pressed = pressed; true },
                     PhysicalKey::Code(KeyCode::KeyD) => { self.right_pressed = pressed; true },
@@ -149,6 +280,7 @@ impl State {
                     PhysicalKey::Code(KeyCode::KeyL) => { self.add_light_pressed = pressed; true },
                     PhysicalKey::Code(KeyCode::KeyO) => { self.add_shape_pressed = pressed; true },
                     PhysicalKey::Code(KeyCode::KeyE) => { self.add_entity_pressed = pressed; true },
+                    PhysicalKey::Code(KeyCode::Delete) => { self.remove_shape_pressed = pressed; true },
                     _ => false,
                 }
             }
@@ -166,8 +298,9 @@ impl State {
         let mut z = 1.0;
         if self.zoom_in_pressed { z *= 0.5f32.powf(frame_time); }
         if self.zoom_out_pressed { z /= 0.5f32.powf(frame_time); }
-        self.renderer.position = wrap(self.renderer.position + d);
-        self.renderer.view_size *= z;
+        self.renderer.position = self.sdf.topology().confine(self.renderer.position + d, self.sdf.world_size());
+        let view_limit = self.sdf.tiles().view_limit();
+        self.renderer.view_size *= z.min((view_limit / self.renderer.view_size).min_element());
 
         if self.add_light_pressed {
             self.add_light_pressed = false;
@@ -177,61 +310,266 @@ impl State {
             }
         }
 
+        // The cursor shape is left out while selecting, so it cannot be picked
+        match (self.gui.tool, self.cursor_shape) {
+            (gui::Tool::Select, Some(handle)) => {
+                self.shapes.remove(handle);
+                self.cursor_shape = None;
+                self.gui.update_shapes(self.shapes.len());
+            }
+            (gui::Tool::Terrain | gui::Tool::Height, None) if self.shapes.len() < renderer::MAX_SHAPES => {
+                self.cursor_shape = Some(self.shapes.insert(self.cursor_data));
+                self.gui.update_shapes(self.shapes.len());
+            }
+            _ => (),
+        }
+
         if self.add_shape_pressed {
             self.add_shape_pressed = false;
-            if self.shapes.len() < renderer::MAX_SHAPES {
-                self.shapes.push(self.shapes[0].clone());
-                self.gui.update_shapes(self.shapes.len());
+            if let Some(&shape) = self.cursor_shape.and_then(|handle| self.shapes.get(handle)) {
+                if self.shapes.len() < renderer::MAX_SHAPES {
+                    self.shapes.insert(shape);
+                    self.gui.update_shapes(self.shapes.len());
+                }
+            }
+        }
+
+        if self.gui.tool == gui::Tool::Select {
+            self.update_selection();
+        } else {
+            self.hovered = None;
+            self.select_from = None;
+        }
+        if let Some(edit) = self.gui.take_selection_edit() {
+            self.edit_selection(edit);
+        }
+        if self.gui.deselect_pressed {
+            self.gui.deselect_pressed = false;
+            self.selection.clear();
+        }
+
+        // Delete only removes the selection, there is none outside the select tool
+        let delete_pressed = std::mem::take(&mut self.remove_shape_pressed) && self.gui.tool == gui::Tool::Select;
+        if delete_pressed || self.gui.delete_selection_pressed {
+            self.gui.delete_selection_pressed = false;
+            for handle in std::mem::take(&mut self.selection) {
+                self.remove_shape(handle);
             }
+            self.gui.update_shapes(self.shapes.len());
         }
 
-        if self.add_entity_pressed {
-            self.add_entity_pressed = false;
-            let count = renderer::MAX_SHAPES / 11;
-            let s = (count as f32 / (WORLD_SIZE.x * WORLD_SIZE.y)).sqrt();
-            let w = (s * WORLD_SIZE.x).ceil();
-            let h = (s * WORLD_SIZE.y).ceil();
+        if self.add_entity_pressed && !self.prefabs.is_empty() {
+            let count = renderer::MAX_SHAPES / self.prefabs[0].prefab.num_parts().max(1);
+            let world_size = self.sdf.world_size();
+            let s = (count as f32 / (world_size.x * world_size.y)).sqrt();
+            let w = (s * world_size.x).ceil();
+            let h = (s * world_size.y).ceil();
             let mut i = 0.;
             let mut j = 0.;
+            let mut dropped = 0;
             while i < w {
                 while j < h {
-                    let x = ((i + 0.5) / w - 0.5) * WORLD_SIZE.x;
-                    let y = ((j + 0.5) / h - 0.5) * WORLD_SIZE.y;
+                    let x = ((i + 0.5) / w - 0.5) * world_size.x;
+                    let y = ((j + 0.5) / h - 0.5) * world_size.y;
                     let position = Vec3::new(x, y, -2.);
-                    self.add_entity(position);
+                    if !self.spawn_prefab(0, position, Quat::IDENTITY) {
+                        dropped += 1;
+                    }
                     j = j + 1.;
                 }
                 j = 0.;
                 i = i + 1.;
             }
-            //self.add_entity(self.mouse_world_pos().to_vec().extend(-2.));
+            if dropped > 0 {
+                self.gui.update_file_status(format!("{} instances did not fit within {} shapes", dropped, renderer::MAX_SHAPES));
+            }
+            //self.spawn_prefab(0, self.mouse_world_pos().extend(-2.), Quat::IDENTITY);
+        }
+        self.add_entity_pressed = false;
+
+        let now = Instant::now();
+        for i in 0..self.prefabs.len() {
+            match self.prefabs[i].reload_if_changed(now) {
+                Ok(true) => {
+                    let dropped = self.respawn_prefab(i);
+                    let path = self.prefabs[i].path().display();
+                    self.gui.update_file_status(match dropped {
+                        0 => format!("Reloaded {}", path),
+                        n => format!("Reloaded {}, {} instances dropped at {} shapes", path, n, renderer::MAX_SHAPES),
+                    });
+                }
+                Ok(false) => (),
+                Err(e) => self.gui.update_file_status(format!("Failed to reload {}: {}", self.prefabs[i].path().display(), e)),
+            }
         }
     }
 
-    fn add_entity(&mut self, position: Vec3) {
-        if self.shapes.len() + 11 <= renderer::MAX_SHAPES {
-            let mut parts: Vec<ShapeData> = vec![ShapeData::new(); 11];
-            parts[0].update_rounded_cone([0., 0., 0.35].into(), 0.35, [0., 0., 0.6].into(), 0.25, [1., 0.5, 0.], 0., 0.8,);
-            parts[1].update_rounded_cone([0., 0., 0.6].into(), 0.15, [0.32, 0., 0.6].into(), 0.075, [0.8, 0.8, 0.8], 0., 0.8,);
-            parts[2].update_sphere([0.36, 0., 0.66].into(), 0.03, [0., 0., 0.], 0., 0.2,);
-            parts[3].update_sphere([0.19, 0.1, 0.7].into(), 0.03, [0., 0., 0.], 0., 0.2,);
-            parts[4].update_sphere([0.19, -0.1, 0.7].into(), 0.03, [0., 0., 0.], 0., 0.2,);
-            parts[5].update_rounded_cone([0., -0.15, 0.4].into(), 0.2, [0., -0.45, 0.45].into(), 0.08, [1., 0.5, 0.], 0., 0.8,);
-            parts[6].update_rounded_cone([0., 0.15, 0.4].into(), 0.2, [0., 0.45, 0.45].into(), 0.08, [1., 0.5, 0.], 0., 0.8,);
-            parts[7].update_sphere([0.07, 0., 0.33].into(), 0.3, [0.8, 0.8, 0.8], 0., 0.2,);
-            parts[8].update_rounded_cone([0., 0., 0.35].into(), 0.2, [-0.3, 0., 0.1].into(), 0.05, [1., 0.5, 0.], 0., 0.8,);
-            parts[9].update_rounded_cone([-0.05, -0.15, 0.05].into(), 0.1, [0.2, -0.2, 0.01].into(), 0.1, [1., 0.5, 0.], 0., 0.8,);
-            parts[10].update_rounded_cone([-0.05, 0.15, 0.05].into(), 0.1, [0.2, 0.2, 0.01].into(), 0.1, [1., 0.5, 0.], 0., 0.8, );
-            for part in parts.iter_mut() {
-                part.translate(position);
+    // Adds the shapes of `prefab` at `position` with `rotation`, unless they do not fit
+    fn spawn_prefab(&mut self, prefab: usize, position: Vec3, rotation: Quat) -> bool {
+        let Some(instance) = PrefabInstance::spawn(prefab, &self.prefabs[prefab].prefab, position, rotation, &mut self.shapes, renderer::MAX_SHAPES) else {
+            return false;
+        };
+        self.instances.push(instance);
+        self.gui.update_shapes(self.shapes.len());
+        true
+    }
+
+    // Rebuilds the instances of `prefab` after it changed, returns how many were dropped
+    fn respawn_prefab(&mut self, prefab: usize) -> usize {
+        let dropped = prefab::respawn(&mut self.instances, prefab, &self.prefabs[prefab].prefab, &mut self.shapes, renderer::MAX_SHAPES);
+        self.gui.update_shapes(self.shapes.len());
+        dropped
+    }
+
+    // Removes a shape, or the whole prefab instance it is part of
+    fn remove_shape(&mut self, handle: ShapeHandle) {
+        match self.instances.iter().position(|instance| instance.shapes.contains(&handle)) {
+            Some(i) => {
+                for handle in self.instances.swap_remove(i).shapes {
+                    self.shapes.remove(handle);
+                }
             }
-            self.shapes.append(&mut parts);
-            self.gui.update_shapes(self.shapes.len());
+            None => {
+                self.shapes.remove(handle);
+            }
+        }
+    }
+
+    // Picks the shape under the mouse on click and the shapes whose centres are in the dragged box
+    // on release
+    fn update_selection(&mut self) {
+        let shapes = &self.shapes;
+        self.selection.retain(|&handle| shapes.get(handle).is_some());
+        let p = self.mouse_world_pos();
+        self.hovered = self.pick_shape(p);
+        let from = match (self.select_pressed, self.select_from) {
+            (true, None) => {
+                self.select_from = Some(p);
+                return;
+            }
+            (false, Some(from)) => from,
+            _ => return,
+        };
+        self.select_from = None;
+        let extend = self.modifiers.shift_key();
+        let world_size = self.sdf.world_size();
+        let d = self.sdf.topology().wrap(p - from, world_size);
+        if (d / self.renderer.view_size).abs().max_element() < 0.005 {
+            match self.hovered {
+                Some(handle) if extend => match self.selection.iter().position(|&h| h == handle) {
+                    Some(i) => { self.selection.remove(i); },
+                    None => self.selection.push(handle),
+                },
+                Some(handle) => self.selection = vec![handle],
+                None if !extend => self.selection.clear(),
+                None => (),
+            }
+            return;
         }
-    } 
+        let min = from.min(from + d);
+        let size = d.abs();
+        if !extend {
+            self.selection.clear();
+        }
+        let topology = self.sdf.topology();
+        let inside: Vec<ShapeHandle> = self.shapes
+            .iter()
+            .filter(|(_, shape)| {
+                let aabb = shape.aabb();
+                let centre = Vec2::new(aabb.center().x, aabb.center().y);
+                let q = topology.wrap(centre - min, world_size);
+                q.cmpge(Vec2::ZERO).all() && q.cmple(size).all()
+            })
+            .map(|(handle, _)| handle)
+            .collect();
+        for handle in inside {
+            if !self.selection.contains(&handle) {
+                self.selection.push(handle);
+            }
+        }
+    }
+
+    // Topmost shape at `p`, cast down the way the geometry pass sees the shapes
+    fn pick_shape(&self, p: Vec2) -> Option<ShapeHandle> {
+        let topology = self.sdf.topology();
+        let world_size = self.sdf.world_size();
+        let wrap = |d: Vec3| topology.wrap(d.xy(), world_size).extend(d.z);
+        self.renderer.cast_ray(&self.shapes, p.extend(2.), -Vec3::Z, 4., wrap).map(|(_, shape)| self.shapes.handle(shape))
+    }
+
+    // Average position of the selected shapes, taken the short way around a wrapping world
+    fn selection_centre(&self) -> Option<Vec3> {
+        let topology = self.sdf.topology();
+        let world_size = self.sdf.world_size();
+        let anchor = self.shapes.get(*self.selection.first()?)?.position();
+        let sum: Vec3 = self.selection.iter()
+            .filter_map(|&handle| self.shapes.get(handle))
+            .map(|shape| {
+                let d = shape.position() - anchor;
+                topology.wrap(d.xy(), world_size).extend(d.z)
+            })
+            .sum();
+        let centre = anchor + sum / self.selection.len() as f32;
+        Some(topology.wrap(centre.xy(), world_size).extend(centre.z))
+    }
+
+    fn edit_selection(&mut self, edit: gui::SelectionEdit) {
+        let Some(centre) = self.selection_centre() else {
+            return;
+        };
+        let topology = self.sdf.topology();
+        let world_size = self.sdf.world_size();
+        let wrap = |p: Vec3| topology.wrap(p.xy(), world_size).extend(p.z);
+        edit_shapes(&mut self.shapes, &mut self.instances, &self.selection, centre, &edit, wrap);
+    }
+
+    // Selection and outlines for the GUI, in view space
+    fn update_gui_selection(&mut self) {
+        let topology = self.sdf.topology();
+        let world_size = self.sdf.world_size();
+        let view_size = self.renderer.view_size;
+        let to_view = |p: Vec2| topology.wrap(p - self.renderer.position, world_size) / view_size;
+        let bounds = |handle: ShapeHandle| {
+            let aabb = self.shapes.get(handle)?.aabb();
+            let min = Vec2::new(aabb.min.x, aabb.min.y);
+            let max = Vec2::new(aabb.max.x, aabb.max.y);
+            let centre = to_view(0.5 * (min + max));
+            let half_size = 0.5 * (max - min) / view_size;
+            Some([centre - half_size, centre + half_size])
+        };
+        let highlights = gui::Highlights {
+            hovered: self.hovered.and_then(bounds),
+            selected: self.selection.iter().filter_map(|&handle| bounds(handle)).collect(),
+            selection_box: self.select_from.map(|from| {
+                let from = to_view(from);
+                [from.min(self.mouse_pos), from.max(self.mouse_pos)]
+            }),
+        };
+        let selection = self.selection_centre().map(|centre| {
+            let shape = self.shapes.get(self.selection[0]).unwrap();
+            let (color, metallic, roughness) = shape.material();
+            let (emissive, emissive_intensity) = shape.emissive();
+            gui::SelectionInfo {
+                count: self.selection.len(),
+                centre: to_view(centre.xy()),
+                view_size,
+                color,
+                metallic,
+                roughness,
+                emissive,
+                emissive_intensity,
+            }
+        });
+        self.gui.update_selection(selection, highlights);
+    }
 
     fn mouse_world_pos(&self) -> Vec2 {
-        wrap(self.mouse_pos.mul_add(self.renderer.view_size, self.renderer.position))
+        self.sdf.topology().wrap(self.mouse_pos.mul_add(self.renderer.view_size, self.renderer.position), self.sdf.world_size())
+    }
+
+    // Status suffix for replacing the whole terrain, which starts its history over
+    fn history_cleared_note(&self) -> &'static str {
+        if self.sdf.history().is_empty() { "" } else { ", undo history cleared" }
     }
 
     fn render(&mut self, view: &TextureView, device: &Device, queue: &Queue, window: &Window) {
@@ -251,45 +589,150 @@ impl State {
         });
 
         let mouse_world_pos = self.mouse_world_pos();
-        let cursor_size = self.gui.cursor_size;
+        let brush = self.gui.brush();
         self.lights[0].update(
             self.gui.light_color(), 
             mouse_world_pos.into(),
             self.gui.light_radius,
-            (self.gui.light_range * 0.5 * WORLD_SIZE.x.min(WORLD_SIZE.y)).max(self.gui.light_radius),
+            (self.gui.light_range * 0.5 * SDF_WINDOW_SIZE.x.min(SDF_WINDOW_SIZE.y)).max(self.gui.light_radius),
         );
-        self.shapes[0].update_sphere(
-            mouse_world_pos.extend(-2. + self.gui.shape_radius),
+        let mut cursor_data = ShapeData::new();
+        cursor_data.update_kind(
+            self.gui.shape_kind,
+            mouse_world_pos.extend(-2.),
             self.gui.shape_radius,
             self.gui.shape_color,
             self.gui.shape_metallic,
             self.gui.shape_roughness,
         );
+        let (rotation, scale) = self.gui.shape_transform();
+        cursor_data.set_rotation(rotation);
+        cursor_data.set_scale(scale);
+        cursor_data.set_emissive(self.gui.shape_emissive, self.gui.shape_emissive_intensity);
+        if cursor_data != self.cursor_data {
+            self.cursor_data = cursor_data;
+            if let Some(cursor_shape) = self.cursor_shape.and_then(|handle| self.shapes.get_mut(handle)) {
+                *cursor_shape = cursor_data;
+            }
+        }
 
+        if self.gui.terrain_config_pressed {
+            self.gui.terrain_config_pressed = false;
+            let (sdf_size, world_size, topology) = self.gui.terrain_config();
+            let history = self.history_cleared_note();
+            let status = match self.sdf.reconfigure(UVec2::splat(sdf_size), tile_config(Vec2::splat(world_size), topology), device, queue) {
+                Ok(()) => {
+                    self.renderer.set_world(self.sdf.world_size(), topology);
+                    self.renderer.position = topology.confine(self.renderer.position, self.sdf.world_size());
+                    format!("Terrain is {}x{} texels per window, {} world is {}x{}{}", sdf_size, sdf_size, topology.name(), self.sdf.world_size().x, self.sdf.world_size().y, history)
+                }
+                Err(e) => format!("Failed to reconfigure terrain: {}", e),
+            };
+            self.gui.update_terrain_config(self.sdf.size().x, self.sdf.world_size().x, self.sdf.topology());
+            self.gui.update_file_status(status);
+        }
+        self.sdf.stream(self.renderer.position, device, queue);
+        self.gui.update_tiles(self.sdf.tiles().num_stored());
+
+        if self.gui.save_pressed {
+            self.gui.save_pressed = false;
+            let path = std::path::PathBuf::from(&self.gui.terrain_path);
+            let status = match self.sdf.save(&path, device, queue) {
+                Ok(()) => format!("Saved {}", path.display()),
+                Err(e) => format!("Failed to save {}: {}", path.display(), e),
+            };
+            self.gui.update_file_status(status);
+        }
+        if self.gui.load_pressed {
+            self.gui.load_pressed = false;
+            let path = std::path::PathBuf::from(&self.gui.terrain_path);
+            let history = self.history_cleared_note();
+            let status = match self.sdf.load(&path, device, queue) {
+                Ok(()) => format!("Loaded {}{}", path.display(), history),
+                Err(e) => format!("Failed to load {}: {}", path.display(), e),
+            };
+            self.gui.update_file_status(status);
+        }
+        if self.gui.import_pressed {
+            self.gui.import_pressed = false;
+            let path = std::path::PathBuf::from(&self.gui.image_path);
+            let status = match image::open(&path) {
+                Ok(img) => {
+                    self.sdf.import_image(&img, &self.gui.import_options(), device, queue);
+                    format!("Imported {}", path.display())
+                }
+                Err(e) => format!("Failed to import {}: {}", path.display(), e),
+            };
+            self.gui.update_file_status(status);
+        }
+        if self.gui.generate_pressed {
+            self.gui.generate_pressed = false;
+            self.sdf.generate(self.gui.generate_layers(), self.gui.keep_terrain(), device, queue);
+        }
+        if self.undo_pressed || self.gui.undo_pressed {
+            self.undo_pressed = false;
+            self.gui.undo_pressed = false;
+            self.sdf.undo(device, queue);
+        }
+        if self.redo_pressed || self.gui.redo_pressed {
+            self.redo_pressed = false;
+            self.gui.redo_pressed = false;
+            self.sdf.redo(device, queue);
+        }
+        if self.gui.palette_changed {
+            self.gui.palette_changed = false;
+            self.sdf.set_palette(queue, self.gui.palette());
+        }
+        if self.gui.redistance_pressed {
+            self.gui.redistance_pressed = false;
+            self.sdf.redistance();
+        }
+        if self.gui.redistance_interval() != self.sdf.redistance_interval() {
+            self.sdf.set_redistance_interval(self.gui.redistance_interval());
+        }
+        let history_config = self.gui.history_config();
+        if history_config != self.sdf.history().config() {
+            self.sdf.set_history_config(history_config);
+        }
+
+        let stroke_from = self.stroke_pos.unwrap_or(mouse_world_pos);
+        if (self.add_pressed || self.subtract_pressed) && !self.sdf.in_stroke() {
+            self.sdf.begin_stroke();
+        }
+        // The height tool raises with the left button and lowers with the right one
+        let height_tool = self.gui.tool == gui::Tool::Height;
         if self.add_pressed {
-            self.sdf.add(
-                mouse_world_pos, 
-                cursor_size, 
-                queue,
-                &mut encoder,
-            );
+            if height_tool {
+                self.sdf.raise(stroke_from, mouse_world_pos, &brush);
+            } else {
+                self.sdf.add(stroke_from, mouse_world_pos, &brush);
+            }
         }
         if self.subtract_pressed {
-            self.sdf.subtract(
-                mouse_world_pos, 
-                cursor_size, 
-                queue,
-                &mut encoder,
-            )
+            if height_tool {
+                self.sdf.lower(stroke_from, mouse_world_pos, &brush);
+            } else {
+                self.sdf.subtract(stroke_from, mouse_world_pos, &brush);
+            }
+        }
+        self.stroke_pos = if self.add_pressed || self.subtract_pressed { Some(mouse_world_pos) } else { None };
+        if self.stroke_pos.is_none() && self.sdf.in_stroke() {
+            self.sdf.end_stroke();
         }
+        self.sdf.apply_edits(device, queue, &mut encoder);
+        self.gui.update_history(self.sdf.history());
+        let query = self.sdf.query();
+        let to_mouse = self.sdf.topology().wrap(mouse_world_pos - self.renderer.position, self.sdf.world_size());
+        self.gui.update_probe(query.distance(mouse_world_pos), query.raycast(self.renderer.position, to_mouse, to_mouse.length()));
 
         self.renderer.update_uniforms(
             mouse_world_pos,
-            cursor_size,
+            2. * brush.bounding_radius(),
             self.gui.exposure,
         );
         self.renderer.update_lights(queue, &self.lights);
-        self.renderer.update_shapes(queue, &mut self.shapes);
+        self.renderer.bvh_builder = self.gui.bvh_builder;
+        self.renderer.update_shapes(queue, &mut encoder, &mut self.shapes);
         self.renderer.update_upsampler(device, queue, &self.gui.upsampler);
         self.renderer.render(device, queue, &mut encoder, &self.sdf, &self.shapes, &view);
         
@@ -298,20 +741,47 @@ impl State {
             pixels_per_point: self.scale_factor as f32,
         };
 
+        self.update_gui_selection();
         let egui_renderer = &mut self.egui_renderer;
         let gui = &mut self.gui;
         egui_renderer.draw(device, queue, &mut encoder, window, view, screen_descriptor, |ctx| gui.draw(ctx));
 
         queue.submit(std::iter::once(encoder.finish()));
+        self.sdf.sync_mirror(device);
     }
 }
 
-fn wrap(p: Vec2) -> Vec2 {
-    let sx = (p.x / WORLD_SIZE.x).abs().ceil() + 0.5;
-    let x = (p.x + sx * WORLD_SIZE.x) % WORLD_SIZE.x - 0.5 * WORLD_SIZE.x;
-    let sy = (p.y / WORLD_SIZE.y).abs().ceil() + 0.5;
-    let y = (p.y + sy * WORLD_SIZE.y) % WORLD_SIZE.y - 0.5 * WORLD_SIZE.y;
-    Vec2::new(x, y)
+// Applies `edit` to the `selection` around `centre`, `wrap` takes offsets and positions around the
+// world. Instances whose shapes all moved rigidly move along, any other edited instance is detached
+// so a prefab reload leaves the edit alone.
+fn edit_shapes(shapes: &mut Scene, instances: &mut Vec<PrefabInstance>, selection: &[ShapeHandle], centre: Vec3, edit: &gui::SelectionEdit, wrap: impl Fn(Vec3) -> Vec3) {
+    for &handle in selection {
+        let shape = shapes.get_mut(handle).unwrap();
+        let offset = wrap(shape.position() - centre);
+        // Around the origin first, so the rotation and scale pivot on the centre
+        shape.transform(offset - shape.position(), Quat::IDENTITY, Vec3::ONE);
+        shape.transform(centre + edit.translation, edit.rotation, Vec3::splat(edit.scale));
+        let position = shape.position();
+        shape.transform(wrap(position) - position, Quat::IDENTITY, Vec3::ONE);
+        if let Some((color, metallic, roughness)) = edit.material {
+            shape.set_material(color, metallic, roughness);
+        }
+        if let Some((emissive, intensity)) = edit.emissive {
+            shape.set_emissive(emissive, intensity);
+        }
+    }
+    let rigid = edit.scale == 1. && edit.material.is_none() && edit.emissive.is_none();
+    instances.retain_mut(|instance| {
+        let selected = instance.shapes.iter().filter(|handle| selection.contains(handle)).count();
+        if selected == 0 {
+            true
+        } else if rigid && selected == instance.shapes.len() {
+            instance.follow(centre, edit.translation, edit.rotation, &wrap);
+            true
+        } else {
+            false
+        }
+    });
 }
 
 fn main() {
@@ -320,6 +790,7 @@ fn main() {
 
 async fn run() {
     env_logger::init();
+    let options = Options::from_args();
     let event_loop = EventLoop::new().expect("New event loop");
     let window = WindowBuilder::new()
     .with_title("WGPU test")
@@ -355,7 +826,7 @@ async fn run() {
 
     surface.configure(&device, &config);
 
-    let mut state = State::new(&window, &device, &queue, config.format);
+    let mut state = State::new(&window, &device, &queue, config.format, &options);
     let mut last_frame_inst = Instant::now();
     let (mut frame_count, mut accum_time) = (0, 0.0);
 
@@ -435,4 +906,99 @@ async fn run() {
             _ => {}
         }
     });
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn no_arguments_give_the_defaults() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.world_size, WORLD_SIZE);
+        assert_eq!(options.sdf_size, SDF_SIZE);
+        assert_eq!(options.topology, Topology::Torus);
+        assert_eq!(options.prefab, std::path::PathBuf::from(PREFAB_PATH));
+    }
+
+    #[test]
+    fn flags_set_their_options() {
+        let options = parse(&["--world-size", "1024", "--sdf-size", "2000", "--topology", "bounded", "--prefab", "a.ron"]).unwrap();
+        assert_eq!(options.world_size, Vec2::splat(1024.));
+        // Rounded down to a multiple of the resident tiles
+        assert_eq!(options.sdf_size, UVec2::splat(2000 / SDF_RESIDENT_TILES.x * SDF_RESIDENT_TILES.x));
+        assert_eq!(options.topology, Topology::Bounded);
+        assert_eq!(options.prefab, std::path::PathBuf::from("a.ron"));
+    }
+
+    #[test]
+    fn unknown_flags_are_rejected() {
+        assert!(parse(&["--size", "512", "--topology", "bounded"]).is_err());
+        assert!(parse(&["512"]).is_err());
+    }
+
+    #[test]
+    fn malformed_values_are_rejected() {
+        assert!(parse(&["--world-size", "big"]).is_err());
+        assert!(parse(&["--sdf-size", "0"]).is_err());
+        assert!(parse(&["--topology", "sphere"]).is_err());
+        assert!(parse(&["--prefab"]).is_err());
+    }
+
+    const POST: &str = "(parts: [
+        (shape: Cylinder(a: (0.0, 0.0, 0.0), b: (0.0, 0.0, 1.0), radius: 0.1)),
+        (shape: Box(half_size: (0.5, 0.1, 0.1)), position: (1.0, 0.0, 0.5), rotation: (0.0, 0.0, 90.0)),
+    ])";
+
+    fn assert_same_shapes(shapes: &Scene, handles: &[ShapeHandle], expected: &[ShapeData]) {
+        for (handle, expected) in handles.iter().zip(expected) {
+            let shape = shapes.get(*handle).unwrap();
+            assert!(shape.position().abs_diff_eq(expected.position(), 1e-4), "{:?} != {:?}", shape.position(), expected.position());
+            let (a, b) = (Quat::from_array(shape.rotation), Quat::from_array(expected.rotation));
+            assert!(a.dot(b).abs() > 1. - 1e-5, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn edited_instances_survive_a_respawn() {
+        let prefab = prefab::Prefab::parse(POST).unwrap();
+        let mut shapes = Scene::new();
+        let spawn = |shapes: &mut Scene, position| PrefabInstance::spawn(0, &prefab, position, Quat::IDENTITY, shapes, 16).unwrap();
+        let mut instances = vec![spawn(&mut shapes, Vec3::new(2., 3., 0.)), spawn(&mut shapes, Vec3::new(-4., 0., 1.))];
+        let whole = instances[0].shapes.clone();
+        let part = instances[1].shapes[1];
+        // Across the edge of a 16 unit torus, so the positions have to wrap
+        let wrap = |p: Vec3| ((p + 8.).rem_euclid(Vec3::splat(16.)) - 8.).truncate().extend(p.z);
+        let edit = gui::SelectionEdit {
+            translation: Vec3::new(-12., 1., 0.5),
+            rotation: Quat::from_rotation_z(1.) * Quat::from_rotation_x(0.3),
+            ..Default::default()
+        };
+        edit_shapes(&mut shapes, &mut instances, &whole, Vec3::new(2., 3., 0.5), &edit, wrap);
+        let lift = gui::SelectionEdit {
+            translation: Vec3::Z,
+            ..Default::default()
+        };
+        let centre = shapes.get(part).unwrap().position();
+        edit_shapes(&mut shapes, &mut instances, &[part], centre, &lift, wrap);
+        let edited: Vec<_> = whole.iter().map(|handle| *shapes.get(*handle).unwrap()).collect();
+        let part_position = shapes.get(part).unwrap().position();
+        // The moved instance follows its shapes, the partly moved one is no longer an instance
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].shapes, whole);
+        assert_eq!(prefab::respawn(&mut instances, 0, &prefab, &mut shapes, 16), 0);
+        assert_same_shapes(&shapes, &whole, &edited);
+        assert_eq!(shapes.get(part).unwrap().position(), part_position);
+    }
+
+    #[test]
+    fn worlds_are_whole_windows() {
+        assert_eq!(tile_config(SDF_WINDOW_SIZE, Topology::Torus).world, SDF_RESIDENT_TILES);
+        assert_eq!(tile_config(SDF_WINDOW_SIZE * 2.2, Topology::Torus).world, 2 * SDF_RESIDENT_TILES);
+        assert_eq!(tile_config(Vec2::ONE, Topology::Clamped).world, SDF_RESIDENT_TILES);
+    }
+}
@@ -0,0 +1,294 @@
+// Prefabs are shape assemblies authored in RON files, see assets/prefabs/creature.ron. Every
+// part is a primitive in the part's own frame, placed in the prefab with an optional position,
+// rotation (degrees around x, then y, then z) and scale. Instances place a whole prefab in the
+// world with a position and a rotation.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use glam::*;
+use serde::Deserialize;
+
+use crate::renderer::scene::{Scene, ShapeHandle};
+use crate::renderer::shape::ShapeData;
+
+#[derive(Debug, Clone, Deserialize)]
+pub enum Primitive {
+    Sphere { radius: f32 },
+    RoundedCone { a: [f32; 3], radius_a: f32, b: [f32; 3], radius_b: f32 },
+    Box { half_size: [f32; 3] },
+    RoundedBox { half_size: [f32; 3], radius: f32 },
+    Capsule { a: [f32; 3], b: [f32; 3], radius: f32 },
+    Cylinder { a: [f32; 3], b: [f32; 3], radius: f32 },
+    Torus { major_radius: f32, minor_radius: f32 },
+    Ellipsoid { radii: [f32; 3] },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Part {
+    pub shape: Primitive,
+    #[serde(default)]
+    pub position: [f32; 3],
+    #[serde(default)]
+    pub rotation: [f32; 3],
+    #[serde(default = "default_scale")]
+    pub scale: [f32; 3],
+    #[serde(default = "default_color")]
+    pub color: [f32; 3],
+    #[serde(default)]
+    pub metallic: f32,
+    #[serde(default = "default_roughness")]
+    pub roughness: f32,
+    #[serde(default)]
+    pub emissive: [f32; 3],
+    #[serde(default = "default_emissive_intensity")]
+    pub emissive_intensity: f32,
+}
+
+fn default_scale() -> [f32; 3] {
+    [1.; 3]
+}
+
+fn default_color() -> [f32; 3] {
+    [0.8; 3]
+}
+
+fn default_roughness() -> f32 {
+    0.5
+}
+
+fn default_emissive_intensity() -> f32 {
+    1.
+}
+
+impl Part {
+    fn shape(&self) -> ShapeData {
+        let mut shape = ShapeData::new();
+        let (color, metallic, roughness) = (self.color, self.metallic, self.roughness);
+        match self.shape {
+            Primitive::Sphere { radius } => shape.update_sphere(Vec3::ZERO, radius, color, metallic, roughness),
+            Primitive::RoundedCone { a, radius_a, b, radius_b } => shape.update_rounded_cone(a.into(), radius_a, b.into(), radius_b, color, metallic, roughness),
+            Primitive::Box { half_size } => shape.update_box(Vec3::ZERO, half_size.into(), color, metallic, roughness),
+            Primitive::RoundedBox { half_size, radius } => shape.update_rounded_box(Vec3::ZERO, half_size.into(), radius, color, metallic, roughness),
+            Primitive::Capsule { a, b, radius } => shape.update_capsule(a.into(), b.into(), radius, color, metallic, roughness),
+            Primitive::Cylinder { a, b, radius } => shape.update_cylinder(a.into(), b.into(), radius, color, metallic, roughness),
+            Primitive::Torus { major_radius, minor_radius } => shape.update_torus(Vec3::ZERO, major_radius, minor_radius, color, metallic, roughness),
+            Primitive::Ellipsoid { radii } => shape.update_ellipsoid(Vec3::ZERO, radii.into(), color, metallic, roughness),
+        }
+        shape.set_emissive(self.emissive, self.emissive_intensity);
+        let [x, y, z] = self.rotation.map(f32::to_radians);
+        shape.transform(self.position.into(), Quat::from_euler(EulerRot::ZYX, z, y, x), self.scale.into());
+        shape
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Prefab {
+    pub parts: Vec<Part>,
+}
+
+impl Prefab {
+    pub fn parse(text: &str) -> Result<Self> {
+        ron::from_str(text).map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn num_parts(&self) -> usize {
+        self.parts.len()
+    }
+
+    // Shapes of the whole prefab placed at `position` with `rotation`
+    pub fn instantiate(&self, position: Vec3, rotation: Quat) -> Vec<ShapeData> {
+        self.parts
+            .iter()
+            .map(|part| {
+                let mut shape = part.shape();
+                shape.transform(position, rotation, Vec3::ONE);
+                shape
+            })
+            .collect()
+    }
+}
+
+// Time between looks at a prefab file, reading its metadata every frame is wasted work
+const RELOAD_INTERVAL: Duration = Duration::from_millis(500);
+
+// A prefab that follows its file on disk
+pub struct PrefabFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    checked: Instant,
+    pub prefab: Prefab,
+}
+
+impl PrefabFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let modified = fs::metadata(path)?.modified().ok();
+        Ok(Self {
+            path: path.to_path_buf(),
+            modified,
+            checked: Instant::now(),
+            prefab: Prefab::read(path)?,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Reloads the prefab if the file changed since the last look, returns whether it did. The file
+    // is looked at once per RELOAD_INTERVAL at most. A file that fails to parse keeps the
+    // previous prefab until it changes again, a missing one is taken as being in the middle of a
+    // save.
+    pub fn reload_if_changed(&mut self, now: Instant) -> Result<bool> {
+        if now.saturating_duration_since(self.checked) < RELOAD_INTERVAL {
+            return Ok(false);
+        }
+        self.checked = now;
+        let Ok(metadata) = fs::metadata(&self.path) else {
+            return Ok(false);
+        };
+        let modified = metadata.modified().ok();
+        if modified == self.modified {
+            return Ok(false);
+        }
+        self.modified = modified;
+        self.prefab = Prefab::read(&self.path)?;
+        Ok(true)
+    }
+}
+
+// Shapes in the scene that were spawned from `prefab`, one per part
+pub struct PrefabInstance {
+    pub prefab: usize,
+    pub position: Vec3,
+    pub rotation: Quat,
+    pub shapes: Vec<ShapeHandle>,
+}
+
+impl PrefabInstance {
+    // Adds the shapes of `prefab`, number `index`, at `position` with `rotation` unless they do not
+    // fit within `max_shapes`
+    pub fn spawn(index: usize, prefab: &Prefab, position: Vec3, rotation: Quat, shapes: &mut Scene, max_shapes: usize) -> Option<Self> {
+        let parts = prefab.instantiate(position, rotation);
+        if shapes.len() + parts.len() > max_shapes {
+            return None;
+        }
+        Some(Self {
+            prefab: index,
+            position,
+            rotation,
+            shapes: parts.into_iter().map(|part| shapes.insert(part)).collect(),
+        })
+    }
+
+    // Follows its shapes after an edit moved each to `translation + rotation * offset` for its
+    // offset from `centre`. `wrap` takes offsets and positions around the world like the edit did.
+    pub fn follow(&mut self, centre: Vec3, translation: Vec3, rotation: Quat, wrap: impl Fn(Vec3) -> Vec3) {
+        self.position = wrap(centre + translation + rotation * wrap(self.position - centre));
+        self.rotation = (rotation * self.rotation).normalize();
+    }
+}
+
+// Rebuilds the instances of `prefab`, number `index`, after it changed, in place while the number
+// of parts stays. Returns how many instances no longer fit within `max_shapes` and were dropped.
+pub fn respawn(instances: &mut Vec<PrefabInstance>, index: usize, prefab: &Prefab, shapes: &mut Scene, max_shapes: usize) -> usize {
+    let mut dropped = 0;
+    let (respawned, kept): (Vec<_>, Vec<_>) = instances.drain(..).partition(|instance| instance.prefab == index);
+    *instances = kept;
+    for mut instance in respawned {
+        let parts = prefab.instantiate(instance.position, instance.rotation);
+        if parts.len() == instance.shapes.len() {
+            for (handle, part) in instance.shapes.iter().zip(parts) {
+                *shapes.get_mut(*handle).unwrap() = part;
+            }
+            instances.push(instance);
+        } else {
+            for handle in instance.shapes.drain(..) {
+                shapes.remove(handle);
+            }
+            match PrefabInstance::spawn(index, prefab, instance.position, instance.rotation, shapes, max_shapes) {
+                Some(instance) => instances.push(instance),
+                None => dropped += 1,
+            }
+        }
+    }
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFAB: &str = r#"
+        // A post with a ball on top
+        (
+            parts: [
+                (shape: Cylinder(a: (0.0, 0.0, 0.0), b: (0.0, 0.0, 1.0), radius: 0.1), color: (0.2, 0.2, 0.2)),
+                (shape: Sphere(radius: 0.25), position: (0.0, 0.0, 1.0), emissive: (1.0, 0.5, 0.0), emissive_intensity: 4.0),
+                (shape: Box(half_size: (0.5, 0.1, 0.1)), position: (1.0, 0.0, 0.5), rotation: (0.0, 0.0, 90.0), scale: (2.0, 1.0, 1.0)),
+            ],
+        )
+    "#;
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!(a.abs_diff_eq(b, 1e-5), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn parts_are_parsed_with_defaults() {
+        let prefab = Prefab::parse(PREFAB).unwrap();
+        assert_eq!(prefab.num_parts(), 3);
+        let post = &prefab.parts[0];
+        assert_eq!((post.position, post.rotation, post.scale), ([0.; 3], [0.; 3], [1.; 3]));
+        assert_eq!((post.metallic, post.roughness, post.emissive, post.emissive_intensity), (0., 0.5, [0.; 3], 1.));
+        assert!(matches!(prefab.parts[1].shape, Primitive::Sphere { radius } if radius == 0.25));
+        assert_eq!(prefab.parts[1].emissive_intensity, 4.);
+        assert_eq!(prefab.parts[2].rotation, [0., 0., 90.]);
+    }
+
+    #[test]
+    fn broken_prefabs_are_invalid_data() {
+        assert_eq!(Prefab::parse("(parts: [(shape: Pyramid(size: 1.0))])").err().unwrap().kind(), ErrorKind::InvalidData);
+        assert_eq!(Prefab::parse("(parts: [").err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn instances_are_placed_and_turned_as_a_whole() {
+        let prefab = Prefab::parse(PREFAB).unwrap();
+        let position = Vec3::new(10., -5., -2.);
+        let turn = Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+        let shapes = prefab.instantiate(position, turn);
+        assert_eq!(shapes.len(), 3);
+        assert_near(shapes[0].position(), position);
+        assert_near(shapes[1].position(), position + Vec3::Z);
+        // The box sits one unit along x in the prefab, along y once the prefab turns
+        assert_near(shapes[2].position(), position + Vec3::new(0., 1., 0.5));
+        let rotation = Quat::from_array(shapes[2].rotation);
+        assert!(rotation.abs_diff_eq(turn * turn, 1e-5) || rotation.abs_diff_eq(-(turn * turn), 1e-5));
+        assert_eq!(&shapes[2].scale[0..3], &[2., 1., 1.]);
+        assert_eq!(shapes[1].emissive(), ([1., 0.5, 0.], 4.));
+    }
+
+    #[test]
+    fn files_are_looked_at_once_per_interval() {
+        let path = std::env::temp_dir().join(format!("prefab-{}.ron", std::process::id()));
+        fs::write(&path, PREFAB).unwrap();
+        let mut file = PrefabFile::load(&path).unwrap();
+        // Pretend the file changed since it was loaded
+        file.modified = None;
+        let start = file.checked;
+        assert!(!file.reload_if_changed(start + RELOAD_INTERVAL / 2).unwrap());
+        assert!(file.reload_if_changed(start + RELOAD_INTERVAL).unwrap());
+        assert!(!file.reload_if_changed(start + 3 * RELOAD_INTERVAL).unwrap());
+        fs::remove_file(&path).unwrap();
+        // A missing file keeps the prefab
+        assert!(!file.reload_if_changed(start + 5 * RELOAD_INTERVAL).unwrap());
+        assert_eq!(file.prefab.num_parts(), 3);
+    }
+}
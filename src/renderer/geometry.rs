@@ -6,6 +6,10 @@ use super::{texture, shape::ShapeData};
 pub struct GeometryRenderer {
     pub diffuse: texture::Texture,
     pub normals_metallic_and_roughness: texture::Texture,
+    // Radiance the surface emits, added as is when lighting
+    pub emissive: texture::Texture,
+    // Index of the shape seen plus one, 0 where the terrain is
+    pub shape: texture::Texture,
     pub depth: texture::Texture,
     terrain_pipeline: wgpu::RenderPipeline,
     shape_pipeline: wgpu::RenderPipeline,
@@ -13,8 +17,13 @@ pub struct GeometryRenderer {
 
 const DIFFUSE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
 const NORMALS_SPECULAR_AND_ROUGHNESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const EMISSIVE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const SHAPE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
 const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24Plus;
 
+pub(crate) const TERRAIN_SHADER: &str = concat!(include_str!("topology.wgsl"), include_str!("terrain_height.wgsl"), include_str!("tiles.wgsl"), include_str!("geometry_terrain.wgsl"));
+pub(crate) const SHAPE_SHADER: &str = concat!(include_str!("topology.wgsl"), include_str!("terrain_height.wgsl"), include_str!("shapes.wgsl"), include_str!("geometry_shape.wgsl"));
+
 impl GeometryRenderer {
     pub fn new(
         resolution: UVec2,
@@ -29,11 +38,13 @@ impl GeometryRenderer {
             resolution,
             NORMALS_SPECULAR_AND_ROUGHNESS_FORMAT,
         );
+        let emissive = texture::Texture::new_intermediate(device, resolution, EMISSIVE_FORMAT);
+        let shape = texture::Texture::new_intermediate(device, resolution, SHAPE_FORMAT);
         let depth = texture::Texture::new_intermediate4(device, resolution, DEPTH_FORMAT, wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING);
 
         let terrain_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Terrain shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("geometry_terrain.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(TERRAIN_SHADER.into()),
         });
 
         let terrain_pipeline_layout =
@@ -70,6 +81,16 @@ impl GeometryRenderer {
                         blend: Some(wgpu::BlendState::REPLACE),
                         write_mask: wgpu::ColorWrites::ALL,
                     }),
+                    Some(wgpu::ColorTargetState {
+                        format: EMISSIVE_FORMAT,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }),
+                    Some(wgpu::ColorTargetState {
+                        format: SHAPE_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }),
                 ],
             }),
             primitive: wgpu::PrimitiveState {
@@ -94,7 +115,7 @@ impl GeometryRenderer {
 
         let shape_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shape shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("geometry_shape.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(SHAPE_SHADER.into()),
         });
 
         let shape_pipeline_layout =
@@ -131,6 +152,16 @@ impl GeometryRenderer {
                         blend: Some(wgpu::BlendState::REPLACE),
                         write_mask: wgpu::ColorWrites::ALL,
                     }),
+                    Some(wgpu::ColorTargetState {
+                        format: EMISSIVE_FORMAT,
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }),
+                    Some(wgpu::ColorTargetState {
+                        format: SHAPE_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }),
                 ],
             }),
             primitive: wgpu::PrimitiveState {
@@ -156,6 +187,8 @@ impl GeometryRenderer {
         return Self {
             diffuse,
             normals_metallic_and_roughness,
+            emissive,
+            shape,
             depth,
             terrain_pipeline,
             shape_pipeline,
@@ -169,6 +202,8 @@ impl GeometryRenderer {
             resolution,
             NORMALS_SPECULAR_AND_ROUGHNESS_FORMAT,
         );
+        self.emissive = texture::Texture::new_intermediate(device, resolution, EMISSIVE_FORMAT);
+        self.shape = texture::Texture::new_intermediate(device, resolution, SHAPE_FORMAT);
         self.depth = texture::Texture::new_intermediate4(device, resolution, DEPTH_FORMAT, wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING);
     }
 
@@ -179,7 +214,7 @@ impl GeometryRenderer {
         uniform_bind_group: &wgpu::BindGroup,
         sdf_bind_group: &wgpu::BindGroup,
         shapes_bind_group: &wgpu::BindGroup,
-        shapes: &Vec<ShapeData>,
+        shapes: &[ShapeData],
     ) {
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -211,6 +246,22 @@ impl GeometryRenderer {
                             store: wgpu::StoreOp::Store,
                         },
                     }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &self.emissive.view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &self.shape.view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
                 ],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth.view,
@@ -9,6 +9,7 @@ struct Uniforms {
     cursor_size: f32,
     time: f32,
     exposure: f32,
+    topology: u32,
 }
 
 @group(0) @binding(0)
@@ -36,26 +37,58 @@ fn main_vert(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
 
 // Fragment shader
 
-@group(1) @binding(0)
-var t_sdf: texture_2d<f32>;
-@group(1) @binding(1)
-var s_sdf: sampler;
+struct MaterialData {
+    albedo_metallic: vec4<f32>,
+    emissive_roughness: vec4<f32>,
+    // Width, profile
+    bevel: vec4<f32>,
+}
+@group(1) @binding(4)
+var<uniform> palette: array<MaterialData, 16>;
 
-fn unpackSdf(v: f32) -> f32 {
-    return v;
+fn sceneMaterial(world_pos: vec2<f32>) -> MaterialData {
+    var uv = world_pos * terrain.inv_window_size;
+    uv.y = -uv.y;
+    uv = uv + 0.5;
+    let texel = vec2<i32>(floor(fract(uv) * vec2<f32>(textureDimensions(t_sdf))));
+    return palette[min(u32(textureLoad(t_sdf, texel, 0).g + 0.5), 15u)];
 }
 
-fn sceneDist(world_pos: vec2<f32>) -> f32 {
-    var uv = world_pos * uniforms.inv_world_size;
+// Top of the terrain at `world_pos`, filtered between texels so that slopes stay smooth
+fn sceneTop(world_pos: vec2<f32>) -> f32 {
+    var uv = world_pos * terrain.inv_window_size;
     uv.y = -uv.y;
     uv = uv + 0.5;
-    return unpackSdf(textureSample(t_sdf, s_sdf, uv).r);
+    return terrainTop(textureSample(t_sdf, s_sdf, uv).b);
+}
+
+// Outward direction of the terrain surface, from the distance gradient. Samples the terrain, so
+// it must be called in uniform control flow.
+fn sceneNormal(world_pos: vec2<f32>) -> vec2<f32> {
+    let h = 1. / (terrain.inv_window_size * vec2<f32>(textureDimensions(t_sdf)));
+    let g = vec2<f32>(
+        sceneDist(world_pos + vec2<f32>(h.x, 0.)) - sceneDist(world_pos - vec2<f32>(h.x, 0.)),
+        sceneDist(world_pos + vec2<f32>(0., h.y)) - sceneDist(world_pos - vec2<f32>(0., h.y)),
+    );
+    return g / max(length(g), 1e-6);
+}
+
+// Gradient of `sceneTop`, it must be called in uniform control flow as well
+fn sceneTopGradient(world_pos: vec2<f32>) -> vec2<f32> {
+    let h = 1. / (terrain.inv_window_size * vec2<f32>(textureDimensions(t_sdf)));
+    return vec2<f32>(
+        sceneTop(world_pos + vec2<f32>(h.x, 0.)) - sceneTop(world_pos - vec2<f32>(h.x, 0.)),
+        sceneTop(world_pos + vec2<f32>(0., h.y)) - sceneTop(world_pos - vec2<f32>(0., h.y)),
+    ) / (2. * h);
 }
 
 struct FragmentOutput {
     @builtin(frag_depth) depth: f32,
     @location(0) albedo: vec4<f32>,
     @location(1) normals_metallic_roughness: vec4<f32>,
+    @location(2) emissive: vec4<f32>,
+    // No shape
+    @location(3) shape: u32,
 }
 
 fn encode_normal(normal: vec3<f32>) -> vec2<f32> {
@@ -75,30 +108,17 @@ fn encode_normal(normal: vec3<f32>) -> vec2<f32> {
 @fragment
 fn main_frag(in: VertexOutput) -> FragmentOutput {
     let dist = sceneDist(in.world_pos);
-    let normal = vec3<f32>(0., 0., 1.);
-
-    var albedo: vec3<f32>;
-    var metallic: f32;
-    var roughness: f32;
-    var depth: f32;
-
-    if dist < 0. {
-        albedo = vec3<f32>(0., 0., 0.);
-        metallic = 0.;
-        roughness = 1.;
-        depth = 0.;
-    } else {
-        albedo = vec3<f32>(.5, .5, .5);
-        metallic = 0.;
-        roughness = 0.1;
-        depth = 1.;
-    }
-    let patternMask = clamp(dot(floor((abs(in.world_pos) + .5) / 1.0), vec2<f32>(1.0)) % 2.0, 0.8, 1.0);
-    albedo = albedo * patternMask;
+    let outward = sceneNormal(in.world_pos);
+    let material = sceneMaterial(in.world_pos);
+    let surface = terrainSurface(material.bevel, dist, outward, sceneTop(in.world_pos), sceneTopGradient(in.world_pos));
+    // Only open ground glows, not the walls cut into the terrain
+    let emissive = select(vec3<f32>(0.), material.emissive_roughness.rgb, dist >= 0.);
 
     return FragmentOutput(
-        depth,
-        vec4<f32>(albedo, 1.0),
-        vec4<f32>(encode_normal(normal), metallic, roughness),
+        world_to_depth(GROUND_Z + surface.height),
+        vec4<f32>(material.albedo_metallic.rgb, 1.0),
+        vec4<f32>(encode_normal(surface.normal), material.albedo_metallic.a, material.emissive_roughness.a),
+        vec4<f32>(emissive, 1.0),
+        0u,
     );
 }
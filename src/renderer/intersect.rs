@@ -0,0 +1,459 @@
+// Ray intersection with shapes on the CPU, the distances of the intersectors in shapes.wgsl
+// without the normals. Rays start at `ro` relative to the shape's position and head along `rd`,
+// which is normalized. Misses are None, hits behind the origin come out negative like on the GPU.
+// Keep in sync with shapes.wgsl, picking on the CPU has to agree with what the GPU draws.
+
+use glam::*;
+
+use super::shape::*;
+
+fn i_sphere(ro: Vec3, rd: Vec3, radius: f32) -> Option<f32> {
+    let b = rd.dot(ro);
+    let c = ro.dot(ro) - radius * radius;
+    let h = b * b - c;
+    (h >= 0.).then(|| -b - h.sqrt())
+}
+
+fn i_rounded_cone(ro: Vec3, rd: Vec3, pb: Vec3, ra: f32, rb: f32) -> Option<f32> {
+    let ba = pb;
+    let oa = ro;
+    let ob = ro - pb;
+    let rr = ra - rb;
+    let m0 = ba.dot(ba);
+    let m1 = ba.dot(oa);
+    let m2 = ba.dot(rd);
+    let m3 = rd.dot(oa);
+    let m5 = oa.dot(oa);
+    let m6 = ob.dot(rd);
+    let m7 = ob.dot(ob);
+
+    let d2 = m0 - rr * rr;
+
+    let k2 = d2 - m2 * m2;
+    let k1 = d2 * m3 - m1 * m2 + m2 * rr * ra;
+    let k0 = d2 * m5 - m1 * m1 + m1 * rr * ra * 2. - m0 * ra * ra;
+
+    let h = k1 * k1 - k0 * k2;
+    if h < 0. {
+        return None;
+    }
+    let t = (-h.sqrt() - k1) / k2;
+    let y = m1 - ra * rr + t * m2;
+    if y > 0. && y < d2 {
+        return Some(t);
+    }
+
+    let h1 = m3 * m3 - m5 + ra * ra;
+    let h2 = m6 * m6 - m7 + rb * rb;
+    let t1 = (h1 > 0.).then(|| -m3 - h1.sqrt());
+    let t2 = (h2 > 0.).then(|| -m6 - h2.sqrt());
+    match (t1, t2) {
+        (Some(t1), Some(t2)) => Some(t1.min(t2)),
+        (t1, t2) => t1.or(t2),
+    }
+}
+
+// Unlike iBox, rays parallel to an axis, like the vertical ones picking casts, are limited by the
+// origin on that axis only. Their slabs would come out NaN otherwise.
+fn i_box(ro: Vec3, rd: Vec3, rad: Vec3) -> Option<f32> {
+    let parallel = rd.cmpeq(Vec3::ZERO);
+    if (parallel & ro.abs().cmpgt(rad)).any() {
+        return None;
+    }
+    let m = rd.recip();
+    let n = m * ro;
+    let k = m.abs() * rad;
+    let t_near = Vec3::select(parallel, Vec3::NEG_INFINITY, -n - k).max_element();
+    let t_far = Vec3::select(parallel, Vec3::INFINITY, -n + k).min_element();
+    (t_near <= t_far && t_far >= 0.).then_some(t_near)
+}
+
+// Roots of the edges of a rounded box along one axis, `a`, `b` and `c` are the quadratic's terms
+fn i_rounded_box_edge(a: f32, b: f32, c: f32) -> Option<f32> {
+    let h = b * b - a * c;
+    (h > 0.).then(|| (-b - h.sqrt()) / a)
+}
+
+fn i_rounded_box(ro_in: Vec3, rd_in: Vec3, size: Vec3, rad: f32) -> Option<f32> {
+    let t = i_box(ro_in, rd_in, size + rad)?;
+
+    // Mirror into the first octant
+    let pos = ro_in + t * rd_in;
+    let s = pos.signum();
+    let ro = ro_in * s;
+    let rd = rd_in * s;
+    let pos = pos * s;
+
+    // Faces
+    let pos = pos - size;
+    let pos = pos.max(pos.yzx());
+    if pos.min_element() < 0. {
+        return Some(t);
+    }
+
+    let oc = ro - size;
+    let dd = rd * rd;
+    let oo = oc * oc;
+    let od = oc * rd;
+    let ra2 = rad * rad;
+
+    // Corner
+    let b = od.x + od.y + od.z;
+    let c = oo.x + oo.y + oo.z - ra2;
+    let h = b * b - c;
+    let mut t = if h > 0. { -b - h.sqrt() } else { f32::INFINITY };
+
+    // Edges along x, y and z
+    let edges = [
+        (i_rounded_box_edge(dd.y + dd.z, od.y + od.z, oo.y + oo.z - ra2), ro.x, rd.x, size.x),
+        (i_rounded_box_edge(dd.z + dd.x, od.z + od.x, oo.z + oo.x - ra2), ro.y, rd.y, size.y),
+        (i_rounded_box_edge(dd.x + dd.y, od.x + od.y, oo.x + oo.y - ra2), ro.z, rd.z, size.z),
+    ];
+    for (h, o, d, size) in edges.iter().copied() {
+        if let Some(h) = h.filter(|&h| h > 0. && h < t && (o + d * h).abs() < size) {
+            t = h;
+        }
+    }
+    t.is_finite().then_some(t)
+}
+
+fn i_capsule(ro: Vec3, rd: Vec3, pb: Vec3, ra: f32) -> Option<f32> {
+    let ba = pb;
+    let oa = ro;
+    let baba = ba.dot(ba);
+    let bard = ba.dot(rd);
+    let baoa = ba.dot(oa);
+    let rdoa = rd.dot(oa);
+    let oaoa = oa.dot(oa);
+    let a = baba - bard * bard;
+    let b = baba * rdoa - baoa * bard;
+    let c = baba * oaoa - baoa * baoa - ra * ra * baba;
+    let h = b * b - a * c;
+    if h < 0. {
+        return None;
+    }
+    let t = (-b - h.sqrt()) / a;
+    let y = baoa + t * bard;
+    // Body
+    if y > 0. && y < baba {
+        return Some(t);
+    }
+    // Caps
+    let oc = if y <= 0. { oa } else { ro - pb };
+    let bc = rd.dot(oc);
+    let cc = oc.dot(oc) - ra * ra;
+    let hc = bc * bc - cc;
+    (hc > 0.).then(|| -bc - hc.sqrt())
+}
+
+fn i_cylinder(ro: Vec3, rd: Vec3, pb: Vec3, ra: f32) -> Option<f32> {
+    let ba = pb;
+    let oc = ro;
+    let baba = ba.dot(ba);
+    let bard = ba.dot(rd);
+    let baoc = ba.dot(oc);
+    let k2 = baba - bard * bard;
+    let k1 = baba * oc.dot(rd) - baoc * bard;
+    let k0 = baba * oc.dot(oc) - baoc * baoc - ra * ra * baba;
+    // Along the axis, like picking upright cylinders, the body drops out and only a cap is hit
+    if k2 <= 1e-6 * baba {
+        let cap = if bard > 0. { 0. } else { baba };
+        return (k0 < 0.).then(|| (cap - baoc) / bard);
+    }
+    let h = k1 * k1 - k2 * k0;
+    if h < 0. {
+        return None;
+    }
+    let h = h.sqrt();
+    let t = (-k1 - h) / k2;
+    // Body
+    let y = baoc + t * bard;
+    if y > 0. && y < baba {
+        return Some(t);
+    }
+    // Caps
+    let t = (if y < 0. { 0. } else { baba } - baoc) / bard;
+    ((k1 + k2 * t).abs() < h).then_some(t)
+}
+
+// Torus around the z axis with radii `major` and `minor`
+fn i_torus(ro: Vec3, rd: Vec3, major: f32, minor: f32) -> Option<f32> {
+    let mut po = 1.;
+    let ra2_major = major * major;
+    let ra2 = minor * minor;
+    let m = ro.dot(ro);
+    let n = ro.dot(rd);
+
+    // Bounding sphere
+    if n * n - m + (major + minor) * (major + minor) < 0. {
+        return None;
+    }
+
+    // Quartic equation
+    let k = (m - ra2 - ra2_major) / 2.;
+    let mut k3 = n;
+    let mut k2 = n * n + ra2_major * rd.z * rd.z + k;
+    let mut k1 = k * n + ra2_major * ro.z * rd.z;
+    let mut k0 = k * k + ra2_major * ro.z * ro.z - ra2_major * ra2;
+
+    // Keep |c1| away from zero
+    if (k3 * (k3 * k3 - k2) + k1).abs() < 0.01 {
+        po = -1.;
+        std::mem::swap(&mut k1, &mut k3);
+        k0 = 1. / k0;
+        k1 *= k0;
+        k2 *= k0;
+        k3 *= k0;
+    }
+
+    let c2 = (2. * k2 - 3. * k3 * k3) / 3.;
+    let c1 = (k3 * (k3 * k3 - k2) + k1) * 2.;
+    let c0 = (k3 * (k3 * (-3. * k3 * k3 + 4. * k2) - 8. * k1) + 4. * k0) / 3.;
+    let q = c2 * c2 + c0;
+    let r = 3. * c0 * c2 - c2 * c2 * c2 - c1 * c1;
+    let h = r * r - q * q * q;
+    let z = if h < 0. {
+        // 4 intersections
+        let sq = q.sqrt();
+        2. * sq * ((r / (sq * q)).acos() / 3.).cos()
+    } else {
+        // 2 intersections
+        let sq = (h.sqrt() + r.abs()).powf(1. / 3.);
+        r.signum() * (sq + q / sq).abs()
+    };
+    let z = c2 - z;
+    let mut d1 = z - 3. * c2;
+    let mut d2 = z * z - 3. * c0;
+    if d1.abs() < 1e-4 {
+        if d2 < 0. {
+            return None;
+        }
+        d2 = d2.sqrt();
+    } else {
+        if d1 < 0. {
+            return None;
+        }
+        d1 = (d1 / 2.).sqrt();
+        d2 = c1 / d1;
+    }
+
+    let mut result = f32::INFINITY;
+    for (offset, h) in [(-d1, d1 * d1 - z + d2), (d1, d1 * d1 - z - d2)].iter().copied() {
+        if h > 0. {
+            let h = h.sqrt();
+            for t in [offset - h - k3, offset + h - k3].iter().copied() {
+                let t = if po < 0. { 2. / t } else { t };
+                if t > 0. {
+                    result = result.min(t);
+                }
+            }
+        }
+    }
+    result.is_finite().then_some(result)
+}
+
+fn i_ellipsoid(ro: Vec3, rd: Vec3, ra: Vec3) -> Option<f32> {
+    let ocn = ro / ra;
+    let rdn = rd / ra;
+    let a = rdn.dot(rdn);
+    let b = ocn.dot(rdn);
+    let c = ocn.dot(ocn);
+    let h = b * b - a * (c - 1.);
+    (h >= 0.).then(|| (-b - h.sqrt()) / a)
+}
+
+// intersectPrimitive, with the untransformed primitive
+fn intersect_primitive(shape: &ShapeData, ro: Vec3, rd: Vec3) -> Option<f32> {
+    let data1 = Vec4::from(shape.data1);
+    let data2 = Vec4::from(shape.data2);
+    let offset = data2.xyz() - data1.xyz();
+    match shape.data0[0] {
+        SHAPE_SPHERE => i_sphere(ro, rd, data1.w),
+        SHAPE_ROUNDED_CONE => i_rounded_cone(ro, rd, offset, data1.w, data2.w),
+        SHAPE_BOX => i_box(ro, rd, data2.xyz()),
+        SHAPE_ROUNDED_BOX => i_rounded_box(ro, rd, (data2.xyz() - data1.w).max(Vec3::ZERO), data1.w),
+        SHAPE_CAPSULE => i_capsule(ro, rd, offset, data1.w),
+        SHAPE_CYLINDER => i_cylinder(ro, rd, offset, data1.w),
+        SHAPE_TORUS => i_torus(ro, rd, data1.w, data2.x),
+        SHAPE_ELLIPSOID => i_ellipsoid(ro, rd, data2.xyz()),
+        _ => None,
+    }
+}
+
+// intersectShape, the ray is intersected with the primitive in object space
+pub fn intersect_shape(shape: &ShapeData, ro: Vec3, rd: Vec3) -> Option<f32> {
+    let inv_rotation = Quat::from_array(shape.rotation).conjugate();
+    let scale = Vec4::from(shape.scale).xyz();
+    let ro_local = inv_rotation * ro / scale;
+    let rd_scaled = inv_rotation * rd / scale;
+    let rd_length = rd_scaled.length();
+    intersect_primitive(shape, ro_local, rd_scaled / rd_length).map(|t| t / rd_length)
+}
+
+#[cfg(test)]
+mod tests {
+    use bvh::aabb::Bounded;
+
+    use super::*;
+
+    #[test]
+    fn vertical_rays_hit_inside_the_bounds() {
+        for kind in ShapeKind::ALL {
+            for (rotation, scale) in [
+                (Quat::IDENTITY, Vec3::ONE),
+                (Quat::from_rotation_z(0.7) * Quat::from_rotation_x(0.4), Vec3::new(1., 2., 0.5)),
+            ] {
+                let mut shape = ShapeData::new();
+                shape.update_kind(kind, Vec3::new(3., -2., -2.), 0.5, [1.; 3], 0., 0.5);
+                shape.set_rotation(rotation);
+                shape.set_scale(scale);
+                let aabb = shape.aabb();
+                let centre = shape.position();
+
+                // A ray through the centre, which every kind but the torus covers, hits the top
+                let ro = Vec3::new(centre.x, centre.y, 2.);
+                let t = intersect_shape(&shape, ro - centre, -Vec3::Z);
+                if kind != ShapeKind::Torus {
+                    let t = t.unwrap_or_else(|| panic!("{} missed", kind.name()));
+                    let z = ro.z - t;
+                    assert!(z <= aabb.max.z + 1e-4 && z >= aabb.min.z - 1e-4, "{} hit at {}", kind.name(), z);
+                }
+
+                // Rays beside the bounds miss
+                for ro in [Vec3::new(aabb.max.x + 0.1, centre.y, 2.), Vec3::new(centre.x, aabb.min.y - 0.1, 2.)] {
+                    assert_eq!(intersect_shape(&shape, ro - centre, -Vec3::Z), None, "{}", kind.name());
+                }
+            }
+        }
+    }
+
+    // Distance to the primitives `intersect` tests, the ellipsoid's is only right on
+    // its surface and in sign
+    fn sdf(index: usize, p: Vec3) -> f32 {
+        match index {
+            0 => p.length() - 1.,
+            1 => {
+                // Between spheres at the origin and `pb`, https://iquilezles.org/articles/distfunctions/
+                let (ba, r1, r2) = (Vec3::new(0.3, 0.2, 1.2), 0.6, 0.3);
+                let l2 = ba.dot(ba);
+                let rr = r1 - r2;
+                let a2 = l2 - rr * rr;
+                let y = p.dot(ba);
+                let z = y - l2;
+                let x2 = (p * l2 - ba * y).length_squared();
+                let k = rr.signum() * rr * rr * x2;
+                if z.signum() * a2 * z * z * l2 > k {
+                    (x2 + z * z * l2).sqrt() / l2 - r2
+                } else if y.signum() * a2 * y * y * l2 < k {
+                    (x2 + y * y * l2).sqrt() / l2 - r1
+                } else {
+                    ((x2 * a2 / l2).sqrt() + y * rr) / l2 - r1
+                }
+            }
+            2 | 3 => {
+                let (size, rad) = if index == 2 { (Vec3::new(1., 0.5, 0.7), 0.) } else { (Vec3::new(0.8, 0.4, 0.5), 0.2) };
+                let q = p.abs() - size;
+                q.max(Vec3::ZERO).length() + q.max_element().min(0.) - rad
+            }
+            4 => {
+                let ba = Vec3::new(0.5, 0.3, 1.);
+                let h = (p.dot(ba) / ba.dot(ba)).clamp(0., 1.);
+                (p - ba * h).length() - 0.4
+            }
+            5 => {
+                let (ba, ra) = (Vec3::new(0.4, -0.3, 1.1), 0.5);
+                let baba = ba.dot(ba);
+                let paba = p.dot(ba);
+                let x = (p * baba - ba * paba).length() - ra * baba;
+                let y = (paba - 0.5 * baba).abs() - 0.5 * baba;
+                let (x2, y2) = (x * x, y * y * baba);
+                let d = if x.max(y) < 0. { -x2.min(y2) } else { x.max(0.).powi(2) + y.max(0.).powi(2) * baba };
+                d.signum() * d.abs().sqrt() / baba
+            }
+            6 => Vec2::new(p.xy().length() - 1., p.z).length() - 0.3,
+            _ => (p / Vec3::new(1.2, 0.6, 0.8)).length() - 1.,
+        }
+    }
+
+    fn intersect(index: usize, ro: Vec3, rd: Vec3) -> Option<f32> {
+        match index {
+            0 => i_sphere(ro, rd, 1.),
+            1 => i_rounded_cone(ro, rd, Vec3::new(0.3, 0.2, 1.2), 0.6, 0.3),
+            2 => i_box(ro, rd, Vec3::new(1., 0.5, 0.7)),
+            3 => i_rounded_box(ro, rd, Vec3::new(0.8, 0.4, 0.5), 0.2),
+            4 => i_capsule(ro, rd, Vec3::new(0.5, 0.3, 1.), 0.4),
+            5 => i_cylinder(ro, rd, Vec3::new(0.4, -0.3, 1.1), 0.5),
+            6 => i_torus(ro, rd, 1., 0.3),
+            _ => i_ellipsoid(ro, rd, Vec3::new(1.2, 0.6, 0.8)),
+        }
+    }
+
+    // Rays from all around towards points in and around the primitives, and straight down and
+    // along the axes where the intersectors special-case parallel rays
+    fn rays() -> Vec<(Vec3, Vec3)> {
+        let mut rays = Vec::new();
+        let golden = std::f32::consts::PI * (3. - 5f32.sqrt());
+        for i in 0..64 {
+            let z = 1. - 2. * (i as f32 + 0.5) / 64.;
+            let r = (1. - z * z).sqrt();
+            let ro = 4. * Vec3::new(r * (golden * i as f32).cos(), r * (golden * i as f32).sin(), z);
+            for target in [Vec3::ZERO, Vec3::new(0.3, 0.2, 0.6), Vec3::new(-0.9, 0.4, 0.), Vec3::new(1., 0., 0.1), Vec3::new(0.2, -0.7, 1.)] {
+                rays.push((ro, (target - ro).normalize()));
+            }
+        }
+        for x in -12..=12 {
+            for y in -12..=12 {
+                let p = Vec2::new(x as f32, y as f32) * 0.125;
+                rays.push((p.extend(4.), -Vec3::Z));
+                rays.push((Vec3::new(-4., p.x, p.y), Vec3::X));
+                rays.push((Vec3::new(p.x, 4., p.y), -Vec3::Y));
+            }
+        }
+        rays
+    }
+
+    #[test]
+    fn every_primitive_is_hit_on_its_surface() {
+        for index in 0..8 {
+            let (mut hits, mut misses) = (0, 0);
+            for (ro, rd) in rays() {
+                let t = intersect(index, ro, rd);
+                let end = t.unwrap_or(8.);
+                if let Some(t) = t {
+                    hits += 1;
+                    assert!(t > 0., "primitive {} hit behind {} along {}", index, ro, rd);
+                    let d = sdf(index, ro + t * rd);
+                    assert!(d.abs() < 2e-3, "primitive {} hit {} off its surface from {} along {}", index, d, ro, rd);
+                } else {
+                    misses += 1;
+                }
+                // Nothing closer was passed through
+                for i in 0..256 {
+                    let p = ro + end * (i as f32 / 256.) * rd;
+                    assert!(sdf(index, p) > -2e-3, "primitive {} missed {} from {} along {}", index, p, ro, rd);
+                }
+            }
+            assert!(hits > 100 && misses > 100, "primitive {} had {} hits and {} misses", index, hits, misses);
+        }
+    }
+
+    #[test]
+    fn origins_inside_give_negative_distances() {
+        // The torus has no inside around its centre
+        for index in (0..8).filter(|&index| index != 6) {
+            let centre = if index == 1 || index == 4 || index == 5 { Vec3::new(0.2, 0., 0.5) } else { Vec3::ZERO };
+            assert!(sdf(index, centre) < 0.);
+            let t = intersect(index, centre, Vec3::Z);
+            assert!(!t.is_some_and(|t| t > 0.), "primitive {} hit at {:?} from inside", index, t);
+        }
+    }
+
+    #[test]
+    fn torus_is_hit_on_its_ring() {
+        let mut shape = ShapeData::new();
+        shape.update_torus(Vec3::ZERO, 1., 0.25, [1.; 3], 0., 0.5);
+        assert_eq!(intersect_shape(&shape, Vec3::new(0., 0., 2.), -Vec3::Z), None);
+        let t = intersect_shape(&shape, Vec3::new(1., 0., 2.), -Vec3::Z).unwrap();
+        assert!((t - 1.75).abs() < 1e-3);
+    }
+}
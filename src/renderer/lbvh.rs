@@ -0,0 +1,430 @@
+use wgpu::PipelineCompilationOptions;
+
+use super::MAX_SHAPES;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Zeroable, bytemuck::Pod)]
+struct Uniforms {
+    pub num_shapes: u32,
+    pub num_blocks: u32,
+    pub shift: u32,
+    pub dummy: u32,
+}
+
+const WORKGROUP_SIZE: u32 = 256;
+const RADIX: u32 = 16;
+const RADIX_BITS: u32 = 4;
+// Morton codes have 30 bits
+const SORT_PASSES: u32 = 8;
+// Scene bounds, then the refit visit count and bounds of every internal node
+const COUNTERS_NODES: usize = 6;
+const COUNTERS_PER_NODE: usize = 7;
+const TREE_NODE_SIZE: u64 = 48;
+
+// The scan pass covers the digit counts of all blocks in a single workgroup
+const _: () = assert!(MAX_SHAPES as u32 / WORKGROUP_SIZE * RADIX <= WORKGROUP_SIZE);
+
+// Builds the shape BVH in compute shaders as a linear BVH. Shapes are sorted by the Morton code
+// of their bounds centre with a 4 bit radix sort, the hierarchy is emitted from the sorted codes,
+// bounds are refitted bottom-up and the tree is flattened into the nodes buffer in the layout the
+// CPU built tree uses, with a single shape in every leaf.
+pub struct LBVHBuilder {
+    uniform_stride: u64,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    _pairs: [wgpu::Buffer; 2],
+    _histogram: wgpu::Buffer,
+    counters: wgpu::Buffer,
+    _tree: wgpu::Buffer,
+    // Reading the first pair buffer and writing the second, and the other way around
+    bind_groups: [wgpu::BindGroup; 2],
+    bounds_pipeline: wgpu::ComputePipeline,
+    morton_pipeline: wgpu::ComputePipeline,
+    histogram_pipeline: wgpu::ComputePipeline,
+    scan_pipeline: wgpu::ComputePipeline,
+    scatter_pipeline: wgpu::ComputePipeline,
+    hierarchy_pipeline: wgpu::ComputePipeline,
+    refit_pipeline: wgpu::ComputePipeline,
+    flatten_pipeline: wgpu::ComputePipeline,
+}
+
+impl LBVHBuilder {
+    // `shapes` holds up to MAX_SHAPES `ShapeData`, `nodes` room for 2 * MAX_SHAPES `ShapeBVHNode`
+    // and `shape_indices` for MAX_SHAPES u32
+    pub fn new(device: &wgpu::Device, shapes: &wgpu::Buffer, nodes: &wgpu::Buffer, shape_indices: &wgpu::Buffer) -> Self {
+        let uniform_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("lbvh_uniform_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    count: None,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<Uniforms>() as u64),
+                    },
+                }
+            ],
+        });
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            count: None,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("lbvh_bind_group_layout"),
+            entries: &[
+                storage_entry(0, true),
+                storage_entry(1, true),
+                storage_entry(2, false),
+                storage_entry(3, false),
+                storage_entry(4, false),
+                storage_entry(5, false),
+                storage_entry(6, false),
+                storage_entry(7, false),
+            ],
+        });
+
+        let storage_buffer = |label, size| device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let pairs = [
+            storage_buffer("LBVH pairs", (MAX_SHAPES * 8) as u64),
+            storage_buffer("LBVH pairs", (MAX_SHAPES * 8) as u64),
+        ];
+        let histogram = storage_buffer("LBVH histogram", (RADIX * MAX_SHAPES as u32 / WORKGROUP_SIZE * 4) as u64);
+        let counters = storage_buffer("LBVH counters", ((COUNTERS_NODES + COUNTERS_PER_NODE * MAX_SHAPES) * 4) as u64);
+        let tree = storage_buffer("LBVH tree", 2 * MAX_SHAPES as u64 * TREE_NODE_SIZE);
+
+        let bind_group = |src: &wgpu::Buffer, dst: &wgpu::Buffer| device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: shapes.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: src.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: dst.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 3, resource: histogram.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 4, resource: counters.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 5, resource: tree.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 6, resource: nodes.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 7, resource: shape_indices.as_entire_binding() },
+            ],
+            label: None,
+        });
+        let bind_groups = [bind_group(&pairs[0], &pairs[1]), bind_group(&pairs[1], &pairs[0])];
+
+        // Slot 0 is shared by all passes but the sort, slot i + 1 holds the digit of sort pass i
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let uniform_stride = (std::mem::size_of::<Uniforms>() as u64).div_ceil(alignment) * alignment;
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("LBVH uniforms"),
+            size: uniform_stride * (SORT_PASSES + 1) as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &uniform_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &uniform_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<Uniforms>() as u64),
+                }),
+            }],
+            label: None,
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("LBVH shader"),
+            source: wgpu::ShaderSource::Wgsl(concat!(include_str!("shapes.wgsl"), include_str!("lbvh.wgsl")).into()),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("LBVH"),
+            bind_group_layouts: &[&uniform_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("LBVH pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+
+        Self {
+            uniform_stride,
+            uniform_buffer,
+            uniform_bind_group,
+            _pairs: pairs,
+            _histogram: histogram,
+            counters,
+            _tree: tree,
+            bind_groups,
+            bounds_pipeline: pipeline("main_bounds"),
+            morton_pipeline: pipeline("main_morton"),
+            histogram_pipeline: pipeline("main_histogram"),
+            scan_pipeline: pipeline("main_scan"),
+            scatter_pipeline: pipeline("main_scatter"),
+            hierarchy_pipeline: pipeline("main_hierarchy"),
+            refit_pipeline: pipeline("main_refit"),
+            flatten_pipeline: pipeline("main_flatten"),
+        }
+    }
+
+    // Records a build over the first `num_shapes` shapes into `encoder` and returns the number
+    // of nodes it writes
+    pub fn build(&self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, num_shapes: u32) -> u32 {
+        if num_shapes == 0 {
+            return 0;
+        }
+        let num_blocks = num_shapes.div_ceil(WORKGROUP_SIZE);
+        let stride = self.uniform_stride as usize;
+        let mut data = vec![0u8; stride * (SORT_PASSES + 1) as usize];
+        for i in 0..=SORT_PASSES as usize {
+            let uniforms = Uniforms {
+                num_shapes,
+                num_blocks,
+                shift: i.saturating_sub(1) as u32 * RADIX_BITS,
+                dummy: 0,
+            };
+            data[i * stride..i * stride + std::mem::size_of::<Uniforms>()].copy_from_slice(bytemuck::bytes_of(&uniforms));
+        }
+        queue.write_buffer(&self.uniform_buffer, 0, &data);
+        encoder.clear_buffer(&self.counters, 0, None);
+
+        let tree_blocks = (2 * num_shapes - 1).div_ceil(WORKGROUP_SIZE);
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("LBVH"), timestamp_writes: None, });
+        compute_pass.set_bind_group(0, &self.uniform_bind_group, &[0]);
+        // Morton codes go into the first pair buffer, which every even sort pass reads from
+        compute_pass.set_bind_group(1, &self.bind_groups[1], &[]);
+        compute_pass.set_pipeline(&self.bounds_pipeline);
+        compute_pass.dispatch_workgroups(num_blocks, 1, 1);
+        compute_pass.set_pipeline(&self.morton_pipeline);
+        compute_pass.dispatch_workgroups(num_blocks, 1, 1);
+
+        for pass in 0..SORT_PASSES {
+            compute_pass.set_bind_group(0, &self.uniform_bind_group, &[(pass + 1) * stride as u32]);
+            compute_pass.set_bind_group(1, &self.bind_groups[(pass % 2) as usize], &[]);
+            compute_pass.set_pipeline(&self.histogram_pipeline);
+            compute_pass.dispatch_workgroups(num_blocks, 1, 1);
+            compute_pass.set_pipeline(&self.scan_pipeline);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+            compute_pass.set_pipeline(&self.scatter_pipeline);
+            compute_pass.dispatch_workgroups(num_blocks, 1, 1);
+        }
+
+        // An even number of passes leaves the sorted codes in the first pair buffer
+        compute_pass.set_bind_group(0, &self.uniform_bind_group, &[0]);
+        compute_pass.set_bind_group(1, &self.bind_groups[0], &[]);
+        compute_pass.set_pipeline(&self.hierarchy_pipeline);
+        compute_pass.dispatch_workgroups(num_blocks, 1, 1);
+        compute_pass.set_pipeline(&self.refit_pipeline);
+        compute_pass.dispatch_workgroups(num_blocks, 1, 1);
+        compute_pass.set_pipeline(&self.flatten_pipeline);
+        compute_pass.dispatch_workgroups(tree_blocks, 1, 1);
+
+        2 * num_shapes - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bvh::aabb::Bounded;
+    use glam::*;
+
+    use super::*;
+    use crate::renderer::shape::{ShapeBVHNode, ShapeData};
+
+    fn expand_bits(v: u32) -> u32 {
+        let mut x = v & 0x3ff;
+        x = (x | (x << 16)) & 0x030000ff;
+        x = (x | (x << 8)) & 0x0300f00f;
+        x = (x | (x << 4)) & 0x030c30c3;
+        x = (x | (x << 2)) & 0x09249249;
+        x
+    }
+
+    fn morton_code(p: Vec3, scene_min: Vec3, scene_max: Vec3) -> u32 {
+        let scale = Vec3::splat(1024.) / (scene_max - scene_min).max(Vec3::splat(1e-6));
+        let q = ((p - scene_min) * scale).floor().clamp(Vec3::ZERO, Vec3::splat(1023.)).as_uvec3();
+        (expand_bits(q.x) << 2) | (expand_bits(q.y) << 1) | expand_bits(q.z)
+    }
+
+    fn bounds(shape: &ShapeData) -> (Vec3, Vec3) {
+        let aabb = shape.aabb();
+        let (min, max): ([f32; 3], [f32; 3]) = (aabb.min.into(), aabb.max.into());
+        (min.into(), max.into())
+    }
+
+    struct Reference<'a> {
+        shapes: &'a [ShapeData],
+        sorted: Vec<(u32, u32)>,
+        nodes: Vec<ShapeBVHNode>,
+    }
+
+    impl Reference<'_> {
+        // Common prefix of sorted codes `i` and `j`, equal codes are told apart by index
+        fn delta(&self, i: usize, j: usize) -> u32 {
+            let (a, b) = (self.sorted[i].0, self.sorted[j].0);
+            if a == b { 32 + ((i ^ j) as u32).leading_zeros() } else { (a ^ b).leading_zeros() }
+        }
+
+        // Emits the subtree over sorted range `first..=last` top-down and returns its bounds
+        fn emit(&mut self, first: usize, last: usize) -> (Vec3, Vec3) {
+            let index = self.nodes.len();
+            self.nodes.push(ShapeBVHNode::default());
+            if first == last {
+                let (min, max) = bounds(&self.shapes[self.sorted[first].1 as usize]);
+                self.nodes[index] = ShapeBVHNode::leaf(min, max, first..first + 1, index as u32 + 1);
+                return (min, max);
+            }
+            let prefix = self.delta(first, last);
+            let split = (first + 1..last).rev().find(|&s| self.delta(first, s) > prefix).unwrap_or(first);
+            let (a_min, a_max) = self.emit(first, split);
+            let (b_min, b_max) = self.emit(split + 1, last);
+            let (min, max) = (a_min.min(b_min), a_max.max(b_max));
+            self.nodes[index] = ShapeBVHNode::inner(min, max, index as u32 + 1, self.nodes.len() as u32);
+            (min, max)
+        }
+    }
+
+    // The same tree built on the CPU, splitting every range where the codes first differ. Returns
+    // the nodes and the shape indices.
+    fn reference(shapes: &[ShapeData]) -> (Vec<ShapeBVHNode>, Vec<u32>) {
+        let (scene_min, scene_max) = shapes.iter().map(bounds)
+            .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), (a, b)| (min.min(a), max.max(b)));
+        let mut sorted: Vec<(u32, u32)> = shapes.iter().enumerate().map(|(i, shape)| {
+            let (min, max) = bounds(shape);
+            (morton_code(0.5 * (min + max), scene_min, scene_max), i as u32)
+        }).collect();
+        sorted.sort_by_key(|&(code, _)| code);
+        let mut reference = Reference { shapes, sorted, nodes: Vec::new() };
+        reference.emit(0, shapes.len() - 1);
+        (reference.nodes, reference.sorted.iter().map(|&(_, shape)| shape).collect())
+    }
+
+    // Spheres on half-integer centres in a scene spanning exactly 0..1024, so the Morton codes
+    // come out the same whatever rounding the GPU does
+    fn scene(count: usize, seed: u32) -> Vec<ShapeData> {
+        let mut state = seed;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state % 1024) as f32 + 0.5
+        };
+        let mut centres = vec![Vec3::splat(0.5), Vec3::splat(1023.5)];
+        centres.extend((2..count).map(|_| Vec3::new(random(), random(), random())));
+        centres.truncate(count);
+        centres.iter().map(|&centre| {
+            let mut shape = ShapeData::new();
+            shape.update_sphere(centre, 0.5, [1.; 3], 0., 0.5);
+            shape
+        }).collect()
+    }
+
+    // Every node must hold the bounds of its shapes, and every shape must be in one leaf
+    fn check_bounds(nodes: &[ShapeBVHNode], indices: &[u32], shapes: &[ShapeData]) {
+        let mut found = vec![false; shapes.len()];
+        for (i, node) in nodes.iter().enumerate() {
+            let (min, max) = node.bounds();
+            for leaf in nodes[i..node.exit as usize].iter().filter(|node| node.is_leaf()) {
+                for &shape in &indices[leaf.shapes()] {
+                    let (shape_min, shape_max) = bounds(&shapes[shape as usize]);
+                    assert!(min.cmple(shape_min + 1e-3).all() && max.cmpge(shape_max - 1e-3).all(), "node {} misses a shape", i);
+                    found[shape as usize] = true;
+                }
+            }
+        }
+        assert!(found.iter().all(|&found| found));
+    }
+
+    #[test]
+    fn reference_covers_every_shape() {
+        for count in [1, 2, 3, 17, 300] {
+            let shapes = scene(count, 7 + count as u32);
+            let (nodes, indices) = reference(&shapes);
+            assert_eq!(nodes.len(), 2 * count - 1);
+            assert_eq!(nodes[0].exit as usize, nodes.len());
+            check_bounds(&nodes, &indices, &shapes);
+        }
+    }
+
+    fn build_on_gpu(device: &wgpu::Device, queue: &wgpu::Queue, shapes: &[ShapeData]) -> (Vec<ShapeBVHNode>, Vec<u32>) {
+        use wgpu::util::DeviceExt;
+
+        let mut padded = shapes.to_vec();
+        padded.resize(MAX_SHAPES, ShapeData::default());
+        let shapes_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&padded),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let output = |size| device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let nodes_size = (2 * MAX_SHAPES * std::mem::size_of::<ShapeBVHNode>()) as u64;
+        let indices_size = (MAX_SHAPES * 4) as u64;
+        let nodes_buffer = output(nodes_size);
+        let indices_buffer = output(indices_size);
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: nodes_size + indices_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let builder = LBVHBuilder::new(device, &shapes_buffer, &nodes_buffer, &indices_buffer);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        let num_nodes = builder.build(queue, &mut encoder, shapes.len() as u32) as usize;
+        encoder.copy_buffer_to_buffer(&nodes_buffer, 0, &readback, 0, nodes_size);
+        encoder.copy_buffer_to_buffer(&indices_buffer, 0, &readback, nodes_size, indices_size);
+        queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |result| result.unwrap());
+        device.poll(wgpu::Maintain::Wait);
+        let data = slice.get_mapped_range();
+        let nodes = bytemuck::cast_slice::<u8, ShapeBVHNode>(&data[..nodes_size as usize])[..num_nodes].to_vec();
+        let indices = bytemuck::cast_slice::<u8, u32>(&data[nodes_size as usize..])[..shapes.len()].to_vec();
+        (nodes, indices)
+    }
+
+    // Run with `cargo test -- --ignored` where there is an adapter
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn gpu_matches_reference() {
+        let instance = wgpu::Instance::default();
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default())).expect("no adapter for the GPU LBVH test");
+        let (device, queue) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default(), None)).expect("no device for the GPU LBVH test");
+
+        for count in [1, 2, 3, 255, 256, 257, 1000, MAX_SHAPES] {
+            let shapes = scene(count, 31 + count as u32);
+            let (expected, expected_indices) = reference(&shapes);
+            let (nodes, indices) = build_on_gpu(&device, &queue, &shapes);
+            assert_eq!(nodes.len(), expected.len());
+            for (i, (node, expected)) in nodes.iter().zip(expected.iter()).enumerate() {
+                assert_eq!((node.entry, node.exit), (expected.entry, expected.exit), "node {} of {} shapes", i, count);
+                let pos = Vec3::from(node.aabb_pos) - Vec3::from(expected.aabb_pos);
+                let rad = Vec3::from(node.aabb_rad) - Vec3::from(expected.aabb_rad);
+                assert!(pos.abs().max_element() < 1e-3 && rad.abs().max_element() < 1e-3, "node {} of {} shapes", i, count);
+            }
+            assert_eq!(indices, expected_indices);
+            check_bounds(&nodes, &indices, &shapes);
+        }
+    }
+}
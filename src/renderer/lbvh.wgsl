@@ -0,0 +1,364 @@
+// Linear BVH construction, shapes.wgsl is prepended. Shapes are sorted along a Morton curve
+// through their bounds centres, the hierarchy is read off the sorted codes (Karras 2012), bounds
+// are refitted bottom-up and the tree is written out in the flattened layout the light map
+// traverses.
+
+struct Uniforms {
+    num_shapes: u32,
+    num_blocks: u32,
+    // Digit of the current radix sort pass
+    shift: u32,
+    dummy: u32,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: Uniforms;
+
+// Internal nodes come first, leaf i is node num_shapes - 1 + i. Leaves keep their shape in `left`,
+// only leaves keep their bounds here.
+struct TreeNode {
+    min: vec3<f32>,
+    parent: u32,
+    max: vec3<f32>,
+    left: u32,
+    right: u32,
+    first: u32,
+    last: u32,
+    dummy: u32,
+}
+
+struct ShapeBVHNode {
+    aabb_pos: vec3<f32>,
+    entry: u32,
+    aabb_rad: vec3<f32>,
+    exit: u32,
+}
+
+@group(1) @binding(0)
+var<storage, read> shapes: array<ShapeData>;
+// (Morton code, shape) pairs, sorted back and forth between `src` and `dst`
+@group(1) @binding(1)
+var<storage, read> src: array<vec2<u32>>;
+@group(1) @binding(2)
+var<storage, read_write> dst: array<vec2<u32>>;
+// Digit counts of every block, digit-major so their exclusive scan is where the blocks scatter to
+@group(1) @binding(3)
+var<storage, read_write> histogram: array<u32>;
+// Scene bounds as order preserving integers, maximum then inverted minimum, followed by the
+// refit visit count, minimum and maximum of every internal node. Cleared before every build.
+@group(1) @binding(4)
+var<storage, read_write> counters: array<atomic<u32>>;
+@group(1) @binding(5)
+var<storage, read_write> tree: array<TreeNode>;
+@group(1) @binding(6)
+var<storage, read_write> nodes: array<ShapeBVHNode>;
+// Shapes in leaf order, which is the sorted order
+@group(1) @binding(7)
+var<storage, read_write> shape_indices: array<u32>;
+
+const WORKGROUP_SIZE: u32 = 256u;
+const RADIX: u32 = 16u;
+const NONE: u32 = 0xffffffffu;
+// Same as in shape.rs, every leaf holds a single shape
+const BVH_LEAF_FLAG: u32 = 0x80000000u;
+const COUNTERS_NODES: u32 = 6u;
+const COUNTERS_PER_NODE: u32 = 7u;
+
+fn orderedBits(f: f32) -> u32 {
+    let u = bitcast<u32>(f);
+    return select(u | 0x80000000u, ~u, (u & 0x80000000u) != 0u);
+}
+
+fn fromOrderedBits(u: u32) -> f32 {
+    return bitcast<f32>(select(~u, u & 0x7fffffffu, (u & 0x80000000u) != 0u));
+}
+
+fn sceneMin() -> vec3<f32> {
+    return vec3<f32>(
+        fromOrderedBits(~atomicLoad(&counters[3])),
+        fromOrderedBits(~atomicLoad(&counters[4])),
+        fromOrderedBits(~atomicLoad(&counters[5])),
+    );
+}
+
+fn sceneMax() -> vec3<f32> {
+    return vec3<f32>(
+        fromOrderedBits(atomicLoad(&counters[0])),
+        fromOrderedBits(atomicLoad(&counters[1])),
+        fromOrderedBits(atomicLoad(&counters[2])),
+    );
+}
+
+// Spreads the low 10 bits of `v` to every third bit
+fn expandBits(v: u32) -> u32 {
+    var x = v & 0x3ffu;
+    x = (x | (x << 16u)) & 0x030000ffu;
+    x = (x | (x << 8u)) & 0x0300f00fu;
+    x = (x | (x << 4u)) & 0x030c30c3u;
+    x = (x | (x << 2u)) & 0x09249249u;
+    return x;
+}
+
+// Same as `morton_code` in the tests of lbvh.rs
+fn mortonCode(p: vec3<f32>, scene_min: vec3<f32>, scene_max: vec3<f32>) -> u32 {
+    let scale = 1024. / max(scene_max - scene_min, vec3<f32>(1e-6));
+    let q = vec3<u32>(clamp(floor((p - scene_min) * scale), vec3<f32>(0.), vec3<f32>(1023.)));
+    return (expandBits(q.x) << 2u) | (expandBits(q.y) << 1u) | expandBits(q.z);
+}
+
+@compute @workgroup_size(256)
+fn main_bounds(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= uniforms.num_shapes {
+        return;
+    }
+    let bounds = shapeBounds(shapes[i]);
+    atomicMax(&counters[0], orderedBits(bounds.max.x));
+    atomicMax(&counters[1], orderedBits(bounds.max.y));
+    atomicMax(&counters[2], orderedBits(bounds.max.z));
+    atomicMax(&counters[3], ~orderedBits(bounds.min.x));
+    atomicMax(&counters[4], ~orderedBits(bounds.min.y));
+    atomicMax(&counters[5], ~orderedBits(bounds.min.z));
+}
+
+@compute @workgroup_size(256)
+fn main_morton(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if i >= uniforms.num_shapes {
+        return;
+    }
+    let bounds = shapeBounds(shapes[i]);
+    dst[i] = vec2<u32>(mortonCode(0.5 * (bounds.min + bounds.max), sceneMin(), sceneMax()), i);
+}
+
+// Radix sort, 4 bits per pass over blocks of one workgroup
+
+var<workgroup> block_counts: array<atomic<u32>, RADIX>;
+var<workgroup> block_digits: array<u32, WORKGROUP_SIZE>;
+var<workgroup> scan: array<u32, WORKGROUP_SIZE>;
+
+fn digit(key: u32) -> u32 {
+    return (key >> uniforms.shift) & (RADIX - 1u);
+}
+
+@compute @workgroup_size(256)
+fn main_histogram(@builtin(global_invocation_id) id: vec3<u32>, @builtin(local_invocation_index) local: u32, @builtin(workgroup_id) block: vec3<u32>) {
+    if local < RADIX {
+        atomicStore(&block_counts[local], 0u);
+    }
+    workgroupBarrier();
+    if id.x < uniforms.num_shapes {
+        atomicAdd(&block_counts[digit(src[id.x].x)], 1u);
+    }
+    workgroupBarrier();
+    if local < RADIX {
+        histogram[local * uniforms.num_blocks + block.x] = atomicLoad(&block_counts[local]);
+    }
+}
+
+// Exclusive scan of the whole histogram in a single workgroup, it has at most 256 entries
+@compute @workgroup_size(256)
+fn main_scan(@builtin(local_invocation_index) local: u32) {
+    let count = RADIX * uniforms.num_blocks;
+    let value = select(0u, histogram[local], local < count);
+    scan[local] = value;
+    workgroupBarrier();
+    for (var offset = 1u; offset < WORKGROUP_SIZE; offset = offset * 2u) {
+        let add = select(0u, scan[max(local, offset) - offset], local >= offset);
+        workgroupBarrier();
+        scan[local] = scan[local] + add;
+        workgroupBarrier();
+    }
+    if local < count {
+        histogram[local] = scan[local] - value;
+    }
+}
+
+@compute @workgroup_size(256)
+fn main_scatter(@builtin(global_invocation_id) id: vec3<u32>, @builtin(local_invocation_index) local: u32, @builtin(workgroup_id) block: vec3<u32>) {
+    let valid = id.x < uniforms.num_shapes;
+    var pair = vec2<u32>(0u);
+    var d = RADIX;
+    if valid {
+        pair = src[id.x];
+        d = digit(pair.x);
+    }
+    block_digits[local] = d;
+    workgroupBarrier();
+    if !valid {
+        return;
+    }
+    // Stable, keys with the same digit keep their order within the block
+    var rank = 0u;
+    for (var j = 0u; j < local; j++) {
+        rank += select(0u, 1u, block_digits[j] == d);
+    }
+    dst[histogram[d * uniforms.num_blocks + block.x] + rank] = pair;
+}
+
+// Hierarchy over the sorted codes in `src`
+
+// Length of the common prefix of sorted codes `i` and `j`, equal codes are told apart by index
+fn delta(i: i32, j: i32) -> i32 {
+    if j < 0 || j >= i32(uniforms.num_shapes) {
+        return -1;
+    }
+    let ki = src[i].x;
+    let kj = src[j].x;
+    if ki == kj {
+        return 32 + i32(countLeadingZeros(u32(i) ^ u32(j)));
+    }
+    return i32(countLeadingZeros(ki ^ kj));
+}
+
+@compute @workgroup_size(256)
+fn main_hierarchy(@builtin(global_invocation_id) id: vec3<u32>) {
+    let n = uniforms.num_shapes;
+    let k = id.x;
+    if k < n {
+        // Leaf
+        let shape = src[k].y;
+        let bounds = shapeBounds(shapes[shape]);
+        let leaf = n - 1u + k;
+        tree[leaf].min = bounds.min;
+        tree[leaf].max = bounds.max;
+        tree[leaf].left = shape;
+        tree[leaf].right = NONE;
+        tree[leaf].first = k;
+        tree[leaf].last = k;
+        if n == 1u {
+            tree[leaf].parent = NONE;
+        }
+    }
+    if k + 1u >= n {
+        return;
+    }
+
+    // Internal node, find the end of its range and then the split inside it
+    let i = i32(k);
+    let d = select(-1, 1, delta(i, i + 1) - delta(i, i - 1) >= 0);
+    let delta_min = delta(i, i - d);
+    var l_max = 2;
+    while delta(i, i + l_max * d) > delta_min {
+        l_max = l_max * 2;
+    }
+    var l = 0;
+    for (var t = l_max / 2; t >= 1; t = t / 2) {
+        if delta(i, i + (l + t) * d) > delta_min {
+            l = l + t;
+        }
+    }
+    let j = i + l * d;
+    let delta_node = delta(i, j);
+    var s = 0;
+    var divisor = 2;
+    var t = (l + divisor - 1) / divisor;
+    loop {
+        if delta(i, i + (s + t) * d) > delta_node {
+            s = s + t;
+        }
+        if t <= 1 {
+            break;
+        }
+        divisor = divisor * 2;
+        t = (l + divisor - 1) / divisor;
+    }
+    let gamma = i + s * d + min(d, 0);
+
+    let first = u32(min(i, j));
+    let last = u32(max(i, j));
+    let left = select(u32(gamma), n - 1u + u32(gamma), first == u32(gamma));
+    let right = select(u32(gamma) + 1u, n + u32(gamma), last == u32(gamma) + 1u);
+    tree[k].left = left;
+    tree[k].right = right;
+    tree[k].first = first;
+    tree[k].last = last;
+    tree[left].parent = k;
+    tree[right].parent = k;
+    if k == 0u {
+        tree[k].parent = NONE;
+    }
+}
+
+// Bottom-up refit, the second child to arrive at a node merges both. Internal bounds go through
+// atomics, so the other child's are seen whichever workgroup wrote them.
+
+struct Bounds {
+    min: vec3<f32>,
+    max: vec3<f32>,
+}
+
+fn nodeBounds(node: u32) -> Bounds {
+    let n = uniforms.num_shapes;
+    if node >= n - 1u {
+        return Bounds(tree[node].min, tree[node].max);
+    }
+    let base = COUNTERS_NODES + COUNTERS_PER_NODE * node;
+    return Bounds(
+        vec3<f32>(
+            fromOrderedBits(atomicLoad(&counters[base + 1u])),
+            fromOrderedBits(atomicLoad(&counters[base + 2u])),
+            fromOrderedBits(atomicLoad(&counters[base + 3u])),
+        ),
+        vec3<f32>(
+            fromOrderedBits(atomicLoad(&counters[base + 4u])),
+            fromOrderedBits(atomicLoad(&counters[base + 5u])),
+            fromOrderedBits(atomicLoad(&counters[base + 6u])),
+        ),
+    );
+}
+
+@compute @workgroup_size(256)
+fn main_refit(@builtin(global_invocation_id) id: vec3<u32>) {
+    let n = uniforms.num_shapes;
+    if id.x >= n {
+        return;
+    }
+    var node = tree[n - 1u + id.x].parent;
+    while node != NONE {
+        let base = COUNTERS_NODES + COUNTERS_PER_NODE * node;
+        if atomicAdd(&counters[base], 1u) == 0u {
+            return;
+        }
+        let left = nodeBounds(tree[node].left);
+        let right = nodeBounds(tree[node].right);
+        let lo = min(left.min, right.min);
+        let hi = max(left.max, right.max);
+        atomicStore(&counters[base + 1u], orderedBits(lo.x));
+        atomicStore(&counters[base + 2u], orderedBits(lo.y));
+        atomicStore(&counters[base + 3u], orderedBits(lo.z));
+        atomicStore(&counters[base + 4u], orderedBits(hi.x));
+        atomicStore(&counters[base + 5u], orderedBits(hi.y));
+        atomicStore(&counters[base + 6u], orderedBits(hi.z));
+        node = tree[node].parent;
+    }
+}
+
+// Flattening, a node's depth-first position follows from the leaves before it and from how many
+// of its ancestors it sits left under
+@compute @workgroup_size(256)
+fn main_flatten(@builtin(global_invocation_id) id: vec3<u32>) {
+    let n = uniforms.num_shapes;
+    let node = id.x;
+    if node >= 2u * n - 1u {
+        return;
+    }
+    var left_of = 0u;
+    var child = node;
+    var parent = tree[node].parent;
+    while parent != NONE {
+        left_of += select(0u, 1u, tree[parent].left == child);
+        child = parent;
+        parent = tree[parent].parent;
+    }
+    let t = tree[node];
+    let bounds = nodeBounds(node);
+    let index = 2u * t.first + left_of;
+    let count = t.last - t.first + 1u;
+    var entry = index + 1u;
+    if node >= n - 1u {
+        entry = BVH_LEAF_FLAG | t.first;
+        shape_indices[t.first] = t.left;
+    }
+    nodes[index] = ShapeBVHNode(0.5 * (bounds.min + bounds.max), entry, 0.5 * (bounds.max - bounds.min), index + 2u * count - 1u);
+}
@@ -1,3 +1,8 @@
+use bvh::aabb::Bounded;
+use glam::Vec3;
+
+use super::shape::ShapeData;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct LightData {
@@ -49,4 +54,103 @@ impl Default for LightsConfig {
         }
     }
     
-}
\ No newline at end of file
+}
+
+// An emissive shape lighting its surroundings like a spherical light of about its size. A sphere
+// of radius r and radiance L lights a surface d away like a point light of intensity PI r^2 L.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct ShapeLightData {
+    pub color: [f32; 4],
+    pub position: [f32; 3],
+    pub radius: f32,
+    pub range: f32,
+    // Shadow rays towards the light pass through its own shape
+    pub shape: u32,
+    _padding: [u32; 2],
+}
+
+impl ShapeLightData {
+    // None unless the shape emits anything
+    pub fn from_shape(shape: &ShapeData, index: u32) -> Option<Self> {
+        let emitted = shape.emitted();
+        if emitted.max_element() <= 0. {
+            return None;
+        }
+        let aabb = shape.aabb();
+        let min = Vec3::new(aabb.min.x, aabb.min.y, aabb.min.z);
+        let max = Vec3::new(aabb.max.x, aabb.max.y, aabb.max.z);
+        let radius = 0.5 * (max - min).element_sum() / 3.;
+        let color = std::f32::consts::PI * radius * radius * emitted;
+        Some(Self {
+            color: color.extend(0.).into(),
+            position: (0.5 * (min + max)).into(),
+            radius,
+            // Where the falloff leaves 1/256 of the intensity
+            range: radius + (256. * color.max_element()).sqrt(),
+            shape: index,
+            _padding: [0; 2],
+        })
+    }
+
+    // How much the light gives off in all, to keep the brightest ones when there are too many
+    pub fn power(&self) -> f32 {
+        self.color[0].max(self.color[1]).max(self.color[2])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glowing_sphere(radius: f32, color: [f32; 3], intensity: f32) -> ShapeData {
+        let mut shape = ShapeData::new();
+        shape.update_sphere(Vec3::new(1., 2., -1.), radius, [1.; 3], 0., 0.5);
+        shape.set_emissive(color, intensity);
+        shape
+    }
+
+    #[test]
+    fn only_emissive_shapes_are_lights() {
+        assert!(ShapeLightData::from_shape(&ShapeData::new(), 0).is_none());
+        assert!(ShapeLightData::from_shape(&glowing_sphere(0.5, [1., 0.5, 0.], 0.), 0).is_none());
+        assert!(ShapeLightData::from_shape(&glowing_sphere(0.5, [0.; 3], 4.), 0).is_none());
+        assert!(ShapeLightData::from_shape(&glowing_sphere(0.5, [1., 0.5, 0.], 4.), 0).is_some());
+    }
+
+    #[test]
+    fn spheres_light_like_themselves() {
+        let light = ShapeLightData::from_shape(&glowing_sphere(0.5, [1., 0.5, 0.], 4.), 7).unwrap();
+        assert_eq!(light.shape, 7);
+        assert_eq!(light.position, [1., 2., -1.]);
+        assert!((light.radius - 0.5).abs() < 1e-6);
+        let intensity = std::f32::consts::PI * 0.25 * 4.;
+        let expected = [intensity, 0.5 * intensity, 0.];
+        for (channel, expected) in light.color[0..3].iter().zip(expected) {
+            assert!((channel - expected).abs() < 1e-5);
+        }
+        assert!((light.power() - intensity).abs() < 1e-5);
+    }
+
+    #[test]
+    fn range_ends_where_the_light_fades_out() {
+        for (radius, intensity) in [(0.25, 1.), (0.5, 4.), (2., 0.1)] {
+            let light = ShapeLightData::from_shape(&glowing_sphere(radius, [1.; 3], intensity), 0).unwrap();
+            let distance = light.range - light.radius;
+            assert!((light.power() / (distance * distance) - 1. / 256.).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn lights_sit_at_the_middle_of_their_shapes() {
+        let mut shape = ShapeData::new();
+        shape.update_rounded_cone(Vec3::new(-1., 0., 0.), 0.5, Vec3::new(3., 0., 0.), 0.5, [1.; 3], 0., 0.5);
+        shape.set_emissive([1.; 3], 1.);
+        let light = ShapeLightData::from_shape(&shape, 0).unwrap();
+        assert_eq!(light.position, [1., 0., 0.]);
+        // The average half extent of its 5 x 1 x 1 bounds
+        assert!((light.radius - 7. / 6.).abs() < 1e-5);
+        let small = ShapeLightData::from_shape(&glowing_sphere(0.5, [1.; 3], 1.), 0).unwrap();
+        assert!(light.power() > small.power() && light.range > small.range);
+    }
+}
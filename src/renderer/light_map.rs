@@ -12,6 +12,8 @@ pub struct LightMapRenderer {
 
 const TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 
+pub(crate) const SHADER: &str = concat!(include_str!("topology.wgsl"), include_str!("terrain_height.wgsl"), include_str!("tiles.wgsl"), include_str!("shapes.wgsl"), include_str!("light_map.wgsl"));
+
 impl LightMapRenderer {
     pub fn new(resolution: UVec2, device: &wgpu::Device, queue: &wgpu::Queue, uniform_bind_group_layout: &wgpu::BindGroupLayout, sdf_bind_group_layout: &wgpu::BindGroupLayout, lights_bind_group_layout: &wgpu::BindGroupLayout, shapes_bind_group_layout: &wgpu::BindGroupLayout, geometry_bind_group_layout: &wgpu::BindGroupLayout) -> Self {
         let blue_noise_bind_group_layout = device.create_bind_group_layout(
@@ -67,7 +69,7 @@ impl LightMapRenderer {
 
         let lightmap_shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Lightmap shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("light_map.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
         });
 
         let lightmap_pipeline_layout =
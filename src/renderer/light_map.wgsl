@@ -129,6 +129,7 @@ struct Uniforms {
     cursor_size: f32,
     time: f32,
     exposure: f32,
+    topology: u32,
 }
 
 @group(0) @binding(0)
@@ -156,10 +157,14 @@ fn main_vert(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
 
 // Fragment shader
 
-@group(1) @binding(0)
-var t_sdf: texture_2d<f32>;
-@group(1) @binding(1)
-var s_sdf: sampler;
+struct MaterialData {
+    albedo_metallic: vec4<f32>,
+    emissive_roughness: vec4<f32>,
+    // Width, profile
+    bevel: vec4<f32>,
+}
+@group(1) @binding(4)
+var<uniform> palette: array<MaterialData, 16>;
 
 struct LightData {
     color: vec4<f32>,
@@ -180,12 +185,6 @@ struct LightsConfig {
 @group(2) @binding(1)
 var<uniform> lightsConfig: LightsConfig;
 
-struct ShapeData {
-    data0: vec4<u32>,
-    data1: vec4<f32>,
-    data2: vec4<f32>,
-};
-
 struct ShapesBuffer {
     shapes: array<ShapeData>,
 };
@@ -194,9 +193,9 @@ var<storage, read> shapesBuffer: ShapesBuffer;
 
 struct ShapeBVHNode {
     aabb_pos: vec3<f32>,
-    entry: i32,
+    entry: u32,
     aabb_rad: vec3<f32>,
-    exit: i32,
+    exit: u32,
 }
 
 struct ShapeBVHNodesBuffer {
@@ -208,10 +207,43 @@ var<storage, read> bvhBuffer: ShapeBVHNodesBuffer;
 struct ShapesConfig {
   numShapes: u32,
   numBvhNodes: u32,
+  numShapeLights: u32,
 };
 @group(3) @binding(2)
 var<uniform> shapesConfig: ShapesConfig;
 
+// Shapes in BVH leaf order
+struct ShapeIndicesBuffer {
+    indices: array<u32>,
+};
+@group(3) @binding(3)
+var<storage, read> shapeIndicesBuffer: ShapeIndicesBuffer;
+
+// Emissive shapes lit like spherical lights, see ShapeLightData in light.rs
+struct ShapeLightData {
+    color: vec4<f32>,
+    position: vec3<f32>,
+    radius: f32,
+    range: f32,
+    shape: u32,
+};
+
+struct ShapeLightsBuffer {
+    lights: array<ShapeLightData>,
+};
+@group(3) @binding(4)
+var<storage, read> shapeLightsBuffer: ShapeLightsBuffer;
+
+// Leaves hold shapes first..=last of the shape indices in `entry`, packed as
+// BVH_LEAF_FLAG | (last - first) << BVH_LEAF_COUNT_SHIFT | first. Same as in shape.rs.
+const BVH_LEAF_FLAG: u32 = 0x80000000u;
+const BVH_LEAF_COUNT_SHIFT: u32 = 24u;
+
+fn bvhLeafShapes(entry: u32) -> vec2<u32> {
+    let first = entry & ((1u << BVH_LEAF_COUNT_SHIFT) - 1u);
+    return vec2<u32>(first, first + ((entry & ~BVH_LEAF_FLAG) >> BVH_LEAF_COUNT_SHIFT));
+}
+
 @group(4) @binding(0)
 var t_diffuse: texture_2d<f32>;
 
@@ -221,18 +253,22 @@ var t_normals_metallic_roughness: texture_2d<f32>;
 @group(4) @binding(2)
 var t_depth: texture_2d<f32>;
 
+@group(4) @binding(3)
+var t_emissive: texture_2d<f32>;
+
+// Index of the shape seen plus one, 0 on the terrain
+@group(4) @binding(4)
+var t_shape: texture_2d<u32>;
+
 @group(5) @binding(0)
 var t_blue_noise: texture_2d<f32>;
 
-fn unpackSdf(v: f32) -> f32 {
-    return v;
-}
-
-fn sceneDist(world_pos: vec2<f32>) -> f32 {
-    var uv = world_pos * uniforms.inv_world_size;
+fn sceneMaterial(world_pos: vec2<f32>) -> MaterialData {
+    var uv = world_pos * terrain.inv_window_size;
     uv.y = -uv.y;
     uv = uv + 0.5;
-    return unpackSdf(textureSample(t_sdf, s_sdf, uv).r);
+    let texel = vec2<i32>(floor(fract(uv) * vec2<f32>(textureDimensions(t_sdf))));
+    return palette[min(u32(textureLoad(t_sdf, texel, 0).g + 0.5), 15u)];
 }
 
 fn hardShadow(ro: vec2<f32>, rd: vec2<f32>, tmax: f32, radius: f32) -> f32 {
@@ -279,17 +315,6 @@ fn softShadow(ro: vec2<f32>, rd: vec2<f32>, tmax: f32, radius: f32) -> f32 {
     return 0.;
 }
 
-fn wrap(p: vec2<f32>) -> vec2<f32> 
-{
-    let s = ceil(abs(p * uniforms.inv_world_size)) + 0.5;
-    return (p + s * uniforms.world_size) % uniforms.world_size - 0.5 * uniforms.world_size;
-}
-
-fn wrap3(p: vec3<f32>) -> vec3<f32>
-{
-    return vec3<f32>(wrap(p.xy), p.z);
-}
-
 fn rotation(angle: f32) -> mat2x2<f32> {
     let cs = cos(angle);
     let sn = sin(angle);
@@ -352,23 +377,48 @@ fn fresnelSchlick(cosTheta: f32, F0: vec3<f32>) -> vec3<f32>
     return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
 }
 
-fn traceTerrain(ro: vec2<f32>, rd: vec2<f32>, tmax: f32) -> f32 {
-    var t: f32 = 0.;
-    for(var i: i32 = 0; i < 32; i = i + 1) {
-        let h = max(sceneDist(ro + t * rd), 0.);
-        t += h;
-        if( h < .001) {
-            return t;
-        }            
+// Top of the terrain at `world_pos`, see `terrainTop`
+fn sceneTop(world_pos: vec2<f32>) -> f32 {
+    var uv = world_pos * terrain.inv_window_size;
+    uv.y = -uv.y;
+    uv = uv + 0.5;
+    return terrainTop(textureSampleLevel(t_sdf, s_sdf, uv, 0.).b);
+}
+
+// Horizontal distance below which the terrain trace gives up on exact steps
+const MIN_TERRAIN_STEP: f32 = 0.05;
+
+// 1 if the ray from `ro` along `rd` clears the terrain height field up to `tmax`, 0 otherwise.
+// Open space is sphere traced with the 2D distance. Over the terrain the steps are kept short
+// enough that the bevel can't rise above the ray within them, and never leave the terrain they
+// start over, another piece of it could begin right behind the edge. Rays that run out of steps
+// are not shadowed, long grazing rays would otherwise leave dark streaks.
+fn traceTerrain(ro: vec3<f32>, rd: vec3<f32>, tmax: f32) -> f32 {
+    let invLengthXY = 1. / max(length(rd.xy), 1e-4);
+    var t: f32 = 0.05;
+    for(var i: i32 = 0; i < 96; i = i + 1) {
+        let p = ro + t * rd;
+        let xy = wrap(p.xy);
+        let dist = sceneDist(xy);
+        var h = dist;
+        if (dist <= 0.) {
+            let bevel = sceneMaterial(xy).bevel;
+            let clearance = p.z - GROUND_Z - terrainHeight(bevel, dist, sceneTop(xy));
+            if (clearance < -.01) {
+                return 0.;
+            }
+            // Steepest rise of the bevel, vertical walls only rise at the edge
+            let slope = TERRAIN_HEIGHT / max(bevel.x, 1.);
+            h = min(clearance / slope, -dist);
+        }
+        t += max(h, MIN_TERRAIN_STEP) * invLengthXY;
         if(t > tmax) {
-            return tmax;
+            return 1.;
         }
     }
-    return t;
+    return 1.;
 }
 
-const kMaxRayDistance: f32 = 1e20;
-
 fn iAABB(ro: vec3<f32>, inv_rd: vec3<f32>, aabb_rad: vec3<f32>, tmax: f32) -> bool {
     let n = inv_rd*ro;
     let k = abs(inv_rd)*aabb_rad;
@@ -381,77 +431,6 @@ fn iAABB(ro: vec3<f32>, inv_rd: vec3<f32>, aabb_rad: vec3<f32>, tmax: f32) -> bo
     return tfar > max(tnear, 0.) && tnear < tmax;
 }
 
-fn iSphere(ro: vec3<f32>, rd: vec3<f32>, radius: f32) -> f32 {
-    let b = dot(rd, ro);
-    let c = dot(ro, ro) - (radius * radius);
-    let h = b*b - c;
-    if (h < 0.0) {
-        return kMaxRayDistance;
-    }
-    return -b - sqrt(h);
-}
-
-fn nSphere(pos: vec3<f32>) -> vec3<f32> {
-    return normalize(pos);
-}
-
-// https://www.shadertoy.com/view/MlKfzm
-fn iRoundedCone(ro: vec3<f32>, rd: vec3<f32>, 
-                  pa: vec3<f32>, pb: vec3<f32>,
-                  ra: f32, rb: f32) -> vec4<f32> {
-    let ba = pb - pa;
-	let oa = ro - pa;
-	let ob = ro - pb;
-    let rr = ra - rb;
-    let m0 = dot(ba,ba);
-    let m1 = dot(ba,oa);
-    let m2 = dot(ba,rd);
-    let m3 = dot(rd,oa);
-    let m5 = dot(oa,oa);
-	let m6 = dot(ob,rd);
-    let m7 = dot(ob,ob);
-    
-    let d2 = m0-rr*rr;
-    
-	let k2 = d2    - m2*m2;
-    let k1 = d2*m3 - m1*m2 + m2*rr*ra;
-    let k0 = d2*m5 - m1*m1 + m1*rr*ra*2.0 - m0*ra*ra;
-    
-	let h = k1*k1 - k0*k2;
-	if(h < 0.0) {
-        return vec4<f32>(kMaxRayDistance);
-    }
-    var t = (-sqrt(h)-k1)/k2;
-
-    let y = m1 - ra*rr + t*m2;
-    if( y>0.0 && y<d2 ) 
-    {
-        return vec4<f32>(t, normalize( d2*(oa + t*rd)-ba*y) );
-    }
-
-    let h1 = m3*m3 - m5 + ra*ra;
-    let h2 = m6*m6 - m7 + rb*rb;
-    if( max(h1,h2)<0.0 ) {
-        return vec4<f32>(kMaxRayDistance);
-    }
-    
-    var r = vec4<f32>(kMaxRayDistance);
-    if( h1>0.0 )
-    {        
-    	t = -m3 - sqrt( h1 );
-        r = vec4<f32>( t, (oa+t*rd)/ra );
-    }
-	if( h2>0.0 )
-    {
-    	t = -m6 - sqrt( h2 );
-        if( t<r.x ) {
-            r = vec4<f32>( t, (ob+t*rd)/rb );
-        }        
-    }
-    
-    return r;
-}
-
 struct RayTraceResult {
     t: f32,
     normal: vec3<f32>,
@@ -460,38 +439,17 @@ struct RayTraceResult {
 
 fn traceRayShape(shapeIndex: u32, ro: vec3<f32>, rd: vec3<f32>, result: RayTraceResult) -> RayTraceResult {
     let s = shapesBuffer.shapes[shapeIndex];
-    if (s.data0[0] == 0u) {
-        let oro = wrap3(ro - s.data1.xyz);
-        let t = iSphere(oro, rd, s.data1.w);
-        if (t > 0. && t < result.t) {
-            return RayTraceResult(t, nSphere(oro + t * rd), shapeIndex);
-        }
-    } else if (s.data0[0] == 1u) {
-        let oro = wrap3(ro - s.data1.xyz);
-        let tnor = iRoundedCone(oro, rd, vec3<f32>(0.), s.data2.xyz- s.data1.xyz, s.data1.w, s.data2.w);
-        if (tnor.x > 0. && tnor.x < result.t) {
-            return RayTraceResult(tnor.x, tnor.yzw, shapeIndex);
-        }
+    let tnor = intersectShape(s, wrap3(ro - s.data1.xyz), rd);
+    if (tnor.x > 0. && tnor.x < result.t) {
+        return RayTraceResult(tnor.x, tnor.yzw, shapeIndex);
     }
     return result;
 } 
 
 fn traceOccShape(shapeIndex: u32, ro: vec3<f32>, rd: vec3<f32>, tmax: f32) -> bool {
     let s = shapesBuffer.shapes[shapeIndex];
-    if (s.data0[0] == 0u) {
-        let oro = wrap3(ro - s.data1.xyz);
-        let t = iSphere(oro, rd, s.data1.w);
-        if (t > 0. && t < tmax) {
-            return true;
-        }
-    } else if (s.data0[0] == 1u) {
-        let oro = wrap3(ro - s.data1.xyz);
-        let t = iRoundedCone(oro, rd, vec3<f32>(0.), s.data2.xyz- s.data1.xyz, s.data1.w, s.data2.w).x;
-        if (t > 0. && t < tmax) {
-            return true;
-        }
-    }
-    return false;
+    let t = intersectShape(s, wrap3(ro - s.data1.xyz), rd).x;
+    return t > 0. && t < tmax;
 } 
 
 fn traceRayBVH(ro: vec3<f32>, rd: vec3<f32>, tmax: f32) -> RayTraceResult {
@@ -500,42 +458,48 @@ fn traceRayBVH(ro: vec3<f32>, rd: vec3<f32>, tmax: f32) -> RayTraceResult {
         vec3<f32>(.0, .0, .0),
         shapesConfig.numShapes,
     );
-    var nodeIndex = 0;
-    let maxLength = i32(shapesConfig.numBvhNodes);
+    var nodeIndex = 0u;
     let inv_rd = 1.0/rd;
 
-    while (nodeIndex < maxLength) {
+    while (nodeIndex < shapesConfig.numBvhNodes) {
         let node = bvhBuffer.nodes[nodeIndex];
 
-        if (node.entry < 0) {
-            result = traceRayShape(u32(-node.entry), ro, rd, result);
+        if (!iAABB(wrap3(ro - node.aabb_pos.xyz), inv_rd, node.aabb_rad.xyz, result.t)) {
             nodeIndex = node.exit;
-        } else if (iAABB(wrap3(ro - node.aabb_pos.xyz), inv_rd, node.aabb_rad.xyz, result.t)) {
-            nodeIndex = node.entry;
-        } else {
+        } else if ((node.entry & BVH_LEAF_FLAG) != 0u) {
+            let shapes = bvhLeafShapes(node.entry);
+            for (var i = shapes.x; i <= shapes.y; i++) {
+                result = traceRayShape(shapeIndicesBuffer.indices[i], ro, rd, result);
+            }
             nodeIndex = node.exit;
+        } else {
+            nodeIndex = node.entry;
         }
     }
     return result;
 }
 
-fn traceOccBVH(ro: vec3<f32>, rd: vec3<f32>, tmax: f32) -> f32 {
-    var nodeIndex = 0;
-    let maxLength = i32(shapesConfig.numBvhNodes);
+// `skipShape` is a shape the ray passes through, numShapes for none
+fn traceOccBVH(ro: vec3<f32>, rd: vec3<f32>, tmax: f32, skipShape: u32) -> f32 {
+    var nodeIndex = 0u;
     let inv_rd = 1.0/rd;
 
-    while (nodeIndex < maxLength) {
+    while (nodeIndex < shapesConfig.numBvhNodes) {
         let node = bvhBuffer.nodes[nodeIndex];
 
-        if (node.entry < 0) {
-            if (traceOccShape(u32(-node.entry), ro, rd, tmax)) {
-                return 0.;
+        if (!iAABB(wrap3(ro - node.aabb_pos.xyz), inv_rd, node.aabb_rad.xyz, tmax)) {
+            nodeIndex = node.exit;
+        } else if ((node.entry & BVH_LEAF_FLAG) != 0u) {
+            let shapes = bvhLeafShapes(node.entry);
+            for (var i = shapes.x; i <= shapes.y; i++) {
+                let shapeIndex = shapeIndicesBuffer.indices[i];
+                if (shapeIndex != skipShape && traceOccShape(shapeIndex, ro, rd, tmax)) {
+                    return 0.;
+                }
             }
             nodeIndex = node.exit;
-        } else if (iAABB(wrap3(ro - node.aabb_pos.xyz), inv_rd, node.aabb_rad.xyz, tmax)) {
-            nodeIndex = node.entry;
         } else {
-            nodeIndex = node.exit;
+            nodeIndex = node.entry;
         }
     }
     return 1.;
@@ -592,11 +556,70 @@ fn decode_normal(po: vec2<f32>) -> vec3<f32> {
     return normalize(n);
 }
 
+// Light reaching `WorldPos` from a spherical light of `radius` at offset `l`, shadow rays pass
+// through `skipShape`
+fn sphereLight(l: vec3<f32>, color: vec3<f32>, radius: f32, range: f32, skipShape: u32, WorldPos: vec3<f32>, N: vec3<f32>, RD: vec3<f32>, F0: vec3<f32>, metallic: f32, roughness: f32, rand: vec4<f32>) -> vec3<f32> {
+    let r = reflect(RD, N);
+    let centerToRay = (dot(l, r) * r) - l;
+    let closestPoint = l + centerToRay * clamp(radius / length(centerToRay), 0., 1.);
+    let distance = length(closestPoint);
+    let L = closestPoint * (1. / distance);
+    let NdotL = dot(N, L);
+    if (NdotL <= 0.) {
+        return vec3<f32>(0.);
+    }
+
+    let effectiveRange = max(range - radius, 0.);
+    if (distance > effectiveRange) {
+        return vec3<f32>(0.);
+    }
+    let falloff = pow(clamp(1. - pow(distance/effectiveRange, 4.), 0., 1.), 2.) / ((distance * distance) + 1.);
+    var shadow = 1.;
+    if (distance > radius) {
+        let distanceToCenter = length(l);
+        let invDistanceToCenter = 1. / distanceToCenter;
+        let w = l * invDistanceToCenter;
+
+        let toWorld = constructONBfrisvad(w);
+        var q = radius * invDistanceToCenter;
+        q = sqrt(1.0 - q * q);
+        let theta = acos(1. - rand.x + rand.x * q);
+        let phi = TwoPI * rand.y;
+        let wp = toWorld * vec3<f32>(sin(theta) * cos(phi), cos(theta), sin(theta) * sin(phi));
+        let tmax = min(iSphere(-l, wp, radius), q * distanceToCenter);
+
+        shadow = traceTerrain(WorldPos, wp, tmax);
+        if (shadow == 0.) {
+            return vec3<f32>(0.);
+        }
+        shadow = shadow * traceOccBVH(WorldPos, wp, tmax, skipShape);
+    }
+    if (shadow == 0.) {
+        return vec3<f32>(0.);
+    }
+    let radiance = color * shadow * falloff;
+
+    let H = normalize(-RD + L);
+
+    // cook-torrance brdf
+    let NDF = DistributionGGX(N, H, roughness, distance, radius);
+    let G   = GeometrySmith(N, -RD, L, roughness);
+    let F   = fresnelSchlick(max(dot(H, -RD), 0.0), F0);
+
+    let kS = F;
+    let kD = (vec3<f32>(1., 1., 1.) - kS) * (1.0 - metallic);
+
+    let numerator    = NDF * G * F;
+    let denominator  = max(dot(N, -RD), 0.) * 4. * NdotL + 0.0001;
+    let specular     = numerator / denominator;
+
+    return (kD  / PI + specular) * radiance * NdotL;
+}
+
 @fragment
 fn main_frag_pbr(in: VertexOutput) -> @location(0) vec4<f32> {
     let offset = vec3<f32>(0.5 * uniforms.pixel_size.xy, 0.);
 
-    let dist = sceneDist(in.world_pos);
     let RO = vec3<f32>(in.world_pos, 2.0);
     let RD = vec3<f32>(0., 0., -1.);
 
@@ -608,91 +631,36 @@ fn main_frag_pbr(in: VertexOutput) -> @location(0) vec4<f32> {
     let roughness = normals_metallic_roughness.w;
     let N = decode_normal(normals_metallic_roughness.xy);
     let depth = textureLoad(t_depth, texel, 0).x;
-    let WorldPos = vec3<f32>(in.world_pos, -4. * depth + 2.);
+    let WorldPos = vec3<f32>(in.world_pos, depth_to_world(depth));
 
     let ao = 1.0;
 
     let F0 = mix(vec3<f32>(.04, .04, .04), albedo, metallic);
-	           
+    let rand = blue_noise(in.position.xy);
+
     // reflectance equation
     var Lo = vec3<f32>(0., 0., 0.);
 
-    let NdotNegRDx4 = max(dot(N, -RD), 0.) * 4.;
-
     for (var i = 0u; i < lightsConfig.numLights; i = i + 1u) {
         let light = lightsBuffer.lights[i];
-        // calculate per-light radiance
         let l = vec3<f32>(wrap(light.position - WorldPos.xy), 0. - WorldPos.z);
-
-        let r = reflect(RD, N);
-        let centerToRay = (dot(l, r) * r) - l;
-        let closestPoint = l + centerToRay * clamp(light.radius / length(centerToRay), 0., 1.);
-        let distance = length(closestPoint);
-        let L = closestPoint * (1. / distance);
-        let NdotL = dot(N, L);
-        if (NdotL <= 0.) {
-            continue;
-        }
-
-        let effectiveRange = max(light.range - light.radius, 0.);
-        if (distance > effectiveRange) {
-            continue;
-        }
-        let falloff = pow(clamp(1. - pow(distance/effectiveRange, 4.), 0., 1.), 2.) / ((distance * distance) + 1.);
-        var shadow = 1.;
-        if (distance > light.radius) {
-            let distanceToCenter = length(l);
-            let invDistanceToCenter = 1. / distanceToCenter;
-            let w = l * invDistanceToCenter;
-
-            let toWorld = constructONBfrisvad(w);
-            let rand = blue_noise(in.position.xy);
-            var q = light.radius * invDistanceToCenter;
-            q = sqrt(1.0 - q * q);
-            let theta = acos(1. - rand.x + rand.x * q);
-            let phi = TwoPI * rand.y;
-            let wp = toWorld * vec3<f32>(sin(theta) * cos(phi), cos(theta), sin(theta) * sin(phi));
-            let tmax = min(iSphere(-l, wp, light.radius), q * distanceToCenter);
-
-            let distanceToTerrainFromLight = traceTerrain(wrap(WorldPos.xy + tmax * wp.xy), -wp.xy, tmax);
-            shadow = mix(
-                smoothstep(10., 0., tmax - distanceToTerrainFromLight), // Inside terrain
-                step(tmax, distanceToTerrainFromLight), 
-                step(0., dist)
-            );
-            if (shadow == 0.) {
-                continue;
-            }
-            if (dist > 0.) {
-                shadow = shadow * traceOccBVH(WorldPos, wp, tmax);
-            }
-        }
-        if (shadow == 0.) {
+        Lo = Lo + sphereLight(l, light.color.rgb, light.radius, light.range, shapesConfig.numShapes, WorldPos, N, RD, F0, metallic, roughness, rand);
+    }
+    // An emissive shape already adds its own emission, it isn't lit by it as well
+    let shape = textureLoad(t_shape, texel, 0).x;
+    for (var i = 0u; i < shapesConfig.numShapeLights; i = i + 1u) {
+        let light = shapeLightsBuffer.lights[i];
+        if light.shape + 1u == shape {
             continue;
         }
-        let radiance = light.color.rgb * shadow * falloff;
-
-        let H = normalize(-RD + L);
-        
-        // cook-torrance brdf
-        let NDF = DistributionGGX(N, H, roughness, distance, light.radius);
-        let G   = GeometrySmith(N, -RD, L, roughness);      
-        let F   = fresnelSchlick(max(dot(H, -RD), 0.0), F0);       
-        
-        let kS = F;
-        let kD = (vec3<f32>(1., 1., 1.) - kS) * (1.0 - metallic);
-        
-        let numerator    = NDF * G * F;
-        let denominator  = NdotNegRDx4 * NdotL + 0.0001;
-        let specular     = numerator / denominator;  
-            
-        // add to outgoing radiance Lo
-        Lo = Lo + (kD  / PI + specular) * radiance * NdotL; 
+        let l = vec3<f32>(wrap(light.position.xy - WorldPos.xy), light.position.z - WorldPos.z);
+        Lo = Lo + sphereLight(l, light.color.rgb, light.radius, light.range, light.shape, WorldPos, N, RD, F0, metallic, roughness, rand);
     }
 
     let ambient = vec3<f32>(.0, .0, .0) * ao;
     var color: vec3<f32> = ambient + Lo;
     color = color * albedo;
-	
+    color = color + textureLoad(t_emissive, texel, 0).rgb;
+
     return vec4<f32>(color, 1.0);
 }
@@ -1,10 +1,14 @@
-mod geometry;
-mod light_map;
+pub(crate) mod geometry;
+pub(crate) mod light_map;
 pub mod texture;
 mod taa;
 mod blit_sampler;
 pub mod light;
 pub mod shape;
+pub mod scene;
+mod shape_bvh;
+mod lbvh;
+mod intersect;
 
 use glam::*;
 use wgpu::PipelineCompilationOptions;
@@ -13,14 +17,21 @@ use wgpu::util::DeviceExt;
 
 use light_map::LightMapRenderer;
 
-use crate::renderer::light::{LightData, LightsConfig};
+use crate::renderer::light::{LightData, LightsConfig, ShapeLightData};
+use crate::renderer::scene::Scene;
 use crate::renderer::shape::{ShapeBVHNode, ShapeData, ShapesConfig};
+use crate::renderer::shape_bvh::ShapeBVH;
+use crate::renderer::lbvh::LBVHBuilder;
+use crate::renderer::intersect::intersect_shape;
 use crate::sdf::SDF;
+use crate::sdf::tiles::Topology;
 
 use self::geometry::GeometryRenderer;
 
 pub const MAX_LIGHTS: usize = 1024;
 pub const MAX_SHAPES: usize = 4096;
+// Emissive shapes that light their surroundings, the brightest when there are more
+pub const MAX_SHAPE_LIGHTS: usize = 64;
 const NUM_SUBPIXEL_JITTER_SAMPLES: usize = 16;
 
 fn halton(base: usize, index: usize) -> f32 {
@@ -43,6 +54,14 @@ pub enum Upsampler {
     BLIT,
 }
 
+// Where the shape BVH is built, the CPU tree is refitted between rebuilds while the GPU one is
+// built from scratch whenever shapes change
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BvhBuilder {
+    Cpu,
+    Gpu,
+}
+
 enum UpsamplerCell {
     TAA(taa::TAA),
     BLIT(blit_sampler::BLIT),
@@ -84,7 +103,7 @@ struct Uniforms {
     pub cursor_size: f32,
     pub time: f32,
     pub exposure: f32,
-    pub dummy: f32,
+    pub topology: u32,
 }
 
 impl Default for Uniforms {
@@ -100,7 +119,7 @@ impl Default for Uniforms {
             cursor_size: 0.0,
             time: 0.0,
             exposure: 1.0,
-            dummy: 0.0,
+            topology: 0,
         }
     }
 }
@@ -114,8 +133,14 @@ pub struct Renderer {
     lights_config_buffer: wgpu::Buffer,
     lights_bind_group: wgpu::BindGroup,
     shapes_buffer: wgpu::Buffer,
-    bvh: Vec<ShapeBVHNode>,
+    bvh: ShapeBVH,
     bvh_buffer: wgpu::Buffer,
+    shape_indices_buffer: wgpu::Buffer,
+    shape_lights_buffer: wgpu::Buffer,
+    lbvh: LBVHBuilder,
+    // Builder of the tree in `bvh_buffer`
+    bvh_built_by: BvhBuilder,
+    pub bvh_builder: BvhBuilder,
     shapes_config_buffer: wgpu::Buffer,
     shapes_bind_group: wgpu::BindGroup,
     geometry_renderer: GeometryRenderer,
@@ -143,6 +168,8 @@ pub struct Renderer {
 
 const COLOR_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
 
+pub(crate) const SHADER: &str = concat!(include_str!("topology.wgsl"), include_str!("tiles.wgsl"), include_str!("renderer.wgsl"));
+
 impl Renderer {
     pub fn new(render_resolution: UVec2, output_resolution: UVec2, world_size: Vec2, device: &wgpu::Device, queue: &wgpu::Queue, sdf: &SDF, surface_format: &wgpu::TextureFormat) -> Self {
         let mut view_size = Vec2::new(world_size.x / 4., world_size.y / 4.);
@@ -152,6 +179,7 @@ impl Renderer {
         uniforms.view_size = [view_size.x, view_size.y];
         uniforms.world_size = [world_size.x, world_size.y];
         uniforms.inv_world_size = [1.0 / world_size.x, 1.0 / world_size.y];
+        uniforms.topology = sdf.topology().index();
         uniforms.pixel_size = [view_size.x / render_resolution.x as f32, view_size.y / render_resolution.y as f32];
 
         let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -244,8 +272,8 @@ impl Renderer {
         });
 
         let initial_shapes_data = vec![ShapeData::default(); MAX_SHAPES];
-        let initial_bvh_data = vec![ShapeBVHNode::default(); 4 * MAX_SHAPES];
-        let shapes_config = ShapesConfig { num_shapes: 0, num_bvh_nodes: 0, };
+        let initial_bvh_data = vec![ShapeBVHNode::default(); 2 * MAX_SHAPES];
+        let shapes_config = ShapesConfig { num_shapes: 0, num_bvh_nodes: 0, num_shape_lights: 0, };
 
         let shapes_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
@@ -257,6 +285,17 @@ impl Renderer {
             contents: bytemuck::cast_slice(&initial_bvh_data),
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST
         });
+        let shape_indices_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&vec![0u32; MAX_SHAPES]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST
+        });
+        let shape_lights_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: (MAX_SHAPE_LIGHTS * std::mem::size_of::<ShapeLightData>()) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
         let shapes_config_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
@@ -297,6 +336,26 @@ impl Renderer {
                         min_binding_size: None,
                     },
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    count: None,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    count: None,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                },
             ]
         });
 
@@ -315,6 +374,14 @@ impl Renderer {
                     binding: 2,
                     resource: shapes_config_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: shape_indices_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: shape_lights_buffer.as_entire_binding(),
+                },
             ],
             label: None,
         });
@@ -354,6 +421,26 @@ impl Renderer {
                         },
                         count: None,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Uint,
+                        },
+                        count: None,
+                    },
                 ],
                 label: Some("geometry_bind_group_layout"),
             }
@@ -375,6 +462,14 @@ impl Renderer {
                         binding: 2,
                         resource: wgpu::BindingResource::TextureView(&geometry_renderer.depth.view),
                     },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&geometry_renderer.emissive.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(&geometry_renderer.shape.view),
+                    },
                 ],
                 label: Some("geometry_bind_group"),
             }
@@ -546,7 +641,7 @@ impl Renderer {
 
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: None,
-            source: wgpu::ShaderSource::Wgsl(include_str!("renderer.wgsl").into()),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
         });
 
         let render_pipeline_layout =
@@ -589,6 +684,8 @@ impl Renderer {
             multiview: None,
         });
 
+        let lbvh = LBVHBuilder::new(device, &shapes_buffer, &bvh_buffer, &shape_indices_buffer);
+
         let start_time = Instant::now();
         let position = Vec2::new(0., 0.);
 
@@ -601,8 +698,13 @@ impl Renderer {
             lights_config_buffer,
             lights_bind_group,
             shapes_buffer,
-            bvh: vec![],
+            bvh: ShapeBVH::new(),
+            lbvh,
             bvh_buffer,
+            shape_indices_buffer,
+            shape_lights_buffer,
+            bvh_built_by: BvhBuilder::Cpu,
+            bvh_builder: BvhBuilder::Cpu,
             shapes_config_buffer,
             shapes_bind_group,
             geometry_renderer,
@@ -647,6 +749,14 @@ impl Renderer {
                         binding: 2,
                         resource: wgpu::BindingResource::TextureView(&self.geometry_renderer.depth.view),
                     },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&self.geometry_renderer.emissive.view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: wgpu::BindingResource::TextureView(&self.geometry_renderer.shape.view),
+                    },
                 ],
                 label: Some("geometry_bind_group"),
             }
@@ -699,6 +809,14 @@ impl Renderer {
         self.view_size.x = self.view_size.y * output_resolution.x as f32 / output_resolution.y as f32;
     }
 
+    // The world ends or wraps around at `world_size` depending on `topology`, shaders pick it
+    // up with the next uniform update
+    pub fn set_world(&mut self, world_size: Vec2, topology: Topology) {
+        self.uniforms.world_size = [world_size.x, world_size.y];
+        self.uniforms.inv_world_size = [1.0 / world_size.x, 1.0 / world_size.y];
+        self.uniforms.topology = topology.index();
+    }
+
     pub fn update_uniforms(&mut self, mouse: Vec2, cursor_size: f32, exposure: f32) {
         self.uniforms.translate = [self.position.x, self.position.y];
         self.uniforms.view_size = [self.view_size.x, self.view_size.y];
@@ -718,21 +836,73 @@ impl Renderer {
         queue.write_buffer(&self.lights_config_buffer, 0, bytemuck::cast_slice(&[LightsConfig { num_lights: lights.len() as u32 }]));
     }
 
-    pub fn update_shapes(&mut self, queue: &wgpu::Queue, shapes: &mut Vec<ShapeData>) {
-        self.bvh = bvh::bvh::BVH::build(shapes).flatten_custom(&|aabb, entry, exit, shape| ShapeBVHNode {
-            aabb_pos: ((aabb.min + aabb.max) * 0.5).into(),
-            entry: if entry == u32::max_value() { -(shape as i32) } else { entry as i32 },
-            aabb_rad: ((aabb.max - aabb.min) * 0.5).into(),
-            exit: exit as i32,
-        });
-        queue.write_buffer(&self.bvh_buffer, 0, bytemuck::cast_slice(&self.bvh));
-        queue.write_buffer(&self.shapes_buffer, 0, bytemuck::cast_slice(shapes));
-        queue.write_buffer(&self.shapes_config_buffer, 0, bytemuck::cast_slice(&[ShapesConfig { num_shapes: shapes.len() as u32, num_bvh_nodes: self.bvh.len() as u32 }]));
+    // Uploads the shapes that changed since the last call. The CPU BVH is refitted to moved shapes
+    // and only rebuilt when shapes came or went or the refitted tree got too loose, the GPU one is
+    // recorded into `encoder` and rebuilt on every change. The brightest emissive shapes become the
+    // shape lights.
+    pub fn update_shapes(&mut self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, scene: &mut Scene) {
+        let switched = self.bvh_builder != self.bvh_built_by;
+        if !scene.is_changed() && !switched {
+            return;
+        }
+        let dirty_shapes = scene.dirty_ranges();
+        for range in dirty_shapes.iter().cloned() {
+            let offset = (range.start * std::mem::size_of::<ShapeData>()) as u64;
+            queue.write_buffer(&self.shapes_buffer, offset, bytemuck::cast_slice(&scene.shapes()[range]));
+        }
+        let num_bvh_nodes = match self.bvh_builder {
+            BvhBuilder::Cpu => {
+                if switched || scene.is_restructured() || !self.bvh.refit(scene.shapes(), &dirty_shapes) {
+                    self.bvh.build(scene.shapes_mut());
+                    queue.write_buffer(&self.shape_indices_buffer, 0, bytemuck::cast_slice(self.bvh.indices()));
+                }
+                if let Some(range) = self.bvh.take_dirty() {
+                    let offset = (range.start * std::mem::size_of::<ShapeBVHNode>()) as u64;
+                    queue.write_buffer(&self.bvh_buffer, offset, bytemuck::cast_slice(&self.bvh.nodes()[range]));
+                }
+                self.bvh.nodes().len() as u32
+            }
+            BvhBuilder::Gpu => self.lbvh.build(queue, encoder, scene.len() as u32),
+        };
+        self.bvh_built_by = self.bvh_builder;
+        let mut shape_lights: Vec<ShapeLightData> = scene.shapes()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, shape)| ShapeLightData::from_shape(shape, i as u32))
+            .collect();
+        shape_lights.sort_unstable_by(|a, b| b.power().total_cmp(&a.power()));
+        shape_lights.truncate(MAX_SHAPE_LIGHTS);
+        if !shape_lights.is_empty() {
+            queue.write_buffer(&self.shape_lights_buffer, 0, bytemuck::cast_slice(&shape_lights));
+        }
+        queue.write_buffer(&self.shapes_config_buffer, 0, bytemuck::cast_slice(&[ShapesConfig {
+            num_shapes: scene.len() as u32,
+            num_bvh_nodes,
+            num_shape_lights: shape_lights.len() as u32,
+        }]));
+        scene.clear_changes();
+    }
+
+    // Closest shape hit before `tmax` by a ray from `ro` along the normalized `rd`, as a position
+    // in the packed shapes. `wrap` maps offsets into the world like wrap3. Goes through the CPU BVH
+    // while it matches `scene` and tests every shape otherwise.
+    pub fn cast_ray(&self, scene: &Scene, ro: Vec3, rd: Vec3, tmax: f32, wrap: impl Fn(Vec3) -> Vec3) -> Option<(f32, usize)> {
+        if scene.is_empty() {
+            return None;
+        }
+        let shapes = scene.shapes();
+        let hit = |shape: usize| intersect_shape(&shapes[shape], wrap(ro - shapes[shape].position()), rd);
+        if self.bvh_built_by == BvhBuilder::Cpu && !scene.is_changed() {
+            return self.bvh.trace_ray(ro, rd, tmax, &wrap, hit);
+        }
+        (0..shapes.len())
+            .filter_map(|shape| hit(shape).filter(|&t| t > 0. && t < tmax).map(|t| (t, shape)))
+            .min_by(|a, b| a.0.total_cmp(&b.0))
     }
 
-    pub fn render(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, sdf: &SDF, shapes: &Vec<ShapeData>, view: &wgpu::TextureView) {
+    pub fn render(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, sdf: &SDF, scene: &Scene, view: &wgpu::TextureView) {
         queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[self.uniforms]));
-        self.geometry_renderer.render(device, encoder, &self.uniform_bind_group, sdf.output_bind_group(), &self.shapes_bind_group, shapes);
+        self.geometry_renderer.render(device, encoder, &self.uniform_bind_group, sdf.output_bind_group(), &self.shapes_bind_group, scene.shapes());
         self.light_map_renderer.render(device, queue, encoder, &self.uniform_bind_group, sdf.output_bind_group(), &self.lights_bind_group, &self.shapes_bind_group, &self.geometry_bind_group);
         {
             // Denoising and diffuse lighting pass
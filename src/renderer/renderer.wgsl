@@ -9,6 +9,7 @@ struct Uniforms {
     cursor_size: f32,
     time: f32,
     exposure: f32,
+    topology: u32,
 };
 
 @group(0) @binding(0)
@@ -38,33 +39,11 @@ fn main_vert(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
 
 // Fragment shader
 
-@group(1) @binding(0)
-var t_sdf: texture_2d<f32>;
-@group(1) @binding(1)
-var s_sdf: sampler;
-
 @group(2) @binding(0)
 var t_lightmap: texture_2d<f32>;
 @group(2) @binding(1)
 var s_lightmap: sampler;
 
-fn unpackSdf(v: f32) -> f32 {
-    return v;
-}
-
-fn sceneDist(world_pos: vec2<f32>) -> f32 {
-    var uv = world_pos * uniforms.inv_world_size;
-    uv.y = -uv.y;
-    uv = uv + 0.5;
-    return unpackSdf(textureSample(t_sdf, s_sdf, uv).r);
-}
-
-fn wrap(p: vec2<f32>) -> vec2<f32> 
-{
-    let s = ceil(abs(p / uniforms.world_size)) + 0.5;
-    return (p + s * uniforms.world_size) % uniforms.world_size - 0.5 * uniforms.world_size;
-}
-
 @fragment
 fn main_frag(in: VertexOutput) -> @location(0) vec4<f32> {
     var col = textureSample(t_lightmap, s_lightmap, in.uv).rgb;
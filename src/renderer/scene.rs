@@ -0,0 +1,255 @@
+use std::ops::Range;
+
+use super::shape::ShapeData;
+
+// Stays valid while its shape lives, a removed shape's handle never finds a later one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShapeHandle {
+    index: u32,
+    generation: u32,
+}
+
+struct Slot {
+    generation: u32,
+    // Position in the packed shapes while alive
+    dense: Option<u32>,
+}
+
+// Shapes behind generational handles. They are packed without gaps in the order the GPU sees
+// them, removing one moves the last shape into its place. Changed positions are tracked, so only
+// they have to be uploaded.
+pub struct Scene {
+    shapes: Vec<ShapeData>,
+    owners: Vec<u32>,
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    dirty: Vec<bool>,
+    changed: bool,
+    restructured: bool,
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self {
+            shapes: Vec::new(),
+            owners: Vec::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+            dirty: Vec::new(),
+            changed: false,
+            restructured: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shapes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shapes.is_empty()
+    }
+
+    pub fn insert(&mut self, shape: ShapeData) -> ShapeHandle {
+        let dense = self.shapes.len() as u32;
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index as usize].dense = Some(dense);
+                index
+            }
+            None => {
+                self.slots.push(Slot { generation: 0, dense: Some(dense) });
+                self.slots.len() as u32 - 1
+            }
+        };
+        self.shapes.push(shape);
+        self.owners.push(index);
+        self.dirty.push(true);
+        self.changed = true;
+        self.restructured = true;
+        ShapeHandle { index, generation: self.slots[index as usize].generation }
+    }
+
+    fn dense(&self, handle: ShapeHandle) -> Option<usize> {
+        let slot = self.slots.get(handle.index as usize)?;
+        if slot.generation != handle.generation {
+            return None;
+        }
+        slot.dense.map(|dense| dense as usize)
+    }
+
+    pub fn get(&self, handle: ShapeHandle) -> Option<&ShapeData> {
+        self.dense(handle).map(|dense| &self.shapes[dense])
+    }
+
+    // Marks the shape for upload
+    pub fn get_mut(&mut self, handle: ShapeHandle) -> Option<&mut ShapeData> {
+        let dense = self.dense(handle)?;
+        self.dirty[dense] = true;
+        self.changed = true;
+        Some(&mut self.shapes[dense])
+    }
+
+    pub fn remove(&mut self, handle: ShapeHandle) -> Option<ShapeData> {
+        let dense = self.dense(handle)?;
+        let slot = &mut self.slots[handle.index as usize];
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.dense = None;
+        self.free.push(handle.index);
+
+        let shape = self.shapes.swap_remove(dense);
+        self.owners.swap_remove(dense);
+        self.dirty.swap_remove(dense);
+        if dense < self.shapes.len() {
+            self.slots[self.owners[dense] as usize].dense = Some(dense as u32);
+            self.dirty[dense] = true;
+        }
+        self.changed = true;
+        self.restructured = true;
+        Some(shape)
+    }
+
+    // Handle of the shape at a position in the packed shapes
+    pub fn handle(&self, dense: usize) -> ShapeHandle {
+        let index = self.owners[dense];
+        ShapeHandle { index, generation: self.slots[index as usize].generation }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ShapeHandle, &ShapeData)> {
+        self.owners.iter().zip(self.shapes.iter()).map(move |(&index, shape)| {
+            (ShapeHandle { index, generation: self.slots[index as usize].generation }, shape)
+        })
+    }
+
+    // Packed shapes as uploaded
+    pub fn shapes(&self) -> &[ShapeData] {
+        &self.shapes
+    }
+
+    // For building acceleration structures over the shapes in place, nothing is marked for upload
+    pub fn shapes_mut(&mut self) -> &mut [ShapeData] {
+        &mut self.shapes
+    }
+
+    // Whether anything was inserted, changed or removed since the last `clear_changes`
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    // Whether shapes were inserted or removed since the last `clear_changes`, which moves them
+    // around in the packed order
+    pub fn is_restructured(&self) -> bool {
+        self.restructured
+    }
+
+    // Runs of packed positions that changed since the last `clear_changes`
+    pub fn dirty_ranges(&self) -> Vec<Range<usize>> {
+        let mut ranges: Vec<Range<usize>> = Vec::new();
+        for (i, _) in self.dirty.iter().enumerate().filter(|(_, dirty)| **dirty) {
+            match ranges.last_mut() {
+                Some(range) if range.end == i => range.end = i + 1,
+                _ => ranges.push(i..i + 1),
+            }
+        }
+        ranges
+    }
+
+    pub fn clear_changes(&mut self) {
+        self.dirty.fill(false);
+        self.changed = false;
+        self.restructured = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    fn sphere(radius: f32) -> ShapeData {
+        let mut shape = ShapeData::new();
+        shape.update_sphere(Vec3::ZERO, radius, [1.; 3], 0., 0.5);
+        shape
+    }
+
+    fn radius(scene: &Scene, handle: ShapeHandle) -> Option<f32> {
+        scene.get(handle).map(|shape| shape.data1[3])
+    }
+
+    #[test]
+    fn shapes_are_found_by_their_handles() {
+        let mut scene = Scene::default();
+        assert!(scene.is_empty());
+        let a = scene.insert(sphere(1.));
+        let b = scene.insert(sphere(2.));
+        assert_eq!((scene.len(), radius(&scene, a), radius(&scene, b)), (2, Some(1.), Some(2.)));
+        scene.get_mut(a).unwrap().data1[3] = 3.;
+        assert_eq!(radius(&scene, a), Some(3.));
+        assert_eq!(scene.iter().map(|(handle, _)| handle).collect::<Vec<_>>(), vec![a, b]);
+    }
+
+    #[test]
+    fn removing_moves_the_last_shape_into_the_gap() {
+        let mut scene = Scene::new();
+        let handles: Vec<_> = (1..=3).map(|i| scene.insert(sphere(i as f32))).collect();
+        assert_eq!(scene.remove(handles[0]).map(|shape| shape.data1[3]), Some(1.));
+        assert_eq!(scene.len(), 2);
+        assert_eq!(scene.handle(0), handles[2]);
+        assert_eq!(radius(&scene, handles[2]), Some(3.));
+        assert_eq!(radius(&scene, handles[1]), Some(2.));
+        assert_eq!(scene.remove(handles[0]), None);
+    }
+
+    #[test]
+    fn reused_slots_do_not_answer_to_old_handles() {
+        let mut scene = Scene::new();
+        let old = scene.insert(sphere(1.));
+        scene.remove(old);
+        let new = scene.insert(sphere(2.));
+        assert_ne!(old, new);
+        assert_eq!(radius(&scene, old), None);
+        assert!(scene.get_mut(old).is_none());
+        assert_eq!(radius(&scene, new), Some(2.));
+    }
+
+    #[test]
+    fn changes_are_tracked_until_cleared() {
+        let mut scene = Scene::new();
+        let handles: Vec<_> = (0..6).map(|_| scene.insert(sphere(1.))).collect();
+        assert!(scene.is_changed() && scene.is_restructured());
+        assert_eq!(scene.dirty_ranges(), vec![0..6]);
+        scene.clear_changes();
+        assert!(!scene.is_changed() && !scene.is_restructured());
+        assert!(scene.dirty_ranges().is_empty());
+
+        // Reading and building in place mark nothing
+        scene.get(handles[0]);
+        scene.shapes_mut()[1].data1[3] = 2.;
+        assert!(!scene.is_changed());
+
+        scene.get_mut(handles[1]);
+        scene.get_mut(handles[2]);
+        scene.get_mut(handles[4]);
+        assert!(scene.is_changed() && !scene.is_restructured());
+        assert_eq!(scene.dirty_ranges(), vec![1..3, 4..5]);
+        scene.clear_changes();
+
+        // The last shape moves into the removed one's place
+        scene.remove(handles[0]);
+        assert!(scene.is_restructured());
+        assert_eq!(scene.dirty_ranges(), vec![0..1]);
+        scene.clear_changes();
+        scene.remove(handles[0]);
+        assert!(!scene.is_changed());
+        // Removing the last shape moves nothing
+        scene.remove(scene.handle(scene.len() - 1));
+        assert!(scene.is_changed());
+        assert!(scene.dirty_ranges().is_empty());
+    }
+}
@@ -1,12 +1,20 @@
+use std::ops::Range;
+
 use bvh::{aabb::{AABB, Bounded}, bounding_hierarchy::BHShape};
-use glam::Vec3;
+use glam::{Mat3, Quat, Vec3};
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct ShapeData {
     pub data0: [u32; 4],
     pub data1: [f32; 4],
     pub data2: [f32; 4],
+    // Orientation quaternion (x, y, z, w) and scale (x, y, z) of the primitive around its position
+    // in `data1`, the primitive itself is defined in object space
+    pub rotation: [f32; 4],
+    pub scale: [f32; 4],
+    // Emitted radiance as a linear color and an intensity it is multiplied by
+    pub emissive: [f32; 4],
 }
 
 impl Default for ShapeData {
@@ -15,6 +23,41 @@ impl Default for ShapeData {
             data0: [0; 4],
             data1: [0.0; 4],
             data2: [0.0; 4],
+            rotation: Quat::IDENTITY.into(),
+            scale: [1.0, 1.0, 1.0, 0.0],
+            emissive: [0.0; 4],
+        }
+    }
+}
+
+// Primitive the cursor shape is drawn as
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShapeKind {
+    #[default]
+    Sphere,
+    Box,
+    RoundedBox,
+    Capsule,
+    Cylinder,
+    Torus,
+    Ellipsoid,
+}
+
+impl ShapeKind {
+    pub const ALL: [ShapeKind; 7] = [
+        ShapeKind::Sphere, ShapeKind::Box, ShapeKind::RoundedBox, ShapeKind::Capsule,
+        ShapeKind::Cylinder, ShapeKind::Torus, ShapeKind::Ellipsoid,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShapeKind::Sphere => "sphere",
+            ShapeKind::Box => "box",
+            ShapeKind::RoundedBox => "rounded box",
+            ShapeKind::Capsule => "capsule",
+            ShapeKind::Cylinder => "cylinder",
+            ShapeKind::Torus => "torus",
+            ShapeKind::Ellipsoid => "ellipsoid",
         }
     }
 }
@@ -37,20 +80,15 @@ impl RgbExt for [f32; 3] {
     }
 }
 
-trait TranslateXYZ {
-    fn translate(&mut self, translate: [f32; 3]);
-}
-
-impl TranslateXYZ for [f32; 4] {
-    fn translate(&mut self, translate: [f32; 3]) {
-        self[0] += translate[0];
-        self[1] += translate[1];
-        self[2] += translate[2];
-    }
-}
-
-const SHAPE_SPHERE: u32 = 0;
-const SHAPE_ROUNDED_CONE: u32 = 1;
+// Keep in sync with the constants in shapes.wgsl
+pub const SHAPE_SPHERE: u32 = 0;
+pub const SHAPE_ROUNDED_CONE: u32 = 1;
+pub const SHAPE_BOX: u32 = 2;
+pub const SHAPE_ROUNDED_BOX: u32 = 3;
+pub const SHAPE_CAPSULE: u32 = 4;
+pub const SHAPE_CYLINDER: u32 = 5;
+pub const SHAPE_TORUS: u32 = 6;
+pub const SHAPE_ELLIPSOID: u32 = 7;
 
 impl ShapeData {
     pub fn new() -> Self {
@@ -59,13 +97,45 @@ impl ShapeData {
         }
     }
 
+    fn set_kind(&mut self, kind: u32, color: [f32; 3], metallic: f32, roughness: f32) {
+        self.data0[0] = kind;
+        self.set_material(color, metallic, roughness);
+    }
+
+    pub fn set_material(&mut self, color: [f32; 3], metallic: f32, roughness: f32) {
+        self.data0[1] = color.to_u32();
+        self.data0[2] = u32::from_le_bytes([ecolor::linear_u8_from_linear_f32(metallic), ecolor::linear_u8_from_linear_f32(roughness), 0u8, 0u8]);
+    }
+
+    // Color, metallic and roughness as packed by `set_material`, to 8 bits each
+    pub fn material(&self) -> ([f32; 3], f32, f32) {
+        let [r, g, b, _] = self.data0[1].to_le_bytes();
+        let [metallic, roughness, _, _] = self.data0[2].to_le_bytes();
+        (
+            [r, g, b].map(ecolor::linear_f32_from_gamma_u8),
+            ecolor::linear_f32_from_linear_u8(metallic),
+            ecolor::linear_f32_from_linear_u8(roughness),
+        )
+    }
+
+    pub fn set_emissive(&mut self, color: [f32; 3], intensity: f32) {
+        self.emissive = [color[0], color[1], color[2], intensity];
+    }
+
+    pub fn emissive(&self) -> ([f32; 3], f32) {
+        ([self.emissive[0], self.emissive[1], self.emissive[2]], self.emissive[3])
+    }
+
+    // Radiance leaving the surface on its own
+    pub fn emitted(&self) -> Vec3 {
+        Vec3::from_slice(&self.emissive[0..3]) * self.emissive[3]
+    }
+
     pub fn update_sphere(&mut self, 
         position: Vec3, radius: f32,
         color: [f32; 3], metallic: f32, roughness: f32, 
     ) {
-        self.data0[0] = SHAPE_SPHERE;
-        self.data0[1] = color.to_u32();
-        self.data0[2] = u32::from_le_bytes([ecolor::linear_u8_from_linear_f32(metallic), ecolor::linear_u8_from_linear_f32(roughness), 0u8, 0u8]);
+        self.set_kind(SHAPE_SPHERE, color, metallic, roughness);
         self.data1 = position.extend(radius).into();
     }
 
@@ -74,40 +144,187 @@ impl ShapeData {
         position_b: Vec3, radius_b: f32,
         color: [f32; 3], metallic: f32, roughness: f32, 
     ) {
-        self.data0[0] = SHAPE_ROUNDED_CONE;
-        self.data0[1] = color.to_u32();
-        self.data0[2] = u32::from_le_bytes([ecolor::linear_u8_from_linear_f32(metallic), ecolor::linear_u8_from_linear_f32(roughness), 0u8, 0u8]);
+        self.set_kind(SHAPE_ROUNDED_CONE, color, metallic, roughness);
         self.data1 = position_a.extend(radius_a).into();
         self.data2 = position_b.extend(radius_b).into();
     }
 
-    pub fn translate(&mut self, translate: Vec3) {
-        self.data1.translate(translate.into());
-        self.data2.translate(translate.into());
+    // Axis aligned, `half_size` from the center to the faces
+    pub fn update_box(&mut self,
+        position: Vec3, half_size: Vec3,
+        color: [f32; 3], metallic: f32, roughness: f32,
+    ) {
+        self.set_kind(SHAPE_BOX, color, metallic, roughness);
+        self.data1 = position.extend(0.).into();
+        self.data2 = half_size.extend(0.).into();
     }
-}
 
-impl Bounded for ShapeData {
-    fn aabb(&self) -> AABB {
+    // Like a box of the same `half_size` with the edges rounded off by `radius`
+    pub fn update_rounded_box(&mut self,
+        position: Vec3, half_size: Vec3, radius: f32,
+        color: [f32; 3], metallic: f32, roughness: f32,
+    ) {
+        self.set_kind(SHAPE_ROUNDED_BOX, color, metallic, roughness);
+        self.data1 = position.extend(radius.min(half_size.min_element())).into();
+        self.data2 = half_size.extend(0.).into();
+    }
+
+    pub fn update_capsule(&mut self,
+        position_a: Vec3, position_b: Vec3, radius: f32,
+        color: [f32; 3], metallic: f32, roughness: f32,
+    ) {
+        self.set_kind(SHAPE_CAPSULE, color, metallic, roughness);
+        self.data1 = position_a.extend(radius).into();
+        self.data2 = position_b.extend(0.).into();
+    }
+
+    // Capped between the centers of its end faces
+    pub fn update_cylinder(&mut self,
+        position_a: Vec3, position_b: Vec3, radius: f32,
+        color: [f32; 3], metallic: f32, roughness: f32,
+    ) {
+        self.set_kind(SHAPE_CYLINDER, color, metallic, roughness);
+        self.data1 = position_a.extend(radius).into();
+        self.data2 = position_b.extend(0.).into();
+    }
+
+    // Lying flat, around the z axis
+    pub fn update_torus(&mut self,
+        position: Vec3, major_radius: f32, minor_radius: f32,
+        color: [f32; 3], metallic: f32, roughness: f32,
+    ) {
+        self.set_kind(SHAPE_TORUS, color, metallic, roughness);
+        self.data1 = position.extend(major_radius).into();
+        self.data2 = [minor_radius, 0., 0., 0.];
+    }
+
+    // Axis aligned
+    pub fn update_ellipsoid(&mut self,
+        position: Vec3, radii: Vec3,
+        color: [f32; 3], metallic: f32, roughness: f32,
+    ) {
+        self.set_kind(SHAPE_ELLIPSOID, color, metallic, roughness);
+        self.data1 = position.extend(0.).into();
+        self.data2 = radii.extend(0.).into();
+    }
+
+    // A shape of `kind` about `radius` in size, resting on the ground under `position`
+    pub fn update_kind(&mut self,
+        kind: ShapeKind, position: Vec3, radius: f32,
+        color: [f32; 3], metallic: f32, roughness: f32,
+    ) {
+        let center = position + Vec3::Z * radius;
+        match kind {
+            ShapeKind::Sphere => self.update_sphere(center, radius, color, metallic, roughness),
+            ShapeKind::Box => self.update_box(center, Vec3::splat(radius), color, metallic, roughness),
+            ShapeKind::RoundedBox => self.update_rounded_box(center, Vec3::splat(radius), 0.25 * radius, color, metallic, roughness),
+            ShapeKind::Capsule => self.update_capsule(
+                position + Vec3::new(-radius, 0., 0.5 * radius), position + Vec3::new(radius, 0., 0.5 * radius), 0.5 * radius,
+                color, metallic, roughness,
+            ),
+            ShapeKind::Cylinder => self.update_cylinder(
+                position, position + Vec3::Z * 2. * radius, 0.5 * radius,
+                color, metallic, roughness,
+            ),
+            ShapeKind::Torus => self.update_torus(
+                position + Vec3::Z * 0.25 * radius, 0.75 * radius, 0.25 * radius,
+                color, metallic, roughness,
+            ),
+            ShapeKind::Ellipsoid => self.update_ellipsoid(
+                center, Vec3::new(radius, 0.5 * radius, radius),
+                color, metallic, roughness,
+            ),
+        }
+    }
+
+    pub fn position(&self) -> Vec3 {
+        Vec3::from_slice(&self.data1[0..3])
+    }
+
+    pub fn set_rotation(&mut self, rotation: Quat) {
+        self.rotation = rotation.normalize().into();
+    }
+
+    pub fn set_scale(&mut self, scale: Vec3) {
+        self.scale = scale.extend(0.).into();
+    }
+
+    // Maps object space around the shape's position to world space directions
+    fn object_to_world(&self) -> Mat3 {
+        Mat3::from_quat(Quat::from_array(self.rotation)) * Mat3::from_diagonal(Vec3::from_slice(&self.scale[0..3]))
+    }
+
+    // Object space bounds relative to the shape's position
+    fn local_bounds(&self) -> (Vec3, Vec3) {
+        let position = self.position();
         match self.data0[0] {
             SHAPE_SPHERE => {
-                let position = bvh::Point3::from_slice(&self.data1[0..3]);
                 let radius = self.data1[3];
-                AABB::with_bounds(position - radius, position + radius)
+                (Vec3::splat(-radius), Vec3::splat(radius))
             }
             SHAPE_ROUNDED_CONE => {
-                let position_a = bvh::Point3::from_slice(&self.data1[0..3]);
                 let radius_a = self.data1[3];
-                let position_b= bvh::Point3::from_slice(&self.data2[0..3]);
+                let position_b = Vec3::from_slice(&self.data2[0..3]) - position;
                 let radius_b = self.data2[3];
-                AABB::with_bounds(
-                    (position_a - radius_a).min(position_b - radius_b),
-                    (position_a + radius_a).max(position_b + radius_b),
+                (
+                    Vec3::splat(-radius_a).min(position_b - radius_b),
+                    Vec3::splat(radius_a).max(position_b + radius_b),
                 )
             }
+            SHAPE_BOX | SHAPE_ROUNDED_BOX | SHAPE_ELLIPSOID => {
+                let half_size = Vec3::from_slice(&self.data2[0..3]);
+                (-half_size, half_size)
+            }
+            SHAPE_CAPSULE => {
+                let position_b = Vec3::from_slice(&self.data2[0..3]) - position;
+                let radius = self.data1[3];
+                (position_b.min(Vec3::ZERO) - radius, position_b.max(Vec3::ZERO) + radius)
+            }
+            SHAPE_CYLINDER => {
+                // The flat caps only reach out across the axis, https://iquilezles.org/articles/diskbbox/
+                let position_b = Vec3::from_slice(&self.data2[0..3]) - position;
+                let radius = self.data1[3];
+                let axis = position_b / position_b.length().max(1e-6);
+                let e = radius * (Vec3::ONE - axis * axis).max(Vec3::ZERO).powf(0.5);
+                (position_b.min(Vec3::ZERO) - e, position_b.max(Vec3::ZERO) + e)
+            }
+            SHAPE_TORUS => {
+                let outer = self.data1[3] + self.data2[0];
+                let half_size = Vec3::new(outer, outer, self.data2[0]);
+                (-half_size, half_size)
+            }
             _ => panic!("Not possible!!!")
         }
     }
+
+    // Applies `translation + rotation * (scale * p)` on top of the shape's own transform. Exact
+    // unless both this and the shape's own transform rotate and scale non-uniformly.
+    pub fn transform(&mut self, translation: Vec3, rotation: Quat, scale: Vec3) {
+        let position = self.position();
+        let offset = Vec3::from_slice(&self.data2[0..3]) - position;
+        let new_position = translation + rotation * (scale * position);
+        self.data1 = new_position.extend(self.data1[3]).into();
+        if matches!(self.data0[0], SHAPE_ROUNDED_CONE | SHAPE_CAPSULE | SHAPE_CYLINDER) {
+            self.data2 = (new_position + offset).extend(self.data2[3]).into();
+        }
+        self.set_rotation(rotation * Quat::from_array(self.rotation));
+        self.set_scale(scale * Vec3::from_slice(&self.scale[0..3]));
+    }
+}
+
+impl Bounded for ShapeData {
+    // World space box around the transformed object space bounds
+    fn aabb(&self) -> AABB {
+        let (min, max) = self.local_bounds();
+        let m = self.object_to_world();
+        let center = self.position() + m * (0.5 * (min + max));
+        let m_abs = Mat3::from_cols(m.x_axis.abs(), m.y_axis.abs(), m.z_axis.abs());
+        let half_size = m_abs * (0.5 * (max - min));
+        AABB::with_bounds(
+            bvh::Point3::from_slice(&(center - half_size).to_array()),
+            bvh::Point3::from_slice(&(center + half_size).to_array()),
+        )
+    }
 }
 
 impl BHShape for ShapeData {
@@ -120,13 +337,22 @@ impl BHShape for ShapeData {
     }
 }
 
+// Set in the `entry` of leaves, which hold shapes `first..first + count` of the BVH's shape
+// indices packed as LEAF_FLAG | (count - 1) << LEAF_COUNT_SHIFT | first. Keep in sync with
+// light_map.wgsl and lbvh.wgsl.
+pub const BVH_LEAF_FLAG: u32 = 1 << 31;
+pub const BVH_LEAF_COUNT_SHIFT: u32 = 24;
+pub const BVH_MAX_LEAF_SHAPES: usize = 1 << (31 - BVH_LEAF_COUNT_SHIFT);
+
+// Flattened BVH node, nodes are stored depth-first. Inner nodes enter their first child with
+// `entry`, every node skips past its subtree with `exit`. Leaves carry their own bounds.
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct ShapeBVHNode {
     pub aabb_pos: [f32; 3],
-    pub entry: i32,
+    pub entry: u32,
     pub aabb_rad: [f32; 3],
-    pub exit: i32,
+    pub exit: u32,
 }
 
 impl Default for ShapeBVHNode {
@@ -140,11 +366,46 @@ impl Default for ShapeBVHNode {
     }
 }
 
+impl ShapeBVHNode {
+    pub fn inner(min: Vec3, max: Vec3, entry: u32, exit: u32) -> Self {
+        Self {
+            aabb_pos: (0.5 * (min + max)).into(),
+            entry,
+            aabb_rad: (0.5 * (max - min)).into(),
+            exit,
+        }
+    }
+
+    pub fn leaf(min: Vec3, max: Vec3, shapes: Range<usize>, exit: u32) -> Self {
+        debug_assert!(!shapes.is_empty() && shapes.len() <= BVH_MAX_LEAF_SHAPES && shapes.start < 1 << BVH_LEAF_COUNT_SHIFT);
+        let entry = BVH_LEAF_FLAG | ((shapes.len() as u32 - 1) << BVH_LEAF_COUNT_SHIFT) | shapes.start as u32;
+        Self::inner(min, max, entry, exit)
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.entry & BVH_LEAF_FLAG != 0
+    }
+
+    // Positions in the shape indices of the shapes in a leaf
+    pub fn shapes(&self) -> Range<usize> {
+        let first = (self.entry & ((1 << BVH_LEAF_COUNT_SHIFT) - 1)) as usize;
+        let count = ((self.entry & !BVH_LEAF_FLAG) >> BVH_LEAF_COUNT_SHIFT) as usize + 1;
+        first..first + count
+    }
+
+    pub fn bounds(&self) -> (Vec3, Vec3) {
+        let pos = Vec3::from(self.aabb_pos);
+        let rad = Vec3::from(self.aabb_rad);
+        (pos - rad, pos + rad)
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct ShapesConfig {
     pub num_shapes: u32,
     pub num_bvh_nodes: u32,
+    pub num_shape_lights: u32,
 }
 
 impl Default for ShapesConfig {
@@ -152,7 +413,139 @@ impl Default for ShapesConfig {
         Self {
             num_shapes: 0,
             num_bvh_nodes: 0,
+            num_shape_lights: 0,
         }
     }
     
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds(shape: &ShapeData) -> (Vec3, Vec3) {
+        let aabb = shape.aabb();
+        (Vec3::from_slice(&aabb.min.to_array()), Vec3::from_slice(&aabb.max.to_array()))
+    }
+
+    fn assert_bounds(shape: &ShapeData, min: Vec3, max: Vec3) {
+        let (a, b) = bounds(shape);
+        assert!(a.abs_diff_eq(min, 1e-5) && b.abs_diff_eq(max, 1e-5), "{:?} {:?}, expected {:?} {:?}", a, b, min, max);
+    }
+
+    #[test]
+    fn kinds_match_the_shader_constants() {
+        let shader = include_str!("shapes.wgsl");
+        for (name, kind) in [
+            ("SPHERE", SHAPE_SPHERE), ("ROUNDED_CONE", SHAPE_ROUNDED_CONE), ("BOX", SHAPE_BOX),
+            ("ROUNDED_BOX", SHAPE_ROUNDED_BOX), ("CAPSULE", SHAPE_CAPSULE), ("CYLINDER", SHAPE_CYLINDER),
+            ("TORUS", SHAPE_TORUS), ("ELLIPSOID", SHAPE_ELLIPSOID),
+        ] {
+            assert!(shader.contains(&format!("const SHAPE_{}: u32 = {}u;", name, kind)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn primitives_are_bounded_by_their_parameters() {
+        let p = Vec3::new(1., 2., 3.);
+        let mut shape = ShapeData::new();
+        shape.update_box(p, Vec3::new(1., 2., 0.5), [1.; 3], 0., 0.5);
+        assert_bounds(&shape, p - Vec3::new(1., 2., 0.5), p + Vec3::new(1., 2., 0.5));
+        shape.update_rounded_box(p, Vec3::ONE, 0.25, [1.; 3], 0., 0.5);
+        assert_bounds(&shape, p - Vec3::ONE, p + Vec3::ONE);
+        shape.update_capsule(p, p + Vec3::X * 4., 0.5, [1.; 3], 0., 0.5);
+        assert_bounds(&shape, p - 0.5, p + Vec3::new(4.5, 0.5, 0.5));
+        shape.update_cylinder(p, p - Vec3::Z * 2., 1., [1.; 3], 0., 0.5);
+        assert_bounds(&shape, p - Vec3::new(1., 1., 2.), p + Vec3::new(1., 1., 0.));
+        shape.update_torus(p, 2., 0.5, [1.; 3], 0., 0.5);
+        assert_bounds(&shape, p - Vec3::new(2.5, 2.5, 0.5), p + Vec3::new(2.5, 2.5, 0.5));
+        shape.update_ellipsoid(p, Vec3::new(3., 2., 1.), [1.; 3], 0., 0.5);
+        assert_bounds(&shape, p - Vec3::new(3., 2., 1.), p + Vec3::new(3., 2., 1.));
+        shape.update_rounded_cone(p, 1., p + Vec3::Y * 3., 0.5, [1.; 3], 0., 0.5);
+        assert_bounds(&shape, p - 1., p + Vec3::new(1., 3.5, 1.));
+    }
+
+    #[test]
+    fn rounded_box_corners_fit_inside_the_box() {
+        let mut shape = ShapeData::new();
+        shape.update_rounded_box(Vec3::ZERO, Vec3::new(2., 1., 3.), 5., [1.; 3], 0., 0.5);
+        assert_eq!(shape.data1[3], 1.);
+    }
+
+    #[test]
+    fn every_kind_rests_on_the_ground() {
+        let ground = Vec3::new(-4., 5., -2.);
+        for kind in ShapeKind::ALL {
+            let mut shape = ShapeData::new();
+            shape.update_kind(kind, ground, 0.5, [1.; 3], 0., 0.5);
+            let (min, max) = bounds(&shape);
+            assert!((min.z - ground.z).abs() < 1e-5, "{} starts at {}", kind.name(), min.z);
+            assert!(max.z > ground.z && min.x < ground.x && max.x > ground.x, "{}", kind.name());
+        }
+    }
+
+    #[test]
+    fn rotated_bounds_follow_the_shape() {
+        let p = Vec3::new(1., 2., 3.);
+        let mut shape = ShapeData::new();
+        shape.update_box(p, Vec3::new(2., 1., 0.5), [1.; 3], 0., 0.5);
+        shape.set_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_2));
+        assert_bounds(&shape, p - Vec3::new(1., 2., 0.5), p + Vec3::new(1., 2., 0.5));
+        // A cube turned by 45 degrees reaches out to its edges
+        shape.update_box(p, Vec3::ONE, [1.; 3], 0., 0.5);
+        shape.set_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_4));
+        let r = std::f32::consts::SQRT_2;
+        assert_bounds(&shape, p - Vec3::new(r, r, 1.), p + Vec3::new(r, r, 1.));
+    }
+
+    #[test]
+    fn scale_stretches_the_shape_before_it_turns() {
+        let p = Vec3::new(1., 2., 3.);
+        let mut shape = ShapeData::new();
+        shape.update_sphere(p, 1., [1.; 3], 0., 0.5);
+        shape.set_scale(Vec3::new(3., 1., 2.));
+        assert_bounds(&shape, p - Vec3::new(3., 1., 2.), p + Vec3::new(3., 1., 2.));
+        shape.set_rotation(Quat::from_rotation_x(std::f32::consts::FRAC_PI_2));
+        assert_bounds(&shape, p - Vec3::new(3., 2., 1.), p + Vec3::new(3., 2., 1.));
+    }
+
+    #[test]
+    fn off_center_shapes_turn_around_their_position() {
+        // The capsule runs from its position along x, a half turn sends it along -x
+        let mut shape = ShapeData::new();
+        shape.update_capsule(Vec3::ZERO, Vec3::X * 4., 1., [1.; 3], 0., 0.5);
+        shape.set_rotation(Quat::from_rotation_z(std::f32::consts::PI));
+        assert_bounds(&shape, Vec3::new(-5., -1., -1.), Vec3::ONE);
+    }
+
+    #[test]
+    fn transforms_compose_with_the_shape_transform() {
+        let mut shape = ShapeData::new();
+        shape.update_capsule(Vec3::X, Vec3::X * 3., 0.5, [1.; 3], 0., 0.5);
+        shape.set_scale(Vec3::new(1., 2., 1.));
+        let turn = Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+        shape.transform(Vec3::Z, turn, Vec3::splat(2.));
+        assert!(shape.position().abs_diff_eq(Vec3::new(0., 2., 1.), 1e-5));
+        // The far end moves along, its offset stays in object space where the rotation turns it
+        assert!(Vec3::from_slice(&shape.data2[0..3]).abs_diff_eq(Vec3::new(2., 2., 1.), 1e-5));
+        assert!(Quat::from_array(shape.rotation).abs_diff_eq(turn, 1e-5));
+        assert_eq!(&shape.scale[0..3], &[2., 4., 2.]);
+    }
+
+    #[test]
+    fn rotations_are_normalized() {
+        let mut shape = ShapeData::new();
+        shape.set_rotation(Quat::from_xyzw(0., 0., 2., 2.));
+        assert!((Quat::from_array(shape.rotation).length() - 1.).abs() < 1e-6);
+    }
+
+    #[test]
+    fn materials_round_trip_to_8_bits() {
+        let mut shape = ShapeData::new();
+        shape.update_sphere(Vec3::ZERO, 1., [0.2, 0.5, 1.], 0.25, 0.75);
+        let (color, metallic, roughness) = shape.material();
+        for (a, b) in color.iter().zip([0.2, 0.5, 1.]) {
+            assert!((a - b).abs() < 0.01, "{} {}", a, b);
+        }
+        assert!((metallic - 0.25).abs() < 0.01 && (roughness - 0.75).abs() < 0.01);
+    }
+}
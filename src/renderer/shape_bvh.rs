@@ -0,0 +1,415 @@
+use std::ops::Range;
+
+use bvh::aabb::Bounded;
+use bvh::bvh::BVHNode;
+use glam::Vec3;
+
+use super::shape::{ShapeBVHNode, ShapeData};
+
+// A refitted tree is rebuilt once its nodes have grown this much in total surface area since the
+// last build, which is what the SAH cost of traversing them scales with
+const SAH_REBUILD_RATIO: f32 = 1.5;
+// Subtrees of the built tree with at most this many shapes are flattened into a single leaf
+const LEAF_SHAPES: usize = 4;
+
+fn surface_area(half_size: Vec3) -> f32 {
+    8. * (half_size.x * half_size.y + half_size.y * half_size.z + half_size.z * half_size.x)
+}
+
+fn shape_bounds(shape: &ShapeData) -> (Vec3, Vec3) {
+    let aabb = shape.aabb();
+    let (min, max): ([f32; 3], [f32; 3]) = (aabb.min.into(), aabb.max.into());
+    (min.into(), max.into())
+}
+
+fn union(bounds: impl Iterator<Item = (Vec3, Vec3)>) -> (Vec3, Vec3) {
+    bounds.fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), (a, b)| (min.min(a), max.max(b)))
+}
+
+// Slab test of iAABB in light_map.wgsl, `ro` is relative to the box centre. Axes the ray runs
+// parallel to only check the origin, their slabs would be NaN.
+fn intersect_aabb(ro: Vec3, inv_rd: Vec3, rad: Vec3, tmax: f32) -> bool {
+    let parallel = inv_rd.abs().cmpeq(Vec3::INFINITY);
+    if (parallel & ro.abs().cmpgt(rad)).any() {
+        return false;
+    }
+    let n = inv_rd * ro;
+    let k = inv_rd.abs() * rad;
+    let tnear = Vec3::select(parallel, Vec3::NEG_INFINITY, -n - k).max_element();
+    let tfar = Vec3::select(parallel, Vec3::INFINITY, -n + k).min_element();
+    tfar > tnear.max(0.) && tnear < tmax
+}
+
+// Flattened shape BVH in the layout the shaders traverse, see `ShapeBVHNode`. Leaves refer to runs
+// of `indices`, which lists the shapes in leaf order.
+pub struct ShapeBVH {
+    nodes: Vec<ShapeBVHNode>,
+    indices: Vec<u32>,
+    // Inner node above every node, none for the root
+    parents: Vec<Option<u32>>,
+    // Leaf node of every shape
+    leaves: Vec<u32>,
+    area: f32,
+    built_area: f32,
+    dirty: Option<Range<usize>>,
+}
+
+impl ShapeBVH {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            indices: Vec::new(),
+            parents: Vec::new(),
+            leaves: Vec::new(),
+            area: 0.,
+            built_area: 0.,
+            dirty: None,
+        }
+    }
+
+    pub fn nodes(&self) -> &[ShapeBVHNode] {
+        &self.nodes
+    }
+
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    fn bounds(&self, node: usize, shapes: &[ShapeData]) -> (Vec3, Vec3) {
+        let node = &self.nodes[node];
+        if node.is_leaf() {
+            union(self.indices[node.shapes()].iter().map(|&shape| shape_bounds(&shapes[shape as usize])))
+        } else {
+            let first = node.entry as usize;
+            let second = self.nodes[first].exit as usize;
+            union([self.nodes[first].bounds(), self.nodes[second].bounds()].iter().copied())
+        }
+    }
+
+    pub fn build(&mut self, shapes: &mut [ShapeData]) {
+        self.nodes.clear();
+        self.indices.clear();
+        self.parents.clear();
+        self.leaves = vec![0; shapes.len()];
+        if !shapes.is_empty() {
+            let tree = bvh::bvh::BVH::build(shapes).nodes;
+            self.flatten(&tree, 0, None, shapes);
+        }
+        self.area = self.nodes.iter().map(|node| surface_area(node.aabb_rad.into())).sum();
+        self.built_area = self.area;
+        self.dirty = Some(0..self.nodes.len());
+    }
+
+    // Pushes the shapes under `node` of the built tree and returns whether they fit in a leaf
+    fn gather(tree: &[BVHNode], node: usize, end: usize, indices: &mut Vec<u32>) -> bool {
+        match tree[node] {
+            BVHNode::Leaf { shape_index, .. } => {
+                indices.push(shape_index as u32);
+                indices.len() <= end
+            }
+            BVHNode::Node { child_l_index, child_r_index, .. } => {
+                Self::gather(tree, child_l_index, end, indices) && Self::gather(tree, child_r_index, end, indices)
+            }
+        }
+    }
+
+    // Appends `node` of the built tree and everything under it, returns its bounds
+    fn flatten(&mut self, tree: &[BVHNode], node: usize, parent: Option<u32>, shapes: &[ShapeData]) -> (Vec3, Vec3) {
+        let index = self.nodes.len();
+        self.nodes.push(ShapeBVHNode::default());
+        self.parents.push(parent);
+
+        let first = self.indices.len();
+        if Self::gather(tree, node, first + LEAF_SHAPES, &mut self.indices) {
+            for &shape in &self.indices[first..] {
+                self.leaves[shape as usize] = index as u32;
+            }
+            let (min, max) = union(self.indices[first..].iter().map(|&shape| shape_bounds(&shapes[shape as usize])));
+            self.nodes[index] = ShapeBVHNode::leaf(min, max, first..self.indices.len(), index as u32 + 1);
+            (min, max)
+        } else {
+            self.indices.truncate(first);
+            let BVHNode::Node { child_l_index, child_r_index, .. } = tree[node] else {
+                unreachable!("a single shape always fits in a leaf");
+            };
+            let (min, max) = union([
+                self.flatten(tree, child_l_index, Some(index as u32), shapes),
+                self.flatten(tree, child_r_index, Some(index as u32), shapes),
+            ].iter().copied());
+            self.nodes[index] = ShapeBVHNode::inner(min, max, index as u32 + 1, self.nodes.len() as u32);
+            (min, max)
+        }
+    }
+
+    // Refits the nodes above the `changed` shapes to their new bounds, walking up until a node no
+    // longer changes. Returns false when the tree should be rebuilt instead, because the shapes
+    // are not the ones it was built over or it has degraded too much.
+    pub fn refit(&mut self, shapes: &[ShapeData], changed: &[Range<usize>]) -> bool {
+        if shapes.len() != self.leaves.len() {
+            return false;
+        }
+        for shape in changed.iter().cloned().flatten() {
+            let mut node = self.leaves[shape] as usize;
+            loop {
+                let (min, max) = self.bounds(node, shapes);
+                let refitted = ShapeBVHNode { aabb_pos: (0.5 * (min + max)).into(), aabb_rad: (0.5 * (max - min)).into(), ..self.nodes[node] };
+                if refitted == self.nodes[node] {
+                    break;
+                }
+                self.area += surface_area(refitted.aabb_rad.into()) - surface_area(self.nodes[node].aabb_rad.into());
+                self.nodes[node] = refitted;
+                self.dirty = Some(match self.dirty.take() {
+                    Some(dirty) => dirty.start.min(node)..dirty.end.max(node + 1),
+                    None => node..node + 1,
+                });
+                match self.parents[node] {
+                    Some(parent) => node = parent as usize,
+                    None => break,
+                }
+            }
+        }
+        self.area <= SAH_REBUILD_RATIO * self.built_area
+    }
+
+    // Nodes that changed since the last call
+    pub fn take_dirty(&mut self) -> Option<Range<usize>> {
+        self.dirty.take()
+    }
+
+    // The stackless traversal of traceRayBVH in light_map.wgsl. `wrap` is applied to offsets from
+    // `ro` like wrap3 and `hit` is the distance along `rd` at which a shape is hit, if it is.
+    // Returns the closest hit before `tmax` and its shape.
+    pub fn trace_ray(&self, ro: Vec3, rd: Vec3, tmax: f32, wrap: impl Fn(Vec3) -> Vec3, hit: impl Fn(usize) -> Option<f32>) -> Option<(f32, usize)> {
+        let inv_rd = rd.recip();
+        let mut result = None;
+        let mut tmax = tmax;
+        let mut index = 0;
+        while index < self.nodes.len() {
+            let node = &self.nodes[index];
+            if !intersect_aabb(wrap(ro - Vec3::from(node.aabb_pos)), inv_rd, node.aabb_rad.into(), tmax) {
+                index = node.exit as usize;
+            } else if node.is_leaf() {
+                for &shape in &self.indices[node.shapes()] {
+                    if let Some(t) = hit(shape as usize).filter(|&t| t > 0. && t < tmax) {
+                        tmax = t;
+                        result = Some((t, shape as usize));
+                    }
+                }
+                index = node.exit as usize;
+            } else {
+                index = node.entry as usize;
+            }
+        }
+        result
+    }
+
+    // Like traceOccBVH, whether any shape is hit before `tmax`
+    #[cfg(test)]
+    pub fn trace_occ(&self, ro: Vec3, rd: Vec3, tmax: f32, hit: impl Fn(usize) -> Option<f32>) -> bool {
+        let inv_rd = rd.recip();
+        let mut index = 0;
+        while index < self.nodes.len() {
+            let node = &self.nodes[index];
+            if !intersect_aabb(ro - Vec3::from(node.aabb_pos), inv_rd, node.aabb_rad.into(), tmax) {
+                index = node.exit as usize;
+            } else if node.is_leaf() {
+                if self.indices[node.shapes()].iter().any(|&shape| hit(shape as usize).is_some_and(|t| t > 0. && t < tmax)) {
+                    return true;
+                }
+                index = node.exit as usize;
+            } else {
+                index = node.entry as usize;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::*;
+
+    use super::*;
+
+    struct Random(u32);
+
+    impl Random {
+        fn next(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            self.0 as f32 / u32::MAX as f32
+        }
+
+        fn vec3(&mut self, min: f32, max: f32) -> Vec3 {
+            Vec3::new(self.next(), self.next(), self.next()) * (max - min) + min
+        }
+    }
+
+    fn random_spheres(random: &mut Random, count: usize) -> Vec<ShapeData> {
+        (0..count).map(|_| {
+            let mut shape = ShapeData::new();
+            shape.update_sphere(random.vec3(-20., 20.), 0.2 + 2. * random.next(), [1.; 3], 0., 0.5);
+            shape
+        }).collect()
+    }
+
+    // iSphere in shapes.wgsl
+    fn hit_sphere(shape: &ShapeData, ro: Vec3, rd: Vec3) -> Option<f32> {
+        let oc = ro - Vec4::from(shape.data1).xyz();
+        let radius = shape.data1[3];
+        let b = oc.dot(rd);
+        let h = b * b - (oc.length_squared() - radius * radius);
+        (h >= 0.).then(|| -b - h.sqrt())
+    }
+
+    // traceRay and traceOcc in light_map.wgsl, testing every shape
+    fn brute_force_ray(shapes: &[ShapeData], ro: Vec3, rd: Vec3, tmax: f32) -> Option<(f32, usize)> {
+        let mut result = None;
+        let mut tmax = tmax;
+        for (i, shape) in shapes.iter().enumerate() {
+            if let Some(t) = hit_sphere(shape, ro, rd).filter(|&t| t > 0. && t < tmax) {
+                tmax = t;
+                result = Some((t, i));
+            }
+        }
+        result
+    }
+
+    fn brute_force_occ(shapes: &[ShapeData], ro: Vec3, rd: Vec3, tmax: f32) -> bool {
+        shapes.iter().any(|shape| hit_sphere(shape, ro, rd).is_some_and(|t| t > 0. && t < tmax))
+    }
+
+    fn check_rays(bvh: &ShapeBVH, shapes: &[ShapeData], random: &mut Random) {
+        for _ in 0..500 {
+            let ro = random.vec3(-30., 30.);
+            let rd = (random.vec3(-20., 20.) - ro).normalize();
+            let tmax = 5. + 60. * random.next();
+            let hit = |shape: usize| hit_sphere(&shapes[shape], ro, rd);
+            assert_eq!(bvh.trace_ray(ro, rd, tmax, |p| p, hit), brute_force_ray(shapes, ro, rd, tmax));
+            assert_eq!(bvh.trace_occ(ro, rd, tmax, hit), brute_force_occ(shapes, ro, rd, tmax));
+        }
+    }
+
+    #[test]
+    fn every_shape_is_in_one_leaf() {
+        let mut random = Random(17);
+        for count in [1, 2, 5, 64, 1000] {
+            let mut shapes = random_spheres(&mut random, count);
+            let mut bvh = ShapeBVH::new();
+            bvh.build(&mut shapes);
+            let mut indices = bvh.indices().to_vec();
+            indices.sort_unstable();
+            assert_eq!(indices, (0..count as u32).collect::<Vec<_>>());
+            assert_eq!(bvh.nodes()[0].exit as usize, bvh.nodes().len());
+            for (i, node) in bvh.nodes().iter().enumerate().filter(|(_, node)| node.is_leaf()) {
+                assert_eq!(node.exit as usize, i + 1);
+                assert!(node.shapes().len() <= LEAF_SHAPES);
+            }
+        }
+    }
+
+    #[test]
+    fn traversal_matches_brute_force() {
+        let mut random = Random(1234);
+        for count in [1, 2, 3, 10, 100, 1000] {
+            let mut shapes = random_spheres(&mut random, count);
+            let mut bvh = ShapeBVH::new();
+            bvh.build(&mut shapes);
+            check_rays(&bvh, &shapes, &mut random);
+        }
+    }
+
+    #[test]
+    fn traversal_matches_brute_force_after_refit() {
+        let mut random = Random(99);
+        let mut shapes = random_spheres(&mut random, 300);
+        let mut bvh = ShapeBVH::new();
+        bvh.build(&mut shapes);
+        for _ in 0..5 {
+            let first = (random.next() * 250.) as usize;
+            for shape in &mut shapes[first..first + 50] {
+                shape.data1[0] += random.next() - 0.5;
+                shape.data1[2] += random.next() - 0.5;
+            }
+            if !bvh.refit(&shapes, std::slice::from_ref(&(first..first + 50))) {
+                bvh.build(&mut shapes);
+            }
+            check_rays(&bvh, &shapes, &mut random);
+        }
+    }
+
+    fn node_contains(node: &ShapeBVHNode, (min, max): (Vec3, Vec3)) -> bool {
+        let (a, b) = node.bounds();
+        a.cmple(min + 1e-4).all() && b.cmpge(max - 1e-4).all()
+    }
+
+    #[test]
+    fn refit_grows_the_nodes_above_a_moved_shape() {
+        let mut random = Random(7);
+        let mut shapes = random_spheres(&mut random, 200);
+        let mut bvh = ShapeBVH::new();
+        bvh.build(&mut shapes);
+        assert_eq!(bvh.take_dirty(), Some(0..bvh.nodes().len()));
+
+        shapes[42].data1[0] += 3.;
+        assert!(bvh.refit(&shapes, std::slice::from_ref(&(42..43))));
+        let dirty = bvh.take_dirty().unwrap();
+        let mut node = bvh.leaves[42] as usize;
+        assert!(dirty.contains(&node));
+        // Refitting stops at the first node that already bounds the shape, all above it do too
+        loop {
+            assert!(node_contains(&bvh.nodes()[node], shape_bounds(&shapes[42])));
+            match bvh.parents[node] {
+                Some(parent) => node = parent as usize,
+                None => break,
+            }
+        }
+        assert_eq!(node, 0);
+    }
+
+    #[test]
+    fn unchanged_shapes_dirty_nothing() {
+        let mut random = Random(8);
+        let mut shapes = random_spheres(&mut random, 50);
+        let mut bvh = ShapeBVH::new();
+        bvh.build(&mut shapes);
+        bvh.take_dirty();
+        assert!(bvh.refit(&shapes, std::slice::from_ref(&(0..50))));
+        assert_eq!(bvh.take_dirty(), None);
+    }
+
+    #[test]
+    fn refit_asks_for_a_rebuild() {
+        let mut random = Random(9);
+        let mut shapes = random_spheres(&mut random, 100);
+        let mut bvh = ShapeBVH::new();
+        bvh.build(&mut shapes);
+        // Other shapes than the tree was built over
+        let mut more = random_spheres(&mut random, 101);
+        assert!(!bvh.refit(&more, &[]));
+        bvh.build(&mut more);
+        assert!(bvh.refit(&more, &[]));
+        // Scattering the shapes blows up the nodes far beyond SAH_REBUILD_RATIO
+        for shape in &mut more {
+            shape.data1[0] *= 20.;
+        }
+        assert!(!bvh.refit(&more, std::slice::from_ref(&(0..101))));
+        bvh.build(&mut more);
+        assert!(bvh.refit(&more, &[]));
+    }
+
+    // Shape 0 used to be stored as entry 0, which the traversal took for a jump to the first node
+    #[test]
+    fn first_shape_is_hit() {
+        let mut random = Random(5);
+        let mut shapes = random_spheres(&mut random, 20);
+        shapes[0].update_sphere(Vec3::new(100., 0., 0.), 1., [1.; 3], 0., 0.5);
+        let mut bvh = ShapeBVH::new();
+        bvh.build(&mut shapes);
+        let ro = Vec3::new(100., 0., 10.);
+        let hit = |shape: usize| hit_sphere(&shapes[shape], ro, -Vec3::Z);
+        assert_eq!(bvh.trace_ray(ro, -Vec3::Z, 100., |p| p, hit), Some((9., 0)));
+        assert!(bvh.trace_occ(ro, -Vec3::Z, 100., hit));
+    }
+}
@@ -0,0 +1,489 @@
+// Shape primitives shared by the G-buffer and light map passes, prepended to both shaders when
+// they are created
+
+struct ShapeData {
+    data0: vec4<u32>,
+    data1: vec4<f32>,
+    data2: vec4<f32>,
+    // Orientation quaternion and scale around the position in `data1`
+    rotation: vec4<f32>,
+    scale: vec4<f32>,
+    // Linear color and intensity of the emitted radiance
+    emissive: vec4<f32>,
+};
+
+// Keep in sync with the constants in shape.rs
+const SHAPE_SPHERE: u32 = 0u;
+const SHAPE_ROUNDED_CONE: u32 = 1u;
+const SHAPE_BOX: u32 = 2u;
+const SHAPE_ROUNDED_BOX: u32 = 3u;
+const SHAPE_CAPSULE: u32 = 4u;
+const SHAPE_CYLINDER: u32 = 5u;
+const SHAPE_TORUS: u32 = 6u;
+const SHAPE_ELLIPSOID: u32 = 7u;
+
+const kMaxRayDistance: f32 = 1e20;
+
+// The intersectors are mirrored on the CPU for picking, keep in sync with intersect.rs
+fn iSphere(ro: vec3<f32>, rd: vec3<f32>, radius: f32) -> f32 {
+    let b = dot(rd, ro);
+    let c = dot(ro, ro) - (radius * radius);
+    let h = b * b - c;
+    if h < 0.0 {
+        return kMaxRayDistance;
+    }
+    return -b - sqrt(h);
+}
+
+fn nSphere(pos: vec3<f32>) -> vec3<f32> {
+    return normalize(pos);
+}
+
+// https://www.shadertoy.com/view/MlKfzm
+fn iRoundedCone(ro: vec3<f32>, rd: vec3<f32>, pa: vec3<f32>, pb: vec3<f32>, ra: f32, rb: f32) -> vec4<f32> {
+    let ba = pb - pa;
+    let oa = ro - pa;
+    let ob = ro - pb;
+    let rr = ra - rb;
+    let m0 = dot(ba, ba);
+    let m1 = dot(ba, oa);
+    let m2 = dot(ba, rd);
+    let m3 = dot(rd, oa);
+    let m5 = dot(oa, oa);
+    let m6 = dot(ob, rd);
+    let m7 = dot(ob, ob);
+
+    let d2 = m0 - rr * rr;
+
+    let k2 = d2 - m2 * m2;
+    let k1 = d2 * m3 - m1 * m2 + m2 * rr * ra;
+    let k0 = d2 * m5 - m1 * m1 + m1 * rr * ra * 2.0 - m0 * ra * ra;
+
+    let h = k1 * k1 - k0 * k2;
+    if h < 0.0 {
+        return vec4<f32>(kMaxRayDistance);
+    }
+    var t = (-sqrt(h) - k1) / k2;
+
+    let y = m1 - ra * rr + t * m2;
+    if y > 0.0 && y < d2 {
+        return vec4<f32>(t, normalize(d2 * (oa + t * rd) - ba * y));
+    }
+
+    let h1 = m3 * m3 - m5 + ra * ra;
+    let h2 = m6 * m6 - m7 + rb * rb;
+    if max(h1, h2) < 0.0 {
+        return vec4<f32>(kMaxRayDistance);
+    }
+
+    var r = vec4<f32>(kMaxRayDistance);
+    if h1 > 0.0 {
+        t = -m3 - sqrt(h1);
+        r = vec4<f32>(t, (oa + t * rd) / ra);
+    }
+    if h2 > 0.0 {
+        t = -m6 - sqrt(h2);
+        if t < r.x {
+            r = vec4<f32>(t, (ob + t * rd) / rb);
+        }
+    }
+
+    return r;
+}
+
+// https://iquilezles.org/articles/intersectors/
+fn iBox(ro: vec3<f32>, rd: vec3<f32>, rad: vec3<f32>) -> vec4<f32> {
+    let m = 1. / rd;
+    let n = m * ro;
+    let k = abs(m) * rad;
+    let t1 = -n - k;
+    let t2 = -n + k;
+    let tN = max(max(t1.x, t1.y), t1.z);
+    let tF = min(min(t2.x, t2.y), t2.z);
+    if tN > tF || tF < 0. {
+        return vec4<f32>(kMaxRayDistance);
+    }
+    return vec4<f32>(tN, -sign(rd) * step(t1.yzx, t1.xyz) * step(t1.zxy, t1.xyz));
+}
+
+// Box of half size `size` grown by `rad`, https://iquilezles.org/articles/intersectors/
+fn iRoundedBox(ro_in: vec3<f32>, rd_in: vec3<f32>, size: vec3<f32>, rad: f32) -> vec4<f32> {
+    let m = 1. / rd_in;
+    let n = m * ro_in;
+    let k = abs(m) * (size + rad);
+    let t1 = -n - k;
+    let t2 = -n + k;
+    let tN = max(max(t1.x, t1.y), t1.z);
+    let tF = min(min(t2.x, t2.y), t2.z);
+    if tN > tF || tF < 0. {
+        return vec4<f32>(kMaxRayDistance);
+    }
+    var t = tN;
+
+    // Mirror into the first octant
+    var pos = ro_in + t * rd_in;
+    let s = sign(pos);
+    let ro = ro_in * s;
+    let rd = rd_in * s;
+    pos = pos * s;
+
+    // Faces
+    pos = pos - size;
+    pos = max(pos.xyz, pos.yzx);
+    if min(min(pos.x, pos.y), pos.z) < 0. {
+        return vec4<f32>(t, nRoundedBox(ro_in + t * rd_in, size));
+    }
+
+    let oc = ro - size;
+    let dd = rd * rd;
+    let oo = oc * oc;
+    let od = oc * rd;
+    let ra2 = rad * rad;
+
+    t = kMaxRayDistance;
+
+    // Corner
+    {
+        let b = od.x + od.y + od.z;
+        let c = oo.x + oo.y + oo.z - ra2;
+        let h = b * b - c;
+        if h > 0. {
+            t = -b - sqrt(h);
+        }
+    }
+    // Edges along x, y and z
+    {
+        let a = dd.y + dd.z;
+        let b = od.y + od.z;
+        let c = oo.y + oo.z - ra2;
+        var h = b * b - a * c;
+        if h > 0. {
+            h = (-b - sqrt(h)) / a;
+            if h > 0. && h < t && abs(ro.x + rd.x * h) < size.x {
+                t = h;
+            }
+        }
+    }
+    {
+        let a = dd.z + dd.x;
+        let b = od.z + od.x;
+        let c = oo.z + oo.x - ra2;
+        var h = b * b - a * c;
+        if h > 0. {
+            h = (-b - sqrt(h)) / a;
+            if h > 0. && h < t && abs(ro.y + rd.y * h) < size.y {
+                t = h;
+            }
+        }
+    }
+    {
+        let a = dd.x + dd.y;
+        let b = od.x + od.y;
+        let c = oo.x + oo.y - ra2;
+        var h = b * b - a * c;
+        if h > 0. {
+            h = (-b - sqrt(h)) / a;
+            if h > 0. && h < t && abs(ro.z + rd.z * h) < size.z {
+                t = h;
+            }
+        }
+    }
+
+    if t >= kMaxRayDistance {
+        return vec4<f32>(kMaxRayDistance);
+    }
+    return vec4<f32>(t, nRoundedBox(ro_in + t * rd_in, size));
+}
+
+fn nRoundedBox(pos: vec3<f32>, size: vec3<f32>) -> vec3<f32> {
+    return sign(pos) * normalize(max(abs(pos) - size, vec3<f32>(0.)));
+}
+
+// https://iquilezles.org/articles/intersectors/
+fn iCapsule(ro: vec3<f32>, rd: vec3<f32>, pa: vec3<f32>, pb: vec3<f32>, ra: f32) -> vec4<f32> {
+    let ba = pb - pa;
+    let oa = ro - pa;
+    let baba = dot(ba, ba);
+    let bard = dot(ba, rd);
+    let baoa = dot(ba, oa);
+    let rdoa = dot(rd, oa);
+    let oaoa = dot(oa, oa);
+    let a = baba - bard * bard;
+    let b = baba * rdoa - baoa * bard;
+    let c = baba * oaoa - baoa * baoa - ra * ra * baba;
+    let h = b * b - a * c;
+    if h < 0. {
+        return vec4<f32>(kMaxRayDistance);
+    }
+    let t = (-b - sqrt(h)) / a;
+    let y = baoa + t * bard;
+    // Body
+    if y > 0. && y < baba {
+        return vec4<f32>(t, (oa + t * rd - ba * y / baba) / ra);
+    }
+    // Caps
+    let oc = select(ro - pb, oa, y <= 0.);
+    let bc = dot(rd, oc);
+    let cc = dot(oc, oc) - ra * ra;
+    let hc = bc * bc - cc;
+    if hc <= 0. {
+        return vec4<f32>(kMaxRayDistance);
+    }
+    let tc = -bc - sqrt(hc);
+    return vec4<f32>(tc, (oc + tc * rd) / ra);
+}
+
+// https://iquilezles.org/articles/intersectors/
+fn iCylinder(ro: vec3<f32>, rd: vec3<f32>, pa: vec3<f32>, pb: vec3<f32>, ra: f32) -> vec4<f32> {
+    let ba = pb - pa;
+    let oc = ro - pa;
+    let baba = dot(ba, ba);
+    let bard = dot(ba, rd);
+    let baoc = dot(ba, oc);
+    let k2 = baba - bard * bard;
+    let k1 = baba * dot(oc, rd) - baoc * bard;
+    let k0 = baba * dot(oc, oc) - baoc * baoc - ra * ra * baba;
+    var h = k1 * k1 - k2 * k0;
+    if h < 0. {
+        return vec4<f32>(kMaxRayDistance);
+    }
+    h = sqrt(h);
+    var t = (-k1 - h) / k2;
+    // Body
+    let y = baoc + t * bard;
+    if y > 0. && y < baba {
+        return vec4<f32>(t, (oc + t * rd - ba * y / baba) / ra);
+    }
+    // Caps
+    t = (select(baba, 0., y < 0.) - baoc) / bard;
+    if abs(k1 + k2 * t) < h {
+        return vec4<f32>(t, ba * sign(y) / sqrt(baba));
+    }
+    return vec4<f32>(kMaxRayDistance);
+}
+
+// Torus around the z axis with radii `tor.x` and `tor.y`, https://iquilezles.org/articles/intersectors/
+fn iTorus(ro: vec3<f32>, rd: vec3<f32>, tor: vec2<f32>) -> vec4<f32> {
+    var po = 1.;
+    let Ra2 = tor.x * tor.x;
+    let ra2 = tor.y * tor.y;
+    let m = dot(ro, ro);
+    let n = dot(ro, rd);
+
+    // Bounding sphere
+    if n * n - m + (tor.x + tor.y) * (tor.x + tor.y) < 0. {
+        return vec4<f32>(kMaxRayDistance);
+    }
+
+    // Quartic equation
+    let k = (m - ra2 - Ra2) / 2.;
+    var k3 = n;
+    var k2 = n * n + Ra2 * rd.z * rd.z + k;
+    var k1 = k * n + Ra2 * ro.z * rd.z;
+    var k0 = k * k + Ra2 * ro.z * ro.z - Ra2 * ra2;
+
+    // Keep |c1| away from zero
+    if abs(k3 * (k3 * k3 - k2) + k1) < 0.01 {
+        po = -1.;
+        let tmp = k1;
+        k1 = k3;
+        k3 = tmp;
+        k0 = 1. / k0;
+        k1 = k1 * k0;
+        k2 = k2 * k0;
+        k3 = k3 * k0;
+    }
+
+    var c2 = 2. * k2 - 3. * k3 * k3;
+    var c1 = k3 * (k3 * k3 - k2) + k1;
+    var c0 = k3 * (k3 * (-3. * k3 * k3 + 4. * k2) - 8. * k1) + 4. * k0;
+    c2 = c2 / 3.;
+    c1 = c1 * 2.;
+    c0 = c0 / 3.;
+    let Q = c2 * c2 + c0;
+    let R = 3. * c0 * c2 - c2 * c2 * c2 - c1 * c1;
+    var h = R * R - Q * Q * Q;
+    var z: f32;
+    if h < 0. {
+        // 4 intersections
+        let sQ = sqrt(Q);
+        z = 2. * sQ * cos(acos(R / (sQ * Q)) / 3.);
+    } else {
+        // 2 intersections
+        let sQ = pow(sqrt(h) + abs(R), 1. / 3.);
+        z = sign(R) * abs(sQ + Q / sQ);
+    }
+    z = c2 - z;
+    var d1 = z - 3. * c2;
+    var d2 = z * z - 3. * c0;
+    if abs(d1) < 1e-4 {
+        if d2 < 0. {
+            return vec4<f32>(kMaxRayDistance);
+        }
+        d2 = sqrt(d2);
+    } else {
+        if d1 < 0. {
+            return vec4<f32>(kMaxRayDistance);
+        }
+        d1 = sqrt(d1 / 2.);
+        d2 = c1 / d1;
+    }
+
+    var result = kMaxRayDistance;
+    h = d1 * d1 - z + d2;
+    if h > 0. {
+        h = sqrt(h);
+        var t1 = -d1 - h - k3;
+        var t2 = -d1 + h - k3;
+        if po < 0. {
+            t1 = 2. / t1;
+            t2 = 2. / t2;
+        }
+        if t1 > 0. {
+            result = t1;
+        }
+        if t2 > 0. {
+            result = min(result, t2);
+        }
+    }
+    h = d1 * d1 - z - d2;
+    if h > 0. {
+        h = sqrt(h);
+        var t1 = d1 - h - k3;
+        var t2 = d1 + h - k3;
+        if po < 0. {
+            t1 = 2. / t1;
+            t2 = 2. / t2;
+        }
+        if t1 > 0. {
+            result = min(result, t1);
+        }
+        if t2 > 0. {
+            result = min(result, t2);
+        }
+    }
+    let pos = ro + result * rd;
+    return vec4<f32>(result, normalize(pos * (dot(pos, pos) - ra2 - Ra2 * vec3<f32>(1., 1., -1.))));
+}
+
+// https://iquilezles.org/articles/intersectors/
+fn iEllipsoid(ro: vec3<f32>, rd: vec3<f32>, ra: vec3<f32>) -> vec4<f32> {
+    let ocn = ro / ra;
+    let rdn = rd / ra;
+    let a = dot(rdn, rdn);
+    let b = dot(ocn, rdn);
+    let c = dot(ocn, ocn);
+    let h = b * b - a * (c - 1.);
+    if h < 0. {
+        return vec4<f32>(kMaxRayDistance);
+    }
+    let t = (-b - sqrt(h)) / a;
+    return vec4<f32>(t, normalize((ro + t * rd) / (ra * ra)));
+}
+
+// Nearest hit with the untransformed primitive as (t, normal), `ro` is relative to the shape's
+// position in `data1`
+fn intersectPrimitive(shape: ShapeData, ro: vec3<f32>, rd: vec3<f32>) -> vec4<f32> {
+    switch shape.data0.x {
+        case SHAPE_SPHERE: {
+            let t = iSphere(ro, rd, shape.data1.w);
+            return vec4<f32>(t, nSphere(ro + t * rd));
+        }
+        case SHAPE_ROUNDED_CONE: {
+            return iRoundedCone(ro, rd, vec3<f32>(0.), shape.data2.xyz - shape.data1.xyz, shape.data1.w, shape.data2.w);
+        }
+        case SHAPE_BOX: {
+            return iBox(ro, rd, shape.data2.xyz);
+        }
+        case SHAPE_ROUNDED_BOX: {
+            return iRoundedBox(ro, rd, max(shape.data2.xyz - shape.data1.w, vec3<f32>(0.)), shape.data1.w);
+        }
+        case SHAPE_CAPSULE: {
+            return iCapsule(ro, rd, vec3<f32>(0.), shape.data2.xyz - shape.data1.xyz, shape.data1.w);
+        }
+        case SHAPE_CYLINDER: {
+            return iCylinder(ro, rd, vec3<f32>(0.), shape.data2.xyz - shape.data1.xyz, shape.data1.w);
+        }
+        case SHAPE_TORUS: {
+            return iTorus(ro, rd, vec2<f32>(shape.data1.w, shape.data2.x));
+        }
+        case SHAPE_ELLIPSOID: {
+            return iEllipsoid(ro, rd, shape.data2.xyz);
+        }
+        default: {
+            return vec4<f32>(kMaxRayDistance);
+        }
+    }
+}
+
+fn quatRotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    let t = 2. * cross(q.xyz, v);
+    return v + q.w * t + cross(q.xyz, t);
+}
+
+fn quatConjugate(q: vec4<f32>) -> vec4<f32> {
+    return vec4<f32>(-q.xyz, q.w);
+}
+
+// Nearest hit with `shape` as (t, normal) in world space, `ro` is relative to the shape's position
+// in `data1`. The ray is intersected with the primitive in object space.
+fn intersectShape(shape: ShapeData, ro: vec3<f32>, rd: vec3<f32>) -> vec4<f32> {
+    let inv_rotation = quatConjugate(shape.rotation);
+    let ro_local = quatRotate(inv_rotation, ro) / shape.scale.xyz;
+    let rd_scaled = quatRotate(inv_rotation, rd) / shape.scale.xyz;
+    let rd_length = length(rd_scaled);
+    let tnor = intersectPrimitive(shape, ro_local, rd_scaled / rd_length);
+    if tnor.x >= kMaxRayDistance {
+        return tnor;
+    }
+    // Normals transform with the inverse transpose, the rotation stays and the scale inverts
+    let normal = normalize(quatRotate(shape.rotation, tnor.yzw / shape.scale.xyz));
+    return vec4<f32>(tnor.x / rd_length, normal);
+}
+
+struct ShapeBounds {
+    min: vec3<f32>,
+    max: vec3<f32>,
+}
+
+// Object space bounds relative to the shape's position, like `local_bounds` in shape.rs
+fn localShapeBounds(shape: ShapeData) -> ShapeBounds {
+    let p = vec3<f32>(0.);
+    let q = shape.data2.xyz - shape.data1.xyz;
+    switch shape.data0.x {
+        case SHAPE_ROUNDED_CONE: {
+            return ShapeBounds(min(p - shape.data1.w, q - shape.data2.w), max(p + shape.data1.w, q + shape.data2.w));
+        }
+        case SHAPE_BOX, SHAPE_ROUNDED_BOX, SHAPE_ELLIPSOID: {
+            return ShapeBounds(p - shape.data2.xyz, p + shape.data2.xyz);
+        }
+        case SHAPE_CAPSULE: {
+            return ShapeBounds(min(p, q) - shape.data1.w, max(p, q) + shape.data1.w);
+        }
+        case SHAPE_CYLINDER: {
+            let axis = q / max(length(q), 1e-6);
+            let e = shape.data1.w * sqrt(max(vec3<f32>(1.) - axis * axis, vec3<f32>(0.)));
+            return ShapeBounds(min(p, q) - e, max(p, q) + e);
+        }
+        case SHAPE_TORUS: {
+            let r = vec3<f32>(vec2<f32>(shape.data1.w + shape.data2.x), shape.data2.x);
+            return ShapeBounds(p - r, p + r);
+        }
+        default: {
+            return ShapeBounds(p - shape.data1.w, p + shape.data1.w);
+        }
+    }
+}
+
+// Same bounds as `Bounded::aabb` in shape.rs
+fn shapeBounds(shape: ShapeData) -> ShapeBounds {
+    let local = localShapeBounds(shape);
+    let center = 0.5 * (local.min + local.max) * shape.scale.xyz;
+    let half_size = 0.5 * (local.max - local.min) * abs(shape.scale.xyz);
+    let x = abs(quatRotate(shape.rotation, vec3<f32>(1., 0., 0.)));
+    let y = abs(quatRotate(shape.rotation, vec3<f32>(0., 1., 0.)));
+    let z = abs(quatRotate(shape.rotation, vec3<f32>(0., 0., 1.)));
+    let world_center = shape.data1.xyz + quatRotate(shape.rotation, center);
+    let world_half_size = x * half_size.x + y * half_size.y + z * half_size.z;
+    return ShapeBounds(world_center - world_half_size, world_center + world_half_size);
+}
@@ -9,6 +9,7 @@ struct Uniforms {
     cursor_size: f32,
     time: f32,
     exposure: f32,
+    topology: u32,
 };
 
 const flt_taa_anti_sparkle = 0.1; // TODO move to uniforms
@@ -0,0 +1,81 @@
+// Terrain height field shared by the shaders that place things in 3D. Prepended to them, it
+// only needs the including shader to pass in the terrain it samples.
+
+const BEVEL_CHAMFER: u32 = 1u;
+const BEVEL_ROUND: u32 = 2u;
+const BEVEL_SMOOTH: u32 = 3u;
+
+// World units between the ground and the top of the terrain, the whole depth range
+const TERRAIN_HEIGHT: f32 = 4.;
+// World z of the ground, the depth range is centered on z = 0
+const GROUND_Z: f32 = -0.5 * TERRAIN_HEIGHT;
+
+// The ground is at the bottom of the depth range, the full terrain height at the top
+fn world_to_depth(z: f32) -> f32 {
+    return saturate(1. - (z - GROUND_Z) / TERRAIN_HEIGHT);
+}
+
+fn depth_to_world(depth: f32) -> f32 {
+    return GROUND_Z + (1. - depth) * TERRAIN_HEIGHT;
+}
+
+// Top of the terrain above the ground, from the height channel of the SDF. The channel is the
+// offset from the full terrain height in units of it, brushes raise and lower it in -1..0.
+fn terrainTop(height: f32) -> f32 {
+    return (1. + clamp(height, -1., 0.)) * TERRAIN_HEIGHT;
+}
+
+// Bevel profile `bevel` (width, profile) at `dist` inside the terrain: the fraction of the top
+// it rises to and its slope along the depth into the terrain, as a numerator and denominator so
+// that vertical walls stay finite
+fn bevelProfile(bevel: vec4<f32>, dist: f32) -> vec3<f32> {
+    let width = bevel.x;
+    let x = -dist / max(width, 1e-6);
+    if width <= 0. || x >= 1. {
+        return vec3<f32>(1., 0., 1.);
+    }
+    switch u32(bevel.y + 0.5) {
+        case BEVEL_CHAMFER: {
+            return vec3<f32>(x, 1., 1.);
+        }
+        case BEVEL_ROUND: {
+            let s = 1. - x;
+            let c = sqrt(1. - s * s);
+            return vec3<f32>(c, s, c);
+        }
+        case BEVEL_SMOOTH: {
+            return vec3<f32>(x * x * (3. - 2. * x), 6. * x * (1. - x), 1.);
+        }
+        default: {
+            return vec3<f32>(1., 0., 1.);
+        }
+    }
+}
+
+// Height of the terrain above the ground. Open space is ground, inside the terrain the bevel
+// profile rises from the ground at the edge to `top` a bevel width further in.
+fn terrainHeight(bevel: vec4<f32>, dist: f32, top: f32) -> f32 {
+    if dist >= 0. {
+        return 0.;
+    }
+    return top * bevelProfile(bevel, dist).x;
+}
+
+struct Surface {
+    // Above the ground
+    height: f32,
+    normal: vec3<f32>,
+}
+
+// `terrainHeight` with its normal, `outward` is the direction of the distance gradient and
+// `top_gradient` the gradient of `top`
+fn terrainSurface(bevel: vec4<f32>, dist: f32, outward: vec2<f32>, top: f32, top_gradient: vec2<f32>) -> Surface {
+    if dist >= 0. {
+        return Surface(0., vec3<f32>(0., 0., 1.));
+    }
+    let profile = bevelProfile(bevel, dist);
+    // The profile rises against `outward`, the top scales all of it
+    let rise = top / max(bevel.x, 1e-6) * profile.y * outward;
+    let normal = vec3<f32>(rise - profile.x * profile.z * top_gradient, profile.z);
+    return Surface(top * profile.x, normalize(normal));
+}
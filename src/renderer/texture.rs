@@ -2,6 +2,7 @@ use glam::UVec2;
 use image::GenericImageView;
 
 pub struct Texture {
+    pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub size: wgpu::Extent3d
 }
@@ -51,6 +52,7 @@ impl Texture {
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
         Self {
+            texture,
             view,
             size,
         }
@@ -89,6 +91,7 @@ impl Texture {
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
 
         Self {
+            texture,
             view,
             size,
         }
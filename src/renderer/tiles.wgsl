@@ -0,0 +1,42 @@
+// Resident terrain tiles shared by the renderer, G-buffer, light map and edit passes, prepended to
+// their shaders after topology.wgsl. Every one of them binds the SDF window and its tile
+// indirection in group 1, and provides `boundDist`. Keep in sync with `TerrainUniforms` and the
+// indirection texture in sdf/tiles.rs.
+
+@group(1) @binding(0)
+var t_sdf: texture_2d<f32>;
+@group(1) @binding(1)
+var s_sdf: sampler;
+
+struct Terrain {
+    inv_window_size: vec2<f32>,
+    inv_tile_size: vec2<f32>,
+    tiles: vec2<i32>,
+    world_tiles: vec2<i32>,
+    empty_dist: f32,
+    topology: u32,
+}
+@group(1) @binding(2)
+var<uniform> terrain: Terrain;
+@group(1) @binding(3)
+var t_tiles: texture_2d<i32>;
+
+fn unpackSdf(v: f32) -> f32 {
+    return v;
+}
+
+// Texels are only valid if their slot holds the tile at `world_pos`, anything else is empty space
+fn terrainResident(world_pos: vec2<f32>) -> bool {
+    let t = vec2<i32>(floor(vec2<f32>(world_pos.x, -world_pos.y) * terrain.inv_tile_size + 0.5 * vec2<f32>(terrain.tiles)));
+    let slot = ((t % terrain.tiles) + terrain.tiles) % terrain.tiles;
+    let tile = ((t % terrain.world_tiles) + terrain.world_tiles) % terrain.world_tiles;
+    return all(textureLoad(t_tiles, slot, 0).xy == tile);
+}
+
+fn sceneDist(world_pos: vec2<f32>) -> f32 {
+    var uv = world_pos * terrain.inv_window_size;
+    uv.y = -uv.y;
+    uv = uv + 0.5;
+    let dist = unpackSdf(textureSample(t_sdf, s_sdf, uv).r);
+    return boundDist(world_pos, select(terrain.empty_dist, dist, terrainResident(world_pos)));
+}
@@ -0,0 +1,38 @@
+// World topology shared by the renderer, G-buffer and light map passes, prepended to their
+// shaders when they are created. Every one of them declares `uniforms` with the world size and
+// topology. Keep in sync with `Topology` in sdf/tiles.rs.
+
+const TOPOLOGY_TORUS: u32 = 0u;
+const TOPOLOGY_CLAMPED: u32 = 1u;
+const TOPOLOGY_BOUNDED: u32 = 2u;
+
+// Only a torus wraps, worlds with edges do not repeat
+fn wrap(p: vec2<f32>) -> vec2<f32> {
+    if uniforms.topology != TOPOLOGY_TORUS {
+        return p;
+    }
+    let s = ceil(abs(p * uniforms.inv_world_size)) + 0.5;
+    return (p + s * uniforms.world_size) % uniforms.world_size - 0.5 * uniforms.world_size;
+}
+
+fn wrap3(p: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(wrap(p.xy), p.z);
+}
+
+// Applies the edges of a world that does not wrap, empty or solid beyond them
+fn boundDist(world_pos: vec2<f32>, dist: f32) -> f32 {
+    let q = abs(world_pos) - 0.5 * uniforms.world_size;
+    let edge = length(max(q, vec2<f32>(0.))) + min(max(q.x, q.y), 0.);
+    switch uniforms.topology {
+        case TOPOLOGY_CLAMPED: {
+            return select(dist, edge, edge > 0.);
+        }
+        case TOPOLOGY_BOUNDED: {
+            return min(dist, -edge);
+        }
+        default: {
+            return dist;
+        }
+    }
+}
+
@@ -0,0 +1,200 @@
+use glam::*;
+
+pub const MAX_POLYGON_POINTS: usize = 16;
+
+const BRUSH_CIRCLE: u32 = 0;
+const BRUSH_BOX: u32 = 1;
+const BRUSH_CAPSULE: u32 = 2;
+const BRUSH_NGON: u32 = 3;
+const BRUSH_POLYGON: u32 = 4;
+const BRUSH_ANNULUS: u32 = 5;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BrushKind {
+    Circle,
+    Box,
+    Capsule,
+    NGon,
+    Polygon,
+    Annulus,
+}
+
+impl BrushKind {
+    pub const ALL: [BrushKind; 6] = [
+        BrushKind::Circle,
+        BrushKind::Box,
+        BrushKind::Capsule,
+        BrushKind::NGon,
+        BrushKind::Polygon,
+        BrushKind::Annulus,
+    ];
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum BrushShape {
+    Circle { radius: f32 },
+    Box { half_size: Vec2 },
+    Capsule { half_length: f32, radius: f32 },
+    NGon { sides: u32, radius: f32 },
+    // Convex polygon in brush local coordinates, at most MAX_POLYGON_POINTS points
+    Polygon { points: Vec<Vec2> },
+    Annulus { radius: f32, thickness: f32 },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Brush {
+    pub shape: BrushShape,
+    pub rotation: f32,
+    pub smoothness: f32,
+    // Palette index painted on the terrain the brush adds, see `editedMaterial` in sdf.wgsl
+    pub material: u32,
+    // Change of the terrain height a raise or lower stroke makes at its center, in units of the
+    // full terrain height
+    pub height: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct BrushData {
+    pub kind: u32,
+    pub num_points: u32,
+    pub rotation: f32,
+    pub smoothness: f32,
+    pub params: [f32; 4],
+    pub points: [[f32; 4]; MAX_POLYGON_POINTS / 2],
+}
+
+impl Default for BrushData {
+    fn default() -> Self {
+        Brush::circle(10.0, 1.0).to_data()
+    }
+}
+
+impl Brush {
+    pub fn circle(radius: f32, smoothness: f32) -> Self {
+        Self {
+            shape: BrushShape::Circle { radius },
+            rotation: 0.0,
+            smoothness,
+            material: 0,
+            height: 0.1,
+        }
+    }
+
+    pub fn bounding_radius(&self) -> f32 {
+        let r = match &self.shape {
+            BrushShape::Circle { radius } => *radius,
+            BrushShape::Box { half_size } => half_size.length(),
+            BrushShape::Capsule { half_length, radius } => half_length + radius,
+            BrushShape::NGon { radius, .. } => *radius,
+            BrushShape::Polygon { points } => points.iter().fold(0.0f32, |r, p| r.max(p.length())),
+            BrushShape::Annulus { radius, thickness } => radius + 0.5 * thickness,
+        };
+        r + self.smoothness
+    }
+
+    pub fn stroke_spacing(&self) -> f32 {
+        let feature_size = match &self.shape {
+            BrushShape::Circle { radius } => *radius,
+            BrushShape::Box { half_size } => half_size.min_element(),
+            BrushShape::Capsule { radius, .. } => *radius,
+            BrushShape::NGon { radius, .. } => *radius,
+            BrushShape::Polygon { points } => 0.5 * points.iter().fold(0.0f32, |r, p| r.max(p.length())),
+            BrushShape::Annulus { thickness, .. } => 0.5 * thickness,
+        };
+        (0.5 * feature_size + 0.25 * self.smoothness).max(1e-3)
+    }
+
+    pub fn to_data(&self) -> BrushData {
+        let mut data = BrushData {
+            kind: BRUSH_CIRCLE,
+            num_points: 0,
+            rotation: self.rotation,
+            smoothness: self.smoothness.max(1e-4),
+            params: [0.0; 4],
+            points: [[0.0; 4]; MAX_POLYGON_POINTS / 2],
+        };
+        match &self.shape {
+            BrushShape::Circle { radius } => {
+                data.kind = BRUSH_CIRCLE;
+                data.params[0] = *radius;
+            }
+            BrushShape::Box { half_size } => {
+                data.kind = BRUSH_BOX;
+                data.params[0] = half_size.x;
+                data.params[1] = half_size.y;
+            }
+            BrushShape::Capsule { half_length, radius } => {
+                data.kind = BRUSH_CAPSULE;
+                data.params[0] = *half_length;
+                data.params[1] = *radius;
+            }
+            BrushShape::NGon { sides, radius } => {
+                data.kind = BRUSH_NGON;
+                data.params[0] = *radius;
+                data.params[1] = (*sides).max(3) as f32;
+            }
+            BrushShape::Polygon { points } => {
+                data.kind = BRUSH_POLYGON;
+                data.num_points = points.len().min(MAX_POLYGON_POINTS) as u32;
+                for (i, p) in points.iter().take(MAX_POLYGON_POINTS).enumerate() {
+                    data.points[i / 2][2 * (i % 2)] = p.x;
+                    data.points[i / 2][2 * (i % 2) + 1] = p.y;
+                }
+            }
+            BrushShape::Annulus { radius, thickness } => {
+                data.kind = BRUSH_ANNULUS;
+                data.params[0] = *radius;
+                data.params[1] = 0.5 * thickness;
+            }
+        }
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polygon_points_are_packed_in_pairs() {
+        let points: Vec<Vec2> = (0..5).map(|i| Vec2::new(i as f32, -(i as f32))).collect();
+        let brush = Brush { shape: BrushShape::Polygon { points }, rotation: 0., smoothness: 0., material: 0, height: 0. };
+        let data = brush.to_data();
+        assert_eq!(data.kind, BRUSH_POLYGON);
+        assert_eq!(data.num_points, 5);
+        assert_eq!(data.points[0], [0., 0., 1., -1.]);
+        assert_eq!(data.points[1], [2., -2., 3., -3.]);
+        assert_eq!(data.points[2], [4., -4., 0., 0.]);
+    }
+
+    #[test]
+    fn polygons_are_capped_at_max_points() {
+        let points = vec![Vec2::ONE; MAX_POLYGON_POINTS + 3];
+        let brush = Brush { shape: BrushShape::Polygon { points }, rotation: 0., smoothness: 0., material: 0, height: 0. };
+        assert_eq!(brush.to_data().num_points, MAX_POLYGON_POINTS as u32);
+    }
+
+    #[test]
+    fn shapes_fill_their_parameters() {
+        let brush = |shape| Brush { shape, rotation: 0.5, smoothness: 0., material: 0, height: 0. };
+        let data = brush(BrushShape::Annulus { radius: 4., thickness: 2. }).to_data();
+        assert_eq!((data.kind, data.params[0], data.params[1]), (BRUSH_ANNULUS, 4., 1.));
+        let data = brush(BrushShape::NGon { sides: 1, radius: 3. }).to_data();
+        assert_eq!((data.kind, data.params[0], data.params[1]), (BRUSH_NGON, 3., 3.));
+        let data = brush(BrushShape::Capsule { half_length: 2., radius: 1. }).to_data();
+        assert_eq!((data.kind, data.params[0], data.params[1], data.rotation), (BRUSH_CAPSULE, 2., 1., 0.5));
+        // Zero smoothness would divide by zero in the smooth union
+        assert!(data.smoothness > 0.);
+    }
+
+    #[test]
+    fn bounding_radius_covers_the_shape_and_smoothing() {
+        let brush = Brush { shape: BrushShape::Box { half_size: Vec2::new(3., 4.) }, rotation: 1., smoothness: 2., material: 0, height: 0. };
+        assert_eq!(brush.bounding_radius(), 7.);
+        let brush = Brush { shape: BrushShape::Capsule { half_length: 2., radius: 1. }, rotation: 0., smoothness: 0., material: 0, height: 0. };
+        assert_eq!(brush.bounding_radius(), 3.);
+        let brush = Brush { shape: BrushShape::Annulus { radius: 4., thickness: 2. }, rotation: 0., smoothness: 0.5, material: 0, height: 0. };
+        assert_eq!(brush.bounding_radius(), 5.5);
+    }
+}
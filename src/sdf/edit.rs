@@ -0,0 +1,104 @@
+use glam::Vec2;
+
+use super::brush::Brush;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EditOp {
+    Add,
+    Subtract,
+    // Change the height channel by the brush's `height`, see `main_frag_height` in sdf.wgsl
+    Raise,
+    Lower,
+}
+
+// A brush stroke segment from `from` to `to`, applied in the order it was queued
+#[derive(Debug, PartialEq, Clone)]
+pub struct Edit {
+    pub op: EditOp,
+    pub from: Vec2,
+    pub to: Vec2,
+    pub brush: Brush,
+}
+
+impl Edit {
+    pub fn new(op: EditOp, from: Vec2, to: Vec2, brush: &Brush) -> Self {
+        Self {
+            op,
+            from,
+            to,
+            brush: brush.clone(),
+        }
+    }
+
+    // Change of the height channel at the center of the stroke, only raising and lowering
+    // touch it
+    pub fn height_change(&self) -> f32 {
+        match self.op {
+            EditOp::Raise => self.brush.height,
+            EditOp::Lower => -self.brush.height,
+            EditOp::Add | EditOp::Subtract => 0.,
+        }
+    }
+
+    // The stroke cut into equal pieces of at most `max_length`, `delta` is the offset from
+    // `from` to `to` as the stroke travels it
+    pub fn split(&self, delta: Vec2, max_length: f32) -> Vec<Edit> {
+        let pieces = (delta.length() / max_length).ceil().max(1.) as usize;
+        let start = self.to - delta;
+        (0..pieces)
+            .map(|i| Edit {
+                from: start + delta * (i as f32 / pieces as f32),
+                to: start + delta * ((i + 1) as f32 / pieces as f32),
+                ..self.clone()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_pieces_join_up() {
+        let brush = Brush::circle(1., 0.);
+        let edit = Edit::new(EditOp::Add, Vec2::new(-10., 2.), Vec2::new(30., 2.), &brush);
+        let pieces = edit.split(edit.to - edit.from, 3.);
+        assert_eq!(pieces.len(), 14);
+        assert_eq!(pieces[0].from, edit.from);
+        assert!((pieces[13].to - edit.to).length() < 1e-4);
+        for pair in pieces.windows(2) {
+            assert_eq!(pair[0].to, pair[1].from);
+        }
+        assert!(pieces.iter().all(|piece| (piece.to - piece.from).length() <= 3.));
+    }
+
+    #[test]
+    fn only_raising_and_lowering_change_the_height() {
+        let brush = Brush { height: 0.25, ..Brush::circle(1., 0.) };
+        let change = |op| Edit::new(op, Vec2::ZERO, Vec2::ONE, &brush).height_change();
+        assert_eq!(change(EditOp::Raise), 0.25);
+        assert_eq!(change(EditOp::Lower), -0.25);
+        assert_eq!(change(EditOp::Add), 0.);
+        assert_eq!(change(EditOp::Subtract), 0.);
+    }
+
+    #[test]
+    fn short_strokes_stay_whole() {
+        let brush = Brush::circle(1., 0.);
+        let edit = Edit::new(EditOp::Subtract, Vec2::ZERO, Vec2::ZERO, &brush);
+        assert_eq!(edit.split(Vec2::ZERO, 3.), vec![edit]);
+    }
+
+    #[test]
+    fn split_follows_the_wrapped_offset() {
+        // Across the seam of a world 100 wide the stroke goes right, not back through the middle
+        let brush = Brush::circle(1., 0.);
+        let edit = Edit::new(EditOp::Add, Vec2::new(45., 0.), Vec2::new(-45., 0.), &brush);
+        let pieces = edit.split(Vec2::new(10., 0.), 5.);
+        assert_eq!(pieces.len(), 2);
+        assert_eq!(pieces[0].from, Vec2::new(-55., 0.));
+        assert_eq!(pieces[1].from, Vec2::new(-50., 0.));
+        assert_eq!(pieces[1].to, edit.to);
+    }
+}
@@ -0,0 +1,298 @@
+// Terrain file layout, all values little-endian:
+//
+//   offset  size  field
+//   0       4     magic "SDF\0"
+//   4       4     version (u32, currently 3)
+//   8       8     SDF size in texels (2 x u32)
+//   16      8     world size in world units (2 x f32)
+//   24      ...   size.x * size.y half floats, row-major, first row is the top of the world
+//   ...     ...   size.x * size.y material palette indices (u8), same order, since version 2
+//   ...     ...   size.x * size.y terrain heights (half floats), same order, since version 3
+//
+// Distances are stored in world units, exactly as they live in the texture, and so are heights,
+// as offsets from the full terrain height. Version 1 files have no materials and load with
+// material 0 everywhere, files before version 3 have no heights and load at full height.
+
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use glam::*;
+
+const MAGIC: [u8; 4] = *b"SDF\0";
+const VERSION: u32 = 3;
+const HEADER_SIZE: usize = 24;
+const MAX_SIZE: u32 = 16384;
+
+pub struct SdfFile {
+    pub size: UVec2,
+    pub world_size: Vec2,
+    pub data: Vec<f32>,
+    pub materials: Vec<u8>,
+    pub heights: Vec<f32>,
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+impl SdfFile {
+    // `bytes` holds little-endian half float quadruples of distance, material, height and an
+    // unused channel, as in the texture
+    pub fn from_texels(size: UVec2, world_size: Vec2, bytes: &[u8]) -> Self {
+        let half_at = |b: &[u8], i: usize| half::f16::from_bits(u16::from_le_bytes([b[i], b[i + 1]])).to_f32();
+        let data = bytes.chunks_exact(8).map(|b| half_at(b, 0)).collect();
+        let materials = bytes.chunks_exact(8).map(|b| half_at(b, 2).round().clamp(0., 255.) as u8).collect();
+        let heights = bytes.chunks_exact(8).map(|b| half_at(b, 4)).collect();
+        Self { size, world_size, data, materials, heights }
+    }
+
+    pub fn to_texels(&self) -> Vec<u8> {
+        self.data
+            .iter()
+            .zip(self.materials.iter())
+            .zip(self.heights.iter())
+            .flat_map(|((v, m), h)| {
+                let [d0, d1] = half::f16::from_f32(*v).to_bits().to_le_bytes();
+                let [m0, m1] = half::f16::from_f32(*m as f32).to_bits().to_le_bytes();
+                let [h0, h1] = half::f16::from_f32(*h).to_bits().to_le_bytes();
+                [d0, d1, m0, m1, h0, h1, 0, 0]
+            })
+            .collect()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + 5 * self.data.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.size.x.to_le_bytes());
+        bytes.extend_from_slice(&self.size.y.to_le_bytes());
+        bytes.extend_from_slice(&self.world_size.x.to_le_bytes());
+        bytes.extend_from_slice(&self.world_size.y.to_le_bytes());
+        bytes.extend(self.data.iter().flat_map(|v| half::f16::from_f32(*v).to_bits().to_le_bytes()));
+        bytes.extend_from_slice(&self.materials);
+        bytes.extend(self.heights.iter().flat_map(|h| half::f16::from_f32(*h).to_bits().to_le_bytes()));
+        fs::write(path, bytes)
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = fs::read(path)?;
+        if bytes.len() < HEADER_SIZE || bytes[0..4] != MAGIC {
+            return Err(invalid_data("Not an SDF terrain file"));
+        }
+        let u32_at = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let f32_at = |i: usize| f32::from_bits(u32_at(i));
+        let version = u32_at(4);
+        if version == 0 || version > VERSION {
+            return Err(invalid_data(&format!("Unsupported SDF terrain file version {}", version)));
+        }
+        let size = UVec2::new(u32_at(8), u32_at(12));
+        let world_size = Vec2::new(f32_at(16), f32_at(20));
+        if size.x == 0 || size.y == 0 || size.x > MAX_SIZE || size.y > MAX_SIZE {
+            return Err(invalid_data(&format!("Invalid SDF size {}x{}", size.x, size.y)));
+        }
+        if !(world_size.x > 0. && world_size.y > 0. && world_size.is_finite()) {
+            return Err(invalid_data(&format!("Invalid world size {}x{}", world_size.x, world_size.y)));
+        }
+        let count = (size.x * size.y) as usize;
+        let expected = HEADER_SIZE + match version {
+            1 => 2 * count,
+            2 => 3 * count,
+            _ => 5 * count,
+        };
+        if bytes.len() != expected {
+            return Err(invalid_data(&format!("Expected {} bytes of SDF data, found {}", expected, bytes.len())));
+        }
+        let halves = |b: &[u8]| -> Vec<f32> {
+            b.chunks_exact(2)
+                .map(|b| half::f16::from_bits(u16::from_le_bytes([b[0], b[1]])).to_f32())
+                .collect()
+        };
+        let data = halves(&bytes[HEADER_SIZE..HEADER_SIZE + 2 * count]);
+        let materials = if version == 1 {
+            vec![0; count]
+        } else {
+            bytes[HEADER_SIZE + 2 * count..HEADER_SIZE + 3 * count].to_vec()
+        };
+        let heights = if version < 3 {
+            vec![0.; count]
+        } else {
+            halves(&bytes[HEADER_SIZE + 3 * count..])
+        };
+        Ok(Self { size, world_size, data, materials, heights })
+    }
+
+    fn index(&self, x: i32, y: i32) -> usize {
+        let x = x.rem_euclid(self.size.x as i32) as u32;
+        let y = y.rem_euclid(self.size.y as i32) as u32;
+        (y * self.size.x + x) as usize
+    }
+
+    fn texel(&self, x: i32, y: i32) -> f32 {
+        self.data[self.index(x, y)]
+    }
+
+    // Bilinear lookup at normalized texture coordinates, repeating like the SDF sampler does
+    fn sample(&self, uv: Vec2) -> f32 {
+        let p = uv * self.size.as_vec2() - 0.5;
+        let i = p.floor();
+        let f = p - i;
+        let (x, y) = (i.x as i32, i.y as i32);
+        let top = self.texel(x, y) * (1. - f.x) + self.texel(x + 1, y) * f.x;
+        let bottom = self.texel(x, y + 1) * (1. - f.x) + self.texel(x + 1, y + 1) * f.x;
+        top * (1. - f.y) + bottom * f.y
+    }
+
+    // Stretches the field over `world_size` at `size` texels. Distances are scaled by the
+    // smaller of the two axis ratios so that they never over-estimate the new field, materials
+    // and heights use the nearest texel.
+    pub fn resample(&self, size: UVec2, world_size: Vec2) -> Self {
+        if size == self.size && world_size == self.world_size {
+            return Self { size, world_size, data: self.data.clone(), materials: self.materials.clone(), heights: self.heights.clone() };
+        }
+        let scale = (world_size / self.world_size).min_element();
+        let inv_size = 1. / size.as_vec2();
+        let mut data = Vec::with_capacity((size.x * size.y) as usize);
+        let mut materials = Vec::with_capacity((size.x * size.y) as usize);
+        let mut heights = Vec::with_capacity((size.x * size.y) as usize);
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let uv = (UVec2::new(x, y).as_vec2() + 0.5) * inv_size;
+                data.push(self.sample(uv) * scale);
+                let nearest = (uv * self.size.as_vec2()).floor().as_ivec2();
+                let i = self.index(nearest.x, nearest.y);
+                materials.push(self.materials[i]);
+                heights.push(self.heights[i]);
+            }
+        }
+        Self { size, world_size, data, materials, heights }
+    }
+
+    // Crops or pads the field around the world origin to `world_size` at the same texel
+    // density, new texels are empty space at `empty_dist` and full height
+    pub fn recenter(&self, world_size: Vec2, empty_dist: f32) -> Self {
+        let texel_size = self.world_size / self.size.as_vec2();
+        let size = (world_size / texel_size).round().as_uvec2().max(UVec2::ONE);
+        if size == self.size {
+            return Self { size, world_size, data: self.data.clone(), materials: self.materials.clone(), heights: self.heights.clone() };
+        }
+        let offset = (self.size.as_ivec2() - size.as_ivec2()) / 2;
+        let mut data = Vec::with_capacity((size.x * size.y) as usize);
+        let mut materials = Vec::with_capacity((size.x * size.y) as usize);
+        let mut heights = Vec::with_capacity((size.x * size.y) as usize);
+        for y in 0..size.y as i32 {
+            for x in 0..size.x as i32 {
+                let p = IVec2::new(x, y) + offset;
+                if p.cmplt(IVec2::ZERO).any() || p.cmpge(self.size.as_ivec2()).any() {
+                    data.push(empty_dist);
+                    materials.push(0);
+                    heights.push(0.);
+                } else {
+                    let i = self.index(p.x, p.y);
+                    data.push(self.data[i]);
+                    materials.push(self.materials[i]);
+                    heights.push(self.heights[i]);
+                }
+            }
+        }
+        Self { size, world_size: size.as_vec2() * texel_size, data, materials, heights }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.sdf", name, std::process::id()))
+    }
+
+    fn ramp(size: UVec2) -> SdfFile {
+        let count = (size.x * size.y) as usize;
+        SdfFile {
+            size,
+            world_size: Vec2::new(8., 4.),
+            data: (0..count).map(|i| i as f32 * 0.5 - 3.).collect(),
+            materials: (0..count).map(|i| (i % 7) as u8).collect(),
+            heights: (0..count).map(|i| -((i % 5) as f32) * 0.25).collect(),
+        }
+    }
+
+    #[test]
+    fn files_round_trip() {
+        let path = temp_path("round-trip");
+        let file = ramp(UVec2::new(4, 3));
+        file.write(&path).unwrap();
+        let read = SdfFile::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.size, file.size);
+        assert_eq!(read.world_size, file.world_size);
+        assert_eq!(read.data, file.data);
+        assert_eq!(read.materials, file.materials);
+        assert_eq!(read.heights, file.heights);
+    }
+
+    #[test]
+    fn broken_files_are_rejected() {
+        let path = temp_path("broken");
+        fs::write(&path, b"not an sdf file at all, really").unwrap();
+        assert_eq!(SdfFile::read(&path).err().unwrap().kind(), ErrorKind::InvalidData);
+
+        ramp(UVec2::new(4, 3)).write(&path).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes.pop();
+        fs::write(&path, &bytes).unwrap();
+        assert_eq!(SdfFile::read(&path).err().unwrap().kind(), ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn version_2_files_load_at_full_height() {
+        let path = temp_path("version-2");
+        let file = ramp(UVec2::new(4, 3));
+        file.write(&path).unwrap();
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[4..8].copy_from_slice(&2u32.to_le_bytes());
+        bytes.truncate(HEADER_SIZE + 3 * file.data.len());
+        fs::write(&path, &bytes).unwrap();
+        let read = SdfFile::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.data, file.data);
+        assert_eq!(read.materials, file.materials);
+        assert_eq!(read.heights, vec![0.; file.data.len()]);
+    }
+
+    #[test]
+    fn texels_round_trip() {
+        let file = ramp(UVec2::new(4, 3));
+        let texels = SdfFile::from_texels(file.size, file.world_size, &file.to_texels());
+        assert_eq!(texels.data, file.data);
+        assert_eq!(texels.materials, file.materials);
+        assert_eq!(texels.heights, file.heights);
+    }
+
+    #[test]
+    fn recentering_pads_with_empty_space() {
+        // 2 units per texel
+        let file = ramp(UVec2::new(4, 2));
+        let padded = file.recenter(Vec2::new(16., 8.), 99.);
+        assert_eq!(padded.size, UVec2::new(8, 4));
+        assert_eq!(padded.world_size, Vec2::new(16., 8.));
+        assert_eq!(&padded.data[..8], &[99.; 8]);
+        assert_eq!(&padded.data[8..16], &[99., 99., -3., -2.5, -2., -1.5, 99., 99.]);
+        assert_eq!(&padded.materials[8..16], &[0, 0, 0, 1, 2, 3, 0, 0]);
+        assert_eq!(&padded.heights[8..16], &[0., 0., 0., -0.25, -0.5, -0.75, 0., 0.]);
+    }
+
+    #[test]
+    fn recentering_crops_around_the_origin() {
+        let file = ramp(UVec2::new(4, 2));
+        let cropped = file.recenter(Vec2::new(4., 4.), 99.);
+        assert_eq!(cropped.size, UVec2::new(2, 2));
+        assert_eq!(cropped.data, vec![-2.5, -2., -0.5, 0.]);
+        let same = file.recenter(file.world_size, 99.);
+        assert_eq!(same.data, file.data);
+    }
+}
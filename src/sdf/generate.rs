@@ -0,0 +1,470 @@
+use glam::*;
+use wgpu::PipelineCompilationOptions;
+
+use crate::renderer::texture;
+
+use super::tiles::Tiles;
+
+// Procedural terrain source. Every source describes solid terrain, negative inside, in world
+// space so that what a world tile gets only depends on the seed and on the tile, not on where
+// the window is. On a torus positions are wrapped into the world first, the noise sources are
+// not periodic though, so they meet at a seam along the world edges.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Source {
+    // Solid wherever fBm Perlin noise with features of `scale` world units rises above `threshold`
+    Caves { scale: f32, octaves: u32, threshold: f32 },
+    // Worley chambers roughly `cell_size` across, separated by walls `wall` thick
+    Caverns { cell_size: f32, wall: f32 },
+    // Random fill smoothed by the 4-5 cellular automaton rule on a world grid of cells roughly
+    // `cell_size` across, see `CellGrid`. Only `MAX_ITERATIONS` iterations are run.
+    CellularCaves { cell_size: f32, fill: f32, iterations: u32 },
+    // Round islands on a jittered grid, `density` is the chance of an island per grid cell
+    Islands { spacing: f32, density: f32, min_radius: f32, max_radius: f32, roughness: f32 },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CombineOp {
+    Union,
+    Subtract,
+    Intersect,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Layer {
+    pub source: Source,
+    pub op: CombineOp,
+    pub seed: u32,
+    // Palette index given to every texel the layer changes
+    pub material: u32,
+}
+
+impl Source {
+    pub fn defaults() -> [Source; 4] {
+        [
+            Source::Caves { scale: 24., octaves: 4, threshold: -0.05 },
+            Source::Caverns { cell_size: 32., wall: 2. },
+            Source::CellularCaves { cell_size: 2., fill: 0.45, iterations: 5 },
+            Source::Islands { spacing: 40., density: 0.5, min_radius: 4., max_radius: 12., roughness: 0.3 },
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Source::Caves { .. } => "Perlin caves",
+            Source::Caverns { .. } => "Worley caverns",
+            Source::CellularCaves { .. } => "Cellular caves",
+            Source::Islands { .. } => "Islands",
+        }
+    }
+}
+
+impl Layer {
+    pub fn new(source: Source) -> Self {
+        Self {
+            source,
+            op: CombineOp::Union,
+            seed: 0,
+            material: 0,
+        }
+    }
+}
+
+pub const MAX_ITERATIONS: u32 = 16;
+
+// Part of the cellular automaton's world grid that a window needs. Cells tile the world, on a
+// torus the grid repeats every `period` cells so the caves wrap around without a seam. The
+// grid reaches `iterations + 1` cells beyond the window: the automaton is only wrong within
+// one cell per iteration of the grid edges, so the cells under the window come out the same
+// wherever the window is.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CellGrid {
+    pub cell_size: Vec2,
+    // World cell of the first grid cell, cell `c` spans `c * cell_size` to `(c + 1) * cell_size`
+    pub origin: IVec2,
+    pub size: UVec2,
+    pub period: IVec2,
+}
+
+impl CellGrid {
+    // Grid for the window with top left corner `window_origin`, `cell_size` is at least a texel
+    pub fn new(window_origin: Vec2, window_size: Vec2, world_size: Vec2, wraps: bool, cell_size: f32, texel_size: Vec2, iterations: u32) -> Self {
+        let cell = cell_size.max(texel_size.max_element());
+        let period = (world_size / cell).round().max(Vec2::ONE);
+        let cell_size = if wraps { world_size / period } else { Vec2::splat(cell) };
+        let margin = IVec2::splat(iterations.min(MAX_ITERATIONS) as i32 + 1);
+        let min = Vec2::new(window_origin.x, window_origin.y - window_size.y);
+        let max = Vec2::new(window_origin.x + window_size.x, window_origin.y);
+        let origin = (min / cell_size).floor().as_ivec2() - margin;
+        let end = (max / cell_size).floor().as_ivec2() + margin;
+        Self {
+            cell_size,
+            origin,
+            size: (end - origin + 1).as_uvec2(),
+            period: if wraps { period.as_ivec2() } else { IVec2::ZERO },
+        }
+    }
+
+    // Grid cells along each axis a window of `size` texels can need
+    fn max_size(size: UVec2) -> UVec2 {
+        size + 2 * (MAX_ITERATIONS + 2)
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Zeroable, bytemuck::Pod)]
+struct Uniforms {
+    pub origin: [f32; 2],
+    pub texel_size: [f32; 2],
+    pub first_texel: [i32; 2],
+    pub size: [u32; 2],
+    pub grid_size: [u32; 2],
+    pub grid_origin: [i32; 2],
+    pub grid_period: [i32; 2],
+    pub world_size: [f32; 2],
+    pub kind: u32,
+    pub op: u32,
+    pub seed: u32,
+    pub material: u32,
+    pub octaves: u32,
+    pub keep: u32,
+    pub wraps: u32,
+    pub dummy: u32,
+    pub params: [f32; 4],
+    pub params2: [f32; 4],
+}
+
+const FIELD_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+const CELLS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+const WORKGROUP_SIZE: u32 = 8;
+
+#[derive(Clone, Copy)]
+enum Stage {
+    Init,
+    Layer,
+    CellsInit,
+    CellsStep,
+}
+
+struct Pass {
+    stage: Stage,
+    uniforms: Uniforms,
+    field: usize,
+    cells: usize,
+    workgroups: UVec2,
+}
+
+// Evaluates a stack of `Layer`s over the resident tiles with one compute pass per layer, plus
+// the cellular automaton passes. The combined field (negative inside, material in green, height
+// in blue) ping-pongs between two textures and is meant to be fed to the jump flood.
+pub struct Generator {
+    size: UVec2,
+    texel_size: Vec2,
+    uniform_stride: u64,
+    uniform_bind_group_layout: wgpu::BindGroupLayout,
+    _fields: [texture::Texture; 2],
+    field_bind_groups: [wgpu::BindGroup; 2],
+    field_out_bind_groups: [wgpu::BindGroup; 2],
+    _cells: [texture::Texture; 2],
+    cells_bind_groups: [wgpu::BindGroup; 2],
+    cells_out_bind_groups: [wgpu::BindGroup; 2],
+    init_pipeline: wgpu::ComputePipeline,
+    layer_pipeline: wgpu::ComputePipeline,
+    cells_init_pipeline: wgpu::ComputePipeline,
+    cells_step_pipeline: wgpu::ComputePipeline,
+}
+
+impl Generator {
+    // `field_bind_group_layout` is the jump flood field layout, used for every sampled texture
+    // so the result can be flooded directly
+    pub fn new(size: UVec2, world_size: Vec2, field_bind_group_layout: &wgpu::BindGroupLayout, device: &wgpu::Device) -> Self {
+        let storage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING;
+        let fields = [
+            texture::Texture::new_intermediate4(device, size, FIELD_FORMAT, storage),
+            texture::Texture::new_intermediate4(device, size, FIELD_FORMAT, storage),
+        ];
+        let cells = [
+            texture::Texture::new_intermediate4(device, CellGrid::max_size(size), CELLS_FORMAT, storage),
+            texture::Texture::new_intermediate4(device, CellGrid::max_size(size), CELLS_FORMAT, storage),
+        ];
+
+        let uniform_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("generate_uniform_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    count: None,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<Uniforms>() as u64),
+                    },
+                }
+            ],
+        });
+        let storage_layout = |label, format| device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(label),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let field_out_bind_group_layout = storage_layout("generate_field_out_bind_group_layout", FIELD_FORMAT);
+        let cells_out_bind_group_layout = storage_layout("generate_cells_out_bind_group_layout", CELLS_FORMAT);
+
+        let field_bind_groups = [
+            Self::create_view_bind_group(device, field_bind_group_layout, &fields[0].view),
+            Self::create_view_bind_group(device, field_bind_group_layout, &fields[1].view),
+        ];
+        let field_out_bind_groups = [
+            Self::create_view_bind_group(device, &field_out_bind_group_layout, &fields[0].view),
+            Self::create_view_bind_group(device, &field_out_bind_group_layout, &fields[1].view),
+        ];
+        let cells_bind_groups = [
+            Self::create_view_bind_group(device, field_bind_group_layout, &cells[0].view),
+            Self::create_view_bind_group(device, field_bind_group_layout, &cells[1].view),
+        ];
+        let cells_out_bind_groups = [
+            Self::create_view_bind_group(device, &cells_out_bind_group_layout, &cells[0].view),
+            Self::create_view_bind_group(device, &cells_out_bind_group_layout, &cells[1].view),
+        ];
+
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let uniform_stride = (std::mem::size_of::<Uniforms>() as u64).div_ceil(alignment) * alignment;
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Generate shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("generate.wgsl").into()),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Generate"),
+            bind_group_layouts: &[
+                &uniform_bind_group_layout,
+                field_bind_group_layout,
+                field_bind_group_layout,
+                &field_out_bind_group_layout,
+                field_bind_group_layout,
+                &cells_out_bind_group_layout,
+            ],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Generate pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point,
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+
+        Self {
+            size,
+            texel_size: world_size / size.as_vec2(),
+            uniform_stride,
+            uniform_bind_group_layout,
+            init_pipeline: pipeline("main_init"),
+            layer_pipeline: pipeline("main_layer"),
+            cells_init_pipeline: pipeline("main_cells_init"),
+            cells_step_pipeline: pipeline("main_cells_step"),
+            _fields: fields,
+            field_bind_groups,
+            field_out_bind_groups,
+            _cells: cells,
+            cells_bind_groups,
+            cells_out_bind_groups,
+        }
+    }
+
+    fn create_view_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, view: &wgpu::TextureView) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+            ],
+            label: None,
+        })
+    }
+
+    fn layer_uniforms(&self, base: &Uniforms, layer: &Layer, tiles: &Tiles) -> Uniforms {
+        let mut grid = CellGrid { cell_size: Vec2::ONE, origin: IVec2::ZERO, size: UVec2::ONE, period: IVec2::ZERO };
+        let (kind, octaves, params, params2) = match layer.source {
+            Source::Caves { scale, octaves, threshold } => (0, octaves, [scale.max(1e-3), threshold, 0., 0.], [0.; 4]),
+            Source::Caverns { cell_size, wall } => (1, 0, [cell_size.max(1e-3), wall, 0., 0.], [0.; 4]),
+            Source::CellularCaves { cell_size, fill, iterations } => {
+                let window_size = self.size.as_vec2() * self.texel_size;
+                grid = CellGrid::new(tiles.window_origin(), window_size, tiles.world_size(), tiles.config().topology.wraps(), cell_size, self.texel_size, iterations);
+                (2, iterations.min(MAX_ITERATIONS), [grid.cell_size.x, fill, grid.cell_size.y, 0.], [0.; 4])
+            }
+            Source::Islands { spacing, density, min_radius, max_radius, roughness } => {
+                (3, 0, [spacing.max(1e-3), density, min_radius, max_radius.max(min_radius)], [roughness, 0., 0., 0.])
+            }
+        };
+        Uniforms {
+            kind,
+            op: layer.op as u32,
+            seed: layer.seed,
+            material: layer.material,
+            octaves,
+            grid_size: grid.size.into(),
+            grid_origin: grid.origin.into(),
+            grid_period: grid.period.into(),
+            params,
+            params2,
+            ..*base
+        }
+    }
+
+    // Records the layers into `encoder` and returns the field bind group holding the result.
+    // With `terrain` the current terrain is the starting point, otherwise the window starts empty.
+    pub fn run(&self, encoder: &mut wgpu::CommandEncoder, layers: &[Layer], terrain: Option<&wgpu::BindGroup>, tiles: &Tiles, device: &wgpu::Device, queue: &wgpu::Queue) -> &wgpu::BindGroup {
+        let size_workgroups = (self.size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+        let base = Uniforms {
+            origin: tiles.window_origin().into(),
+            texel_size: self.texel_size.into(),
+            first_texel: tiles.first_texel().into(),
+            size: self.size.into(),
+            world_size: tiles.world_size().into(),
+            wraps: tiles.config().topology.wraps() as u32,
+            keep: terrain.is_some() as u32,
+            ..Default::default()
+        };
+
+        let mut passes = vec![Pass { stage: Stage::Init, uniforms: base, field: 1, cells: 0, workgroups: size_workgroups }];
+        let mut field = 0;
+        let mut cells = 0;
+        for layer in layers {
+            let uniforms = self.layer_uniforms(&base, layer, tiles);
+            if let Source::CellularCaves { iterations, .. } = layer.source {
+                let workgroups = (UVec2::from(uniforms.grid_size) + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+                passes.push(Pass { stage: Stage::CellsInit, uniforms, field, cells, workgroups });
+                cells = 1 - cells;
+                for _ in 0..iterations.min(MAX_ITERATIONS) {
+                    passes.push(Pass { stage: Stage::CellsStep, uniforms, field, cells, workgroups });
+                    cells = 1 - cells;
+                }
+            }
+            passes.push(Pass { stage: Stage::Layer, uniforms, field, cells, workgroups: size_workgroups });
+            field = 1 - field;
+        }
+
+        let stride = self.uniform_stride as usize;
+        let mut data = vec![0u8; stride * passes.len()];
+        for (i, pass) in passes.iter().enumerate() {
+            data[i * stride..i * stride + std::mem::size_of::<Uniforms>()].copy_from_slice(bytemuck::bytes_of(&pass.uniforms));
+        }
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Generate uniforms"),
+            size: data.len() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&uniform_buffer, 0, &data);
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.uniform_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &uniform_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<Uniforms>() as u64),
+                }),
+            }],
+            label: None,
+        });
+
+        // Every pass binds all groups, always reading one texture of each pair and writing the other
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("Generate"), timestamp_writes: None, });
+        for (i, pass) in passes.iter().enumerate() {
+            compute_pass.set_pipeline(match pass.stage {
+                Stage::Init => &self.init_pipeline,
+                Stage::Layer => &self.layer_pipeline,
+                Stage::CellsInit => &self.cells_init_pipeline,
+                Stage::CellsStep => &self.cells_step_pipeline,
+            });
+            compute_pass.set_bind_group(0, &uniform_bind_group, &[(i * stride) as u32]);
+            compute_pass.set_bind_group(1, terrain.unwrap_or(&self.field_bind_groups[pass.field]), &[]);
+            compute_pass.set_bind_group(2, &self.field_bind_groups[pass.field], &[]);
+            compute_pass.set_bind_group(3, &self.field_out_bind_groups[1 - pass.field], &[]);
+            compute_pass.set_bind_group(4, &self.cells_bind_groups[pass.cells], &[]);
+            compute_pass.set_bind_group(5, &self.cells_out_bind_groups[1 - pass.cells], &[]);
+            compute_pass.dispatch_workgroups(pass.workgroups.x, pass.workgroups.y, 1);
+        }
+        &self.field_bind_groups[field]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXEL_SIZE: Vec2 = Vec2::splat(0.5);
+
+    // Cells that the window's texels interpolate between
+    fn window_cells(window_origin: Vec2, window_size: Vec2, grid: &CellGrid) -> (IVec2, IVec2) {
+        let min = Vec2::new(window_origin.x, window_origin.y - window_size.y) + 0.5 * TEXEL_SIZE;
+        let max = Vec2::new(window_origin.x + window_size.x, window_origin.y) - 0.5 * TEXEL_SIZE;
+        let first = (min / grid.cell_size - 0.5).floor().as_ivec2();
+        let last = (max / grid.cell_size - 0.5).floor().as_ivec2() + 1;
+        (first - grid.origin, last - grid.origin)
+    }
+
+    #[test]
+    fn grids_reach_beyond_the_window_by_the_iterations() {
+        let window_size = Vec2::splat(64.);
+        for iterations in [0, 3, MAX_ITERATIONS, MAX_ITERATIONS + 10] {
+            for window_origin in [Vec2::new(-32., 32.), Vec2::new(-96., 0.), Vec2::new(13., -7.)] {
+                let grid = CellGrid::new(window_origin, window_size, Vec2::splat(256.), false, 2., TEXEL_SIZE, iterations);
+                let (first, last) = window_cells(window_origin, window_size, &grid);
+                let margin = iterations.min(MAX_ITERATIONS) as i32;
+                assert!(first.cmpge(IVec2::splat(margin)).all(), "{:?} {:?}", first, grid);
+                assert!(last.cmplt(grid.size.as_ivec2() - margin).all(), "{:?} {:?}", last, grid);
+                assert!(grid.size.cmple(CellGrid::max_size(UVec2::splat(128))).all());
+            }
+        }
+    }
+
+    #[test]
+    fn grid_cells_are_anchored_to_the_world() {
+        let window_size = Vec2::splat(64.);
+        let a = CellGrid::new(Vec2::new(-32., 32.), window_size, Vec2::splat(256.), false, 4., TEXEL_SIZE, 5);
+        let b = CellGrid::new(Vec2::new(0., 32.), window_size, Vec2::splat(256.), false, 4., TEXEL_SIZE, 5);
+        assert_eq!(a.cell_size, b.cell_size);
+        assert_eq!(b.origin - a.origin, IVec2::new(8, 0));
+        assert_eq!(a.size, b.size);
+    }
+
+    #[test]
+    fn torus_grids_repeat_with_the_world() {
+        let world_size = Vec2::new(256., 100.);
+        let grid = CellGrid::new(Vec2::new(-32., 32.), Vec2::splat(64.), world_size, true, 3., TEXEL_SIZE, 5);
+        assert_eq!(grid.period, IVec2::new(85, 33));
+        assert!((grid.cell_size * grid.period.as_vec2() - world_size).abs().max_element() < 1e-4);
+        let clamped = CellGrid::new(Vec2::new(-32., 32.), Vec2::splat(64.), world_size, false, 3., TEXEL_SIZE, 5);
+        assert_eq!(clamped.period, IVec2::ZERO);
+        assert_eq!(clamped.cell_size, Vec2::splat(3.));
+    }
+
+    #[test]
+    fn cells_are_at_least_a_texel() {
+        let grid = CellGrid::new(Vec2::new(-32., 32.), Vec2::splat(64.), Vec2::splat(256.), false, 0.1, TEXEL_SIZE, 0);
+        assert_eq!(grid.cell_size, TEXEL_SIZE);
+        assert_eq!(grid.size, UVec2::splat(131));
+    }
+
+    #[test]
+    fn uniforms_match_the_shader_layout() {
+        assert_eq!(std::mem::size_of::<Uniforms>(), 128);
+        assert_eq!(std::mem::offset_of!(Uniforms, params), 96);
+    }
+}
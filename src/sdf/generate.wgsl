@@ -0,0 +1,282 @@
+struct Uniforms {
+    // World position of the top left corner of the resident window
+    origin: vec2<f32>,
+    texel_size: vec2<f32>,
+    // Texel holding the top left corner of the window, the textures are a toroidal ring
+    first_texel: vec2<i32>,
+    size: vec2<u32>,
+    // Part of the world's cellular automaton grid under the window, see `CellGrid`
+    grid_size: vec2<u32>,
+    grid_origin: vec2<i32>,
+    // Cells after which the grid repeats, zero unless the world wraps
+    grid_period: vec2<i32>,
+    world_size: vec2<f32>,
+    kind: u32,
+    op: u32,
+    seed: u32,
+    material: u32,
+    octaves: u32,
+    keep: u32,
+    wraps: u32,
+    dummy: u32,
+    params: vec4<f32>,
+    params2: vec4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: Uniforms;
+
+// Current terrain, distance in red, material in green and height in blue
+@group(1) @binding(0)
+var t_terrain: texture_2d<f32>;
+
+// Combined field so far, negative inside solid terrain, material in green, height in blue
+@group(2) @binding(0)
+var t_field: texture_2d<f32>;
+
+@group(3) @binding(0)
+var t_field_out: texture_storage_2d<rgba32float, write>;
+
+// Cellular automaton state, 1 for solid cells
+@group(4) @binding(0)
+var t_cells: texture_2d<f32>;
+
+@group(5) @binding(0)
+var t_cells_out: texture_storage_2d<r32float, write>;
+
+const KIND_CAVES: u32 = 0u;
+const KIND_CAVERNS: u32 = 1u;
+const KIND_CELLULAR: u32 = 2u;
+const KIND_ISLANDS: u32 = 3u;
+
+const OP_UNION: u32 = 0u;
+const OP_SUBTRACT: u32 = 1u;
+
+const EMPTY: f32 = 1e4;
+const TAU: f32 = 6.28318530718;
+
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn hash(cell: vec2<i32>, seed: u32) -> u32 {
+    return pcg(bitcast<u32>(cell.x) ^ pcg(bitcast<u32>(cell.y) ^ pcg(seed)));
+}
+
+fn rand(h: u32) -> f32 {
+    return f32(h) / 4294967295.;
+}
+
+fn rand2(cell: vec2<i32>, seed: u32) -> vec2<f32> {
+    let h = hash(cell, seed);
+    return vec2<f32>(rand(h), rand(pcg(h)));
+}
+
+fn gradient(cell: vec2<i32>, seed: u32) -> vec2<f32> {
+    let a = TAU * rand(hash(cell, seed));
+    return vec2<f32>(cos(a), sin(a));
+}
+
+fn perlin(p: vec2<f32>, seed: u32) -> f32 {
+    let i = vec2<i32>(floor(p));
+    let f = fract(p);
+    let u = f * f * (3. - 2. * f);
+    let a = dot(gradient(i, seed), f);
+    let b = dot(gradient(i + vec2<i32>(1, 0), seed), f - vec2<f32>(1., 0.));
+    let c = dot(gradient(i + vec2<i32>(0, 1), seed), f - vec2<f32>(0., 1.));
+    let d = dot(gradient(i + vec2<i32>(1, 1), seed), f - vec2<f32>(1., 1.));
+    return 1.41421356 * mix(mix(a, b, u.x), mix(c, d, u.x), u.y);
+}
+
+fn fbm(p: vec2<f32>, octaves: u32, seed: u32) -> f32 {
+    var sum = 0.;
+    var amplitude = 0.5;
+    var total = 0.;
+    var q = p;
+    for (var i = 0u; i < octaves; i = i + 1u) {
+        sum = sum + amplitude * perlin(q, seed + i);
+        total = total + amplitude;
+        amplitude = amplitude * 0.5;
+        q = 2. * q;
+    }
+    return sum / max(total, 1e-6);
+}
+
+// World position of texel `t`, unwrapped like the window origin
+fn worldPos(t: vec2<i32>) -> vec2<f32> {
+    let size = vec2<i32>(uniforms.size);
+    let k = (((t - uniforms.first_texel) % size) + size) % size;
+    return uniforms.origin + vec2<f32>(f32(k.x) + 0.5, -(f32(k.y) + 0.5)) * uniforms.texel_size;
+}
+
+// Position in the world centered on the origin, the same for a tile wherever the window is
+fn sourcePos(t: vec2<i32>) -> vec2<f32> {
+    let p = worldPos(t);
+    if uniforms.wraps == 0u {
+        return p;
+    }
+    return p - uniforms.world_size * floor(p / uniforms.world_size + 0.5);
+}
+
+// Solid where the noise rises above the threshold
+fn caves(p: vec2<f32>) -> f32 {
+    let scale = uniforms.params.x;
+    let threshold = uniforms.params.y;
+    return (threshold - fbm(p / scale, uniforms.octaves, uniforms.seed)) * scale;
+}
+
+// Open chambers around Worley feature points, separated by walls along the cell borders
+fn caverns(p: vec2<f32>) -> f32 {
+    let cell_size = uniforms.params.x;
+    let wall = uniforms.params.y;
+    let cell = vec2<i32>(floor(p / cell_size));
+    var f1 = 1e20;
+    var f2 = 1e20;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let c = cell + vec2<i32>(x, y);
+            let feature = (vec2<f32>(c) + rand2(c, uniforms.seed)) * cell_size;
+            let d = length(p - feature);
+            if d < f1 {
+                f2 = f1;
+                f1 = d;
+            } else if d < f2 {
+                f2 = d;
+            }
+        }
+    }
+    return 0.5 * (f2 - f1) - wall;
+}
+
+// Cell `c` of the grid, the cells beyond its edges repeat the edge cells. They are only read
+// within the margin that the grid has around the window.
+fn cell(c: vec2<i32>) -> f32 {
+    return textureLoad(t_cells, clamp(c, vec2<i32>(0), vec2<i32>(uniforms.grid_size) - 1), 0).r;
+}
+
+// Smoothed cellular automaton, interpolated between cell centers
+fn cellular(t: vec2<i32>) -> f32 {
+    let cell_size = uniforms.params.xz;
+    let g = worldPos(t) / cell_size - 0.5 - vec2<f32>(uniforms.grid_origin);
+    let i = vec2<i32>(floor(g));
+    let f = g - floor(g);
+    let top = mix(cell(i), cell(i + vec2<i32>(1, 0)), f.x);
+    let bottom = mix(cell(i + vec2<i32>(0, 1)), cell(i + vec2<i32>(1, 1)), f.x);
+    return (0.5 - mix(top, bottom, f.y)) * min(cell_size.x, cell_size.y);
+}
+
+// Round islands on a jittered grid, roughened with noise
+fn islands(p: vec2<f32>) -> f32 {
+    let spacing = uniforms.params.x;
+    let density = uniforms.params.y;
+    let min_radius = uniforms.params.z;
+    let max_radius = uniforms.params.w;
+    let roughness = uniforms.params2.x;
+    let cell = vec2<i32>(floor(p / spacing));
+    var d = EMPTY;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let c = cell + vec2<i32>(x, y);
+            let h = hash(c, uniforms.seed);
+            if rand(h) >= density {
+                continue;
+            }
+            let center = (vec2<f32>(c) + rand2(c, pcg(h))) * spacing;
+            let radius = mix(min_radius, max_radius, rand(pcg(pcg(h))));
+            d = min(d, length(p - center) - radius);
+        }
+    }
+    return d + roughness * max_radius * fbm(p / max(max_radius, 1e-3), 3u, uniforms.seed + 17u);
+}
+
+@compute @workgroup_size(8, 8)
+fn main_init(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= uniforms.size) {
+        return;
+    }
+    let t = vec2<i32>(id.xy);
+    var value = vec3<f32>(EMPTY, 0., 0.);
+    if uniforms.keep != 0u {
+        value = textureLoad(t_terrain, t, 0).xyz;
+    }
+    textureStore(t_field_out, t, vec4<f32>(value, 0.));
+}
+
+@compute @workgroup_size(8, 8)
+fn main_layer(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= uniforms.size) {
+        return;
+    }
+    let t = vec2<i32>(id.xy);
+    let p = sourcePos(t);
+    var f: f32;
+    switch uniforms.kind {
+        case KIND_CAVES: {
+            f = caves(p);
+        }
+        case KIND_CAVERNS: {
+            f = caverns(p);
+        }
+        case KIND_CELLULAR: {
+            f = cellular(t);
+        }
+        default: {
+            f = islands(p);
+        }
+    }
+    let current = textureLoad(t_field, t, 0).xyz;
+    var d: f32;
+    switch uniforms.op {
+        case OP_UNION: {
+            d = min(current.x, f);
+        }
+        case OP_SUBTRACT: {
+            d = max(current.x, -f);
+        }
+        default: {
+            d = max(current.x, f);
+        }
+    }
+    let material = select(current.y, f32(uniforms.material), d != current.x);
+    textureStore(t_field_out, t, vec4<f32>(d, material, current.z, 0.));
+}
+
+@compute @workgroup_size(8, 8)
+fn main_cells_init(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= uniforms.grid_size) {
+        return;
+    }
+    let c = vec2<i32>(id.xy);
+    var world_cell = c + uniforms.grid_origin;
+    if uniforms.wraps != 0u {
+        world_cell = ((world_cell % uniforms.grid_period) + uniforms.grid_period) % uniforms.grid_period;
+    }
+    let fill = uniforms.params.y;
+    textureStore(t_cells_out, c, vec4<f32>(select(0., 1., rand(hash(world_cell, uniforms.seed)) < fill), 0., 0., 0.));
+}
+
+// One step of the classic 4-5 cave rule
+@compute @workgroup_size(8, 8)
+fn main_cells_step(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= uniforms.grid_size) {
+        return;
+    }
+    let c = vec2<i32>(id.xy);
+    var n = 0.;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            if x != 0 || y != 0 {
+                n = n + cell(c + vec2<i32>(x, y));
+            }
+        }
+    }
+    var value = cell(c);
+    if n > 4.5 {
+        value = 1.;
+    } else if n < 3.5 {
+        value = 0.;
+    }
+    textureStore(t_cells_out, c, vec4<f32>(value, 0., 0., 0.));
+}
@@ -0,0 +1,363 @@
+use glam::IVec2;
+
+use crate::renderer::texture;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HistoryConfig {
+    // Upper bound on steps that can be undone, the oldest are forgotten beyond it
+    pub max_steps: usize,
+    // Upper bound on the memory all snapshots take, the oldest steps are forgotten beyond it. A
+    // step that takes more than all of it can't be undone and leaves no history before it.
+    pub max_snapshot_bytes: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            max_steps: 64,
+            max_snapshot_bytes: 256 << 20,
+        }
+    }
+}
+
+// Contents of world tile `tile` on the other side of a step: from before the step while it is
+// done, from after it once it is undone
+pub struct Snapshot<T> {
+    pub tile: IVec2,
+    pub texture: T,
+    bytes: usize,
+}
+
+// Undo log of terrain steps, a stroke or a pass over the whole window, each holding snapshots
+// of the world tiles it changed. Undoing or redoing a step swaps its snapshots with the
+// current contents of their tiles, wherever those live, so history does not depend on which
+// tiles are resident. Snapshots hold SDF tile textures, anything else only stands in for them
+// in tests.
+pub struct History<T = texture::Texture> {
+    config: HistoryConfig,
+    steps: Vec<Vec<Snapshot<T>>>,
+    position: usize,
+    current: Option<Vec<Snapshot<T>>>,
+    // The current step outgrew the byte budget, it records nothing more until it ends
+    overflowed: bool,
+}
+
+impl<T> History<T> {
+    pub fn new(config: HistoryConfig) -> Self {
+        Self {
+            config,
+            steps: Vec::new(),
+            position: 0,
+            current: None,
+            overflowed: false,
+        }
+    }
+
+    pub fn config(&self) -> HistoryConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: HistoryConfig) {
+        self.config = HistoryConfig {
+            max_steps: config.max_steps.max(1),
+            max_snapshot_bytes: config.max_snapshot_bytes,
+        };
+        self.trim();
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn num_snapshots(&self) -> usize {
+        self.steps.iter().map(Vec::len).sum()
+    }
+
+    // Memory the snapshots take, of the current step too
+    pub fn snapshot_bytes(&self) -> usize {
+        self.steps.iter().chain(self.current.iter()).flatten().map(|s| s.bytes).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.steps.clear();
+        self.position = 0;
+        self.current = None;
+        self.overflowed = false;
+    }
+
+    pub fn begin_step(&mut self) {
+        if self.current.is_none() {
+            self.current = Some(Vec::new());
+        }
+    }
+
+    pub fn end_step(&mut self) {
+        self.overflowed = false;
+        if let Some(step) = self.current.take() {
+            if !step.is_empty() {
+                self.commit(step);
+            }
+        }
+    }
+
+    // Whether the current step already holds a snapshot of `tile`
+    pub fn has_snapshot(&self, tile: IVec2) -> bool {
+        self.current.iter().flatten().any(|s| s.tile == tile)
+    }
+
+    // Whether `record` would keep a snapshot of `tile`, to skip copying it otherwise
+    pub fn needs_snapshot(&self, tile: IVec2) -> bool {
+        !self.overflowed && !self.has_snapshot(tile)
+    }
+
+    // Keeps the contents of `tile` from before the current step, which take `bytes`. Only the
+    // first snapshot of a tile in a step counts.
+    pub fn record(&mut self, tile: IVec2, texture: T, bytes: usize) {
+        if !self.needs_snapshot(tile) {
+            return;
+        }
+        // The step replaces the redo branch once it ends, it has a snapshot now
+        self.steps.truncate(self.position);
+        self.current.get_or_insert_with(Vec::new).push(Snapshot { tile, texture, bytes });
+        self.trim();
+        if self.snapshot_bytes() > self.config.max_snapshot_bytes {
+            // Only the current step is left and it doesn't fit
+            self.current = Some(Vec::new());
+            self.overflowed = true;
+        }
+    }
+
+    fn commit(&mut self, step: Vec<Snapshot<T>>) {
+        self.steps.truncate(self.position);
+        self.steps.push(step);
+        self.position += 1;
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        let excess = self.steps.len().saturating_sub(self.config.max_steps).min(self.position);
+        self.steps.drain(..excess);
+        self.position -= excess;
+
+        // The oldest steps go first, the redo branch last
+        let mut bytes = self.snapshot_bytes();
+        while bytes > self.config.max_snapshot_bytes && !self.steps.is_empty() {
+            let step = if self.position > 0 {
+                self.position -= 1;
+                self.steps.remove(0)
+            } else {
+                self.steps.pop().unwrap()
+            };
+            bytes -= step.iter().map(|s| s.bytes).sum::<usize>();
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.position > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.position < self.steps.len()
+    }
+
+    // Steps back, `swap` exchanges each snapshot of the step with the current contents of its tile
+    pub fn undo(&mut self, swap: impl FnMut(&mut Snapshot<T>)) -> bool {
+        self.end_step();
+        if !self.can_undo() {
+            return false;
+        }
+        self.position -= 1;
+        self.steps[self.position].iter_mut().for_each(swap);
+        true
+    }
+
+    // Steps forward, see `undo`
+    pub fn redo(&mut self, swap: impl FnMut(&mut Snapshot<T>)) -> bool {
+        self.end_step();
+        if !self.can_redo() {
+            return false;
+        }
+        self.steps[self.position].iter_mut().for_each(swap);
+        self.position += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    // Tile contents stand in for textures
+    struct World {
+        tiles: HashMap<IVec2, u32>,
+        history: History<u32>,
+    }
+
+    impl World {
+        fn new(max_steps: usize) -> Self {
+            Self::with_config(HistoryConfig { max_steps, ..HistoryConfig::default() })
+        }
+
+        fn with_config(config: HistoryConfig) -> Self {
+            Self { tiles: HashMap::new(), history: History::new(config) }
+        }
+
+        fn get(&self, x: i32) -> u32 {
+            self.tiles.get(&IVec2::new(x, 0)).copied().unwrap_or(0)
+        }
+
+        fn set(&mut self, x: i32, value: u32) {
+            let tile = IVec2::new(x, 0);
+            self.history.record(tile, self.get(x), 1);
+            self.tiles.insert(tile, value);
+        }
+
+        fn step(&mut self, changes: &[(i32, u32)]) {
+            self.history.begin_step();
+            for (x, value) in changes {
+                self.set(*x, *value);
+            }
+            self.history.end_step();
+        }
+
+        fn undo(&mut self) -> bool {
+            let tiles = &mut self.tiles;
+            self.history.undo(|s| std::mem::swap(&mut s.texture, tiles.entry(s.tile).or_insert(0)))
+        }
+
+        fn redo(&mut self) -> bool {
+            let tiles = &mut self.tiles;
+            self.history.redo(|s| std::mem::swap(&mut s.texture, tiles.entry(s.tile).or_insert(0)))
+        }
+    }
+
+    #[test]
+    fn undo_and_redo_swap_tiles() {
+        let mut world = World::new(8);
+        world.step(&[(0, 1), (1, 1)]);
+        world.step(&[(1, 2), (1, 3), (2, 3)]);
+        assert_eq!(world.history.len(), 2);
+        assert_eq!(world.history.num_snapshots(), 4);
+
+        assert!(world.undo());
+        assert_eq!([world.get(0), world.get(1), world.get(2)], [1, 1, 0]);
+        assert!(world.undo());
+        assert_eq!([world.get(0), world.get(1), world.get(2)], [0, 0, 0]);
+        assert!(!world.undo());
+        assert!(world.redo());
+        assert!(world.redo());
+        assert_eq!([world.get(0), world.get(1), world.get(2)], [1, 3, 3]);
+        assert!(!world.redo());
+    }
+
+    #[test]
+    fn only_the_first_snapshot_of_a_tile_counts() {
+        let mut world = World::new(8);
+        world.history.begin_step();
+        world.set(0, 1);
+        world.set(0, 2);
+        assert!(world.history.has_snapshot(IVec2::ZERO));
+        world.history.end_step();
+        assert_eq!(world.history.num_snapshots(), 1);
+        world.undo();
+        assert_eq!(world.get(0), 0);
+    }
+
+    #[test]
+    fn empty_steps_are_not_recorded() {
+        let mut world = World::new(8);
+        world.step(&[]);
+        assert!(world.history.is_empty());
+        assert!(!world.undo());
+    }
+
+    #[test]
+    fn new_steps_drop_the_redo_branch() {
+        let mut world = World::new(8);
+        world.step(&[(0, 1)]);
+        world.step(&[(0, 2)]);
+        world.undo();
+        world.step(&[(1, 5)]);
+        assert_eq!(world.history.len(), 2);
+        assert!(!world.history.can_redo());
+        world.undo();
+        world.undo();
+        assert_eq!([world.get(0), world.get(1)], [0, 0]);
+    }
+
+    #[test]
+    fn old_steps_are_forgotten_beyond_max_steps() {
+        let mut world = World::new(2);
+        for i in 1..=4 {
+            world.step(&[(0, i)]);
+        }
+        assert_eq!(world.history.len(), 2);
+        assert!(world.undo());
+        assert!(world.undo());
+        assert!(!world.undo());
+        assert_eq!(world.get(0), 2);
+
+        world.history.set_config(HistoryConfig { max_steps: 1, ..world.history.config() });
+        assert_eq!(world.history.len(), 2);
+        world.redo();
+        world.redo();
+        world.history.set_config(HistoryConfig { max_steps: 1, ..world.history.config() });
+        assert_eq!(world.history.len(), 1);
+        assert_eq!(world.history.position(), 1);
+    }
+
+    #[test]
+    fn old_steps_are_forgotten_beyond_the_byte_budget() {
+        let mut world = World::with_config(HistoryConfig { max_steps: 8, max_snapshot_bytes: 4 });
+        world.step(&[(0, 1), (1, 1)]);
+        world.step(&[(2, 1)]);
+        world.step(&[(0, 2), (3, 2)]);
+        assert_eq!(world.history.len(), 2);
+        assert_eq!(world.history.snapshot_bytes(), 3);
+
+        // Recording drops the redo branch before older steps
+        world.undo();
+        world.step(&[(1, 3), (2, 3)]);
+        assert_eq!(world.history.len(), 2);
+        assert_eq!(world.history.snapshot_bytes(), 3);
+        world.undo();
+        world.undo();
+        assert!(!world.undo());
+        assert_eq!([world.get(0), world.get(1), world.get(2), world.get(3)], [1, 1, 0, 0]);
+
+        world.redo();
+        world.history.set_config(HistoryConfig { max_snapshot_bytes: 2, ..world.history.config() });
+        assert_eq!(world.history.snapshot_bytes(), 2);
+        assert_eq!((world.history.position(), world.history.len()), (0, 1));
+    }
+
+    #[test]
+    fn steps_beyond_the_byte_budget_leave_no_history() {
+        let mut world = World::with_config(HistoryConfig { max_steps: 8, max_snapshot_bytes: 2 });
+        world.step(&[(0, 1)]);
+        world.history.begin_step();
+        for x in 0..4 {
+            world.set(x, 2);
+        }
+        assert!(!world.history.needs_snapshot(IVec2::new(5, 0)));
+        assert_eq!(world.history.snapshot_bytes(), 0);
+        world.history.end_step();
+        assert!(world.history.is_empty());
+        assert!(!world.undo());
+
+        // The next step is recorded again
+        world.step(&[(0, 3)]);
+        assert!(world.undo());
+        assert_eq!(world.get(0), 2);
+    }
+}
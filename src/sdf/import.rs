@@ -0,0 +1,108 @@
+use glam::*;
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MaskChannel {
+    Luminance,
+    Alpha,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ImportOptions {
+    pub channel: MaskChannel,
+    // Mask values at or past `inside` are solid, values at or past `outside` are empty and the
+    // boundary is placed halfway in between. Setting `inside` below `outside` makes dark pixels solid.
+    pub inside: f32,
+    pub outside: f32,
+    // Stretch the image over the whole SDF, otherwise it is centered one pixel per texel
+    pub resize: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self {
+            channel: MaskChannel::Luminance,
+            inside: 0.25,
+            outside: 0.75,
+            resize: true,
+        }
+    }
+}
+
+// Converts `img` into a `size` texel scalar field for the jump flood, negative inside,
+// positive outside and crossing zero on the mask boundary with subtexel precision
+pub fn mask_field(img: &DynamicImage, size: UVec2, options: &ImportOptions) -> Vec<f32> {
+    let img = if options.resize {
+        img.resize_exact(size.x, size.y, FilterType::Triangle)
+    } else {
+        img.clone()
+    };
+    let rgba = img.to_rgba8();
+    let offset = (size.as_ivec2() - IVec2::new(rgba.width() as i32, rgba.height() as i32)) / 2;
+    let range = options.inside - options.outside;
+    let range = if range.abs() < 1e-4 { 1e-4f32.copysign(range) } else { range };
+
+    let mut field = vec![0.5; (size.x * size.y) as usize];
+    for (x, y, pixel) in rgba.enumerate_pixels() {
+        let p = IVec2::new(x as i32, y as i32) + offset;
+        if p.x < 0 || p.y < 0 || p.x >= size.x as i32 || p.y >= size.y as i32 {
+            continue;
+        }
+        let [r, g, b, a] = pixel.0.map(|c| c as f32 / 255.);
+        let mask = match options.channel {
+            MaskChannel::Luminance => 0.2126 * r + 0.7152 * g + 0.0722 * b,
+            MaskChannel::Alpha => a,
+        };
+        let coverage = ((mask - options.outside) / range).clamp(0., 1.);
+        field[(p.y as u32 * size.x + p.x as u32) as usize] = 0.5 - coverage;
+    }
+    field
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma, Rgba, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn dark_pixels_are_solid_by_default() {
+        let img = DynamicImage::ImageLuma8(GrayImage::from_fn(4, 1, |x, _| Luma([[0, 255, 128, 64][x as usize]])));
+        let options = ImportOptions { resize: false, ..ImportOptions::default() };
+        let field = mask_field(&img, UVec2::new(4, 1), &options);
+        assert_eq!(field[0], -0.5);
+        assert_eq!(field[1], 0.5);
+        assert!(field[2].abs() < 0.02);
+        assert!(field[3] < 0. && field[3] > -0.5);
+    }
+
+    #[test]
+    fn inverted_thresholds_make_bright_pixels_solid() {
+        let img = DynamicImage::ImageLuma8(GrayImage::from_fn(2, 1, |x, _| Luma([[255, 0][x as usize]])));
+        let options = ImportOptions { inside: 0.25, outside: 0.75, resize: false, ..ImportOptions::default() };
+        let inverted = ImportOptions { inside: 0.75, outside: 0.25, ..options };
+        assert_eq!(mask_field(&img, UVec2::new(2, 1), &options), vec![0.5, -0.5]);
+        assert_eq!(mask_field(&img, UVec2::new(2, 1), &inverted), vec![-0.5, 0.5]);
+    }
+
+    #[test]
+    fn small_images_are_centered_in_empty_space() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([0, 0, 0, 255])));
+        let options = ImportOptions { channel: MaskChannel::Alpha, inside: 0.75, outside: 0.25, resize: false };
+        let field = mask_field(&img, UVec2::new(4, 4), &options);
+        for y in 0..4 {
+            for x in 0..4 {
+                let solid = (1..3).contains(&x) && (1..3).contains(&y);
+                assert_eq!(field[y * 4 + x], if solid { -0.5 } else { 0.5 });
+            }
+        }
+    }
+
+    #[test]
+    fn resized_images_cover_the_field() {
+        let img = DynamicImage::ImageLuma8(GrayImage::from_pixel(3, 5, Luma([0])));
+        let field = mask_field(&img, UVec2::new(8, 8), &ImportOptions::default());
+        assert!(field.iter().all(|d| *d == -0.5));
+    }
+}
@@ -0,0 +1,307 @@
+use glam::*;
+use wgpu::PipelineCompilationOptions;
+
+use crate::renderer::texture;
+
+use super::tiles::Tiles;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct Uniforms {
+    pub size: [u32; 2],
+    pub step: u32,
+    pub wraps: u32,
+    pub texel_size: [f32; 2],
+    pub first_texel: [i32; 2],
+}
+
+const SEEDS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Float;
+const WORKGROUP_SIZE: u32 = 8;
+
+// Turns the zero level set of a scalar field (negative inside) into a signed distance field
+// with the jump flooding algorithm. Texels next to a sign change are seeded with the
+// interpolated crossing, the seeds are flooded in log2(size) passes with halving step sizes
+// plus one extra pass at step 1, and a resolve pass writes the signed distance in world units.
+// The textures are a toroidal ring of tiles, texels are only neighbours when their tiles are
+// neighbours in the world: lookups stop at the edges of the resident window unless it covers
+// a whole world that wraps around.
+pub struct JumpFlood {
+    size: UVec2,
+    texel_size: Vec2,
+    steps: Vec<u32>,
+    uniform_stride: u64,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    pub field_bind_group_layout: wgpu::BindGroupLayout,
+    _seeds: [texture::Texture; 2],
+    seeds_bind_groups: [wgpu::BindGroup; 2],
+    seeds_out_bind_groups: [wgpu::BindGroup; 2],
+    seed_pipeline: wgpu::ComputePipeline,
+    flood_pipeline: wgpu::ComputePipeline,
+    resolve_pipeline: wgpu::RenderPipeline,
+}
+
+impl JumpFlood {
+    pub fn new(size: UVec2, world_size: Vec2, output_format: wgpu::TextureFormat, device: &wgpu::Device) -> Self {
+        let seeds = [
+            texture::Texture::new_intermediate4(device, size, SEEDS_FORMAT, wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING),
+            texture::Texture::new_intermediate4(device, size, SEEDS_FORMAT, wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::STORAGE_BINDING),
+        ];
+
+        let mut steps = Vec::new();
+        let mut step = size.max_element().next_power_of_two() / 2;
+        while step >= 1 {
+            steps.push(step);
+            step /= 2;
+        }
+        steps.push(1);
+
+        let uniform_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("jump_flood_uniform_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::FRAGMENT,
+                    count: None,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<Uniforms>() as u64),
+                    },
+                }
+            ],
+        });
+
+        let sampled_entry = wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::COMPUTE | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let field_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("jump_flood_field_bind_group_layout"),
+            entries: &[sampled_entry],
+        });
+        let seeds_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("jump_flood_seeds_bind_group_layout"),
+            entries: &[sampled_entry],
+        });
+        let seeds_out_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("jump_flood_seeds_out_bind_group_layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::StorageTexture {
+                        access: wgpu::StorageTextureAccess::WriteOnly,
+                        format: SEEDS_FORMAT,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let seeds_bind_groups = [
+            Self::create_view_bind_group(device, &seeds_bind_group_layout, &seeds[0].view),
+            Self::create_view_bind_group(device, &seeds_bind_group_layout, &seeds[1].view),
+        ];
+        let seeds_out_bind_groups = [
+            Self::create_view_bind_group(device, &seeds_out_bind_group_layout, &seeds[0].view),
+            Self::create_view_bind_group(device, &seeds_out_bind_group_layout, &seeds[1].view),
+        ];
+
+        // Slot 0 is shared by the seed and resolve passes, slot i + 1 holds flood step i. They are
+        // written by `run` since the window moves.
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let uniform_stride = (std::mem::size_of::<Uniforms>() as u64).div_ceil(alignment) * alignment;
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Jump flood uniforms"),
+            size: uniform_stride * (steps.len() + 1) as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &uniform_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &uniform_buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<Uniforms>() as u64),
+                }),
+            }],
+            label: None,
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Jump flood shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("jump_flood.wgsl").into()),
+        });
+
+        let compute_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Jump flood"),
+            bind_group_layouts: &[&uniform_bind_group_layout, &field_bind_group_layout, &seeds_bind_group_layout, &seeds_out_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let seed_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Jump flood seed pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &shader,
+            entry_point: "main_seed",
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+
+        let flood_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Jump flood pipeline"),
+            layout: Some(&compute_pipeline_layout),
+            module: &shader,
+            entry_point: "main_flood",
+            compilation_options: PipelineCompilationOptions::default(),
+        });
+
+        let resolve_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Jump flood resolve"),
+            bind_group_layouts: &[&uniform_bind_group_layout, &field_bind_group_layout, &seeds_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let resolve_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Jump flood resolve"),
+            layout: Some(&resolve_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "main_vert",
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "main_resolve",
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: output_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Self {
+            size,
+            texel_size: world_size / size.as_vec2(),
+            steps,
+            uniform_stride,
+            uniform_buffer,
+            uniform_bind_group,
+            field_bind_group_layout,
+            _seeds: seeds,
+            seeds_bind_groups,
+            seeds_out_bind_groups,
+            seed_pipeline,
+            flood_pipeline,
+            resolve_pipeline,
+        }
+    }
+
+    fn create_view_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, view: &wgpu::TextureView) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+            ],
+            label: None,
+        })
+    }
+
+    // The field texture must be `size` texels and readable as unfilterable float
+    pub fn create_field_bind_group(&self, device: &wgpu::Device, view: &wgpu::TextureView) -> wgpu::BindGroup {
+        Self::create_view_bind_group(device, &self.field_bind_group_layout, view)
+    }
+
+    fn uniform_data(&self, tiles: &Tiles) -> Vec<u8> {
+        let stride = self.uniform_stride as usize;
+        let mut data = vec![0u8; stride * (self.steps.len() + 1)];
+        for (i, step) in std::iter::once(0).chain(self.steps.iter().copied()).enumerate() {
+            let uniforms = Uniforms {
+                size: self.size.into(),
+                step,
+                wraps: tiles.window_wraps() as u32,
+                texel_size: self.texel_size.into(),
+                first_texel: tiles.first_texel().into(),
+            };
+            data[i * stride..i * stride + std::mem::size_of::<Uniforms>()].copy_from_slice(bytemuck::bytes_of(&uniforms));
+        }
+        data
+    }
+
+    // Records the whole transform over the resident window of `tiles` into `encoder`, writing
+    // the distance field into `output`. Only one run can be recorded per submit.
+    pub fn run(&self, encoder: &mut wgpu::CommandEncoder, queue: &wgpu::Queue, field_bind_group: &wgpu::BindGroup, output: &wgpu::TextureView, tiles: &Tiles) {
+        queue.write_buffer(&self.uniform_buffer, 0, &self.uniform_data(tiles));
+        let workgroups = (self.size + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE;
+        let stride = self.uniform_stride as u32;
+        let mut seeds_index = 0;
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("Jump flood"), timestamp_writes: None, });
+            compute_pass.set_pipeline(&self.seed_pipeline);
+            compute_pass.set_bind_group(0, &self.uniform_bind_group, &[0]);
+            compute_pass.set_bind_group(1, field_bind_group, &[]);
+            compute_pass.set_bind_group(2, &self.seeds_bind_groups[1], &[]);
+            compute_pass.set_bind_group(3, &self.seeds_out_bind_groups[0], &[]);
+            compute_pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
+
+            compute_pass.set_pipeline(&self.flood_pipeline);
+            for i in 0..self.steps.len() {
+                compute_pass.set_bind_group(0, &self.uniform_bind_group, &[(i as u32 + 1) * stride]);
+                compute_pass.set_bind_group(2, &self.seeds_bind_groups[seeds_index], &[]);
+                compute_pass.set_bind_group(3, &self.seeds_out_bind_groups[1 - seeds_index], &[]);
+                compute_pass.dispatch_workgroups(workgroups.x, workgroups.y, 1);
+                seeds_index = 1 - seeds_index;
+            }
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Jump flood resolve"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: output,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    }
+                })
+            ],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.resolve_pipeline);
+        render_pass.set_bind_group(0, &self.uniform_bind_group, &[0]);
+        render_pass.set_bind_group(1, field_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.seeds_bind_groups[seeds_index], &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
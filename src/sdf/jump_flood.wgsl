@@ -0,0 +1,147 @@
+struct Uniforms {
+    size: vec2<u32>,
+    step: u32,
+    // Whether the window covers a whole world that wraps around
+    wraps: u32,
+    texel_size: vec2<f32>,
+    // Texel holding the top left corner of the window, the textures are a toroidal ring
+    first_texel: vec2<i32>,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: Uniforms;
+
+// Scalar field, negative inside. Only the zero crossings and the sign are used, the green and
+// blue channels are passed through to the output untouched.
+@group(1) @binding(0)
+var t_field: texture_2d<f32>;
+
+// Nearest zero crossing found so far for each texel, in window texel coordinates
+@group(2) @binding(0)
+var t_seeds: texture_2d<f32>;
+
+@group(3) @binding(0)
+var t_seeds_out: texture_storage_2d<rg32float, write>;
+
+const NO_SEED: f32 = -1e6;
+
+fn wrapTexel(p: vec2<i32>) -> vec2<i32> {
+    let s = vec2<i32>(uniforms.size);
+    return ((p % s) + s) % s;
+}
+
+// Window coordinates of texture texel `t`, counted from the top left corner of the window
+fn windowTexel(t: vec2<i32>) -> vec2<i32> {
+    return wrapTexel(t - uniforms.first_texel);
+}
+
+// Texture texel at window coordinates `w`
+fn ringTexel(w: vec2<i32>) -> vec2<i32> {
+    return wrapTexel(w + uniforms.first_texel);
+}
+
+fn inWindow(w: vec2<i32>) -> bool {
+    return uniforms.wraps != 0u || (all(w >= vec2<i32>(0)) && all(w < vec2<i32>(uniforms.size)));
+}
+
+fn windowDelta(d: vec2<f32>) -> vec2<f32> {
+    if uniforms.wraps == 0u {
+        return d;
+    }
+    let s = vec2<f32>(uniforms.size);
+    return d - s * round(d / s);
+}
+
+fn field(w: vec2<i32>) -> f32 {
+    return textureLoad(t_field, ringTexel(w), 0).r;
+}
+
+// Seeds every texel next to a sign change with the interpolated zero crossing
+@compute @workgroup_size(8, 8)
+fn main_seed(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= uniforms.size) {
+        return;
+    }
+    var offsets = array<vec2<i32>, 4>(
+        vec2<i32>(1, 0),
+        vec2<i32>(-1, 0),
+        vec2<i32>(0, 1),
+        vec2<i32>(0, -1),
+    );
+    let p = vec2<i32>(id.xy);
+    let w = windowTexel(p);
+    let f = field(w);
+    var best = vec2<f32>(NO_SEED);
+    var best_t = 2.;
+    for (var i = 0; i < 4; i = i + 1) {
+        let o = offsets[i];
+        if !inWindow(w + o) {
+            continue;
+        }
+        let g = field(w + o);
+        if (f < 0.) != (g < 0.) {
+            let t = f / (f - g);
+            if t < best_t {
+                best_t = t;
+                best = vec2<f32>(w) + 0.5 + t * vec2<f32>(o);
+            }
+        }
+    }
+    textureStore(t_seeds_out, p, vec4<f32>(best, 0., 0.));
+}
+
+@compute @workgroup_size(8, 8)
+fn main_flood(@builtin(global_invocation_id) id: vec3<u32>) {
+    if any(id.xy >= uniforms.size) {
+        return;
+    }
+    let p = vec2<i32>(id.xy);
+    let w = windowTexel(p);
+    let center = vec2<f32>(w) + 0.5;
+    let k = i32(uniforms.step);
+    var best = vec2<f32>(NO_SEED);
+    var best_d = 1e20;
+    for (var y = -1; y <= 1; y = y + 1) {
+        for (var x = -1; x <= 1; x = x + 1) {
+            let q = w + k * vec2<i32>(x, y);
+            if !inWindow(q) {
+                continue;
+            }
+            let seed = textureLoad(t_seeds, ringTexel(q), 0).xy;
+            if seed.x > NO_SEED {
+                let d = windowDelta(center - seed) * uniforms.texel_size;
+                let dd = dot(d, d);
+                if dd < best_d {
+                    best_d = dd;
+                    best = seed;
+                }
+            }
+        }
+    }
+    textureStore(t_seeds_out, p, vec4<f32>(best, 0., 0.));
+}
+
+// Resolve pass
+
+@vertex
+fn main_vert(@builtin(vertex_index) in_vertex_index: u32) -> @builtin(position) vec4<f32> {
+    var vertices: array<vec2<f32>, 3> = array<vec2<f32>, 3>(
+        vec2<f32>(-1., -3.0),
+        vec2<f32>(3.0, 1.),
+        vec2<f32>(-1., 1.),
+    );
+    return vec4<f32>(vertices[in_vertex_index], 0.0, 1.0);
+}
+
+@fragment
+fn main_resolve(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let p = vec2<i32>(floor(position.xy));
+    let field = textureLoad(t_field, p, 0);
+    let s = select(1., -1., field.r < 0.);
+    let seed = textureLoad(t_seeds, p, 0).xy;
+    if seed.x <= NO_SEED {
+        return vec4<f32>(s * length(vec2<f32>(uniforms.size) * uniforms.texel_size), field.gb, 0.);
+    }
+    let center = vec2<f32>(windowTexel(p)) + 0.5;
+    return vec4<f32>(s * length(windowDelta(center - seed) * uniforms.texel_size), field.gb, 0.);
+}
@@ -0,0 +1,166 @@
+pub const MAX_MATERIALS: usize = 16;
+pub const DEFAULT_BEVEL_WIDTH: f32 = 1.;
+
+// How the terrain rises from the ground at its edges to its full height. Wide bevels turn the
+// terrain into slopes and hills.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BevelProfile {
+    // Vertical walls
+    Flat,
+    // Straight slope
+    Chamfer,
+    // Quarter circle, steep at the edge and level at the top
+    Round,
+    // Level at both ends
+    Smooth,
+}
+
+impl BevelProfile {
+    pub const ALL: [BevelProfile; 4] = [BevelProfile::Flat, BevelProfile::Chamfer, BevelProfile::Round, BevelProfile::Smooth];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BevelProfile::Flat => "flat",
+            BevelProfile::Chamfer => "chamfer",
+            BevelProfile::Round => "round",
+            BevelProfile::Smooth => "smooth",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Material {
+    pub name: String,
+    pub albedo: [f32; 3],
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emissive: [f32; 3],
+    // Distance from the edge at which the terrain reaches its full height, in world units
+    pub bevel_width: f32,
+    pub bevel_profile: BevelProfile,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct MaterialData {
+    pub albedo_metallic: [f32; 4],
+    pub emissive_roughness: [f32; 4],
+    // Width, profile
+    pub bevel: [f32; 4],
+}
+
+impl Material {
+    pub fn new(name: &str, albedo: [f32; 3], metallic: f32, roughness: f32, emissive: [f32; 3]) -> Self {
+        Self {
+            name: name.to_string(),
+            albedo,
+            metallic,
+            roughness,
+            emissive,
+            bevel_width: DEFAULT_BEVEL_WIDTH,
+            bevel_profile: BevelProfile::Flat,
+        }
+    }
+
+    // Bevels are opt-in, materials have vertical walls otherwise
+    pub fn with_bevel(mut self, width: f32, profile: BevelProfile) -> Self {
+        self.bevel_width = width;
+        self.bevel_profile = profile;
+        self
+    }
+
+    pub fn to_data(&self) -> MaterialData {
+        MaterialData {
+            albedo_metallic: [self.albedo[0], self.albedo[1], self.albedo[2], self.metallic],
+            emissive_roughness: [self.emissive[0], self.emissive[1], self.emissive[2], self.roughness],
+            bevel: [self.bevel_width, self.bevel_profile as u32 as f32, 0., 0.],
+        }
+    }
+}
+
+// Material 0 is what untouched terrain uses
+pub fn default_palette() -> Vec<Material> {
+    vec![
+        Material::new("stone", [0.5, 0.5, 0.5], 0., 0.1, [0., 0., 0.]),
+        Material::new("rock", [0.35, 0.3, 0.25], 0., 0.9, [0., 0., 0.]),
+        Material::new("metal", [0.9, 0.9, 0.95], 1., 0.3, [0., 0., 0.]),
+        Material::new("ice", [0.7, 0.85, 1.], 0., 0.05, [0., 0., 0.]),
+        Material::new("grass", [0.2, 0.45, 0.1], 0., 0.8, [0., 0., 0.]),
+        Material::new("lava", [0.1, 0.02, 0.], 0., 0.6, [4., 0.8, 0.1]),
+    ]
+}
+
+pub fn palette_data(palette: &[Material]) -> [MaterialData; MAX_MATERIALS] {
+    let mut data = [MaterialData::default(); MAX_MATERIALS];
+    for (d, m) in data.iter_mut().zip(palette) {
+        *d = m.to_data();
+    }
+    data
+}
+
+impl Default for MaterialData {
+    fn default() -> Self {
+        Material::new("", [0.5, 0.5, 0.5], 0., 0.1, [0., 0., 0.]).to_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn materials_pack_into_the_uniform_layout() {
+        let material = Material::new("test", [0.1, 0.2, 0.3], 0.4, 0.5, [0.6, 0.7, 0.8]).with_bevel(3., BevelProfile::Smooth);
+        let data = material.to_data();
+        assert_eq!(data.albedo_metallic, [0.1, 0.2, 0.3, 0.4]);
+        assert_eq!(data.emissive_roughness, [0.6, 0.7, 0.8, 0.5]);
+        assert_eq!(data.bevel, [3., 3., 0., 0.]);
+        assert_eq!(std::mem::size_of::<MaterialData>(), 48);
+    }
+
+    #[test]
+    fn unused_palette_entries_are_default() {
+        let palette = default_palette();
+        assert!(!palette.is_empty() && palette.len() <= MAX_MATERIALS);
+        let data = palette_data(&palette);
+        for (d, m) in data.iter().zip(&palette) {
+            assert_eq!(d.albedo_metallic, m.to_data().albedo_metallic);
+        }
+        for d in &data[palette.len()..] {
+            assert_eq!(d.albedo_metallic, MaterialData::default().albedo_metallic);
+        }
+    }
+
+    #[test]
+    fn palettes_beyond_max_materials_are_cut_off() {
+        let palette = vec![Material::new("red", [1., 0., 0.], 0., 1., [0., 0., 0.]); MAX_MATERIALS + 4];
+        let data = palette_data(&palette);
+        assert!(data.iter().all(|d| d.albedo_metallic == [1., 0., 0., 0.]));
+    }
+
+    #[test]
+    fn bevels_are_opt_in() {
+        let material = Material::new("plain", [0.5, 0.5, 0.5], 0., 0.5, [0., 0., 0.]);
+        assert_eq!(material.bevel_profile, BevelProfile::Flat);
+        assert_eq!(material.to_data().bevel[1], 0.);
+        let bevelled = material.with_bevel(2., BevelProfile::Round);
+        assert_eq!(bevelled.to_data().bevel, [2., 2., 0., 0.]);
+    }
+
+    #[test]
+    fn the_default_palette_has_vertical_walls() {
+        let palette = default_palette();
+        let names: Vec<&str> = palette.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["stone", "rock", "metal", "ice", "grass", "lava"]);
+        assert!(palette.iter().all(|m| m.bevel_profile == BevelProfile::Flat));
+    }
+
+    #[test]
+    fn profiles_match_the_shader_constants() {
+        let shader = include_str!("../renderer/terrain_height.wgsl");
+        for profile in &BevelProfile::ALL[1..] {
+            let constant = format!("const BEVEL_{}: u32 = {}u;", profile.name().to_uppercase(), *profile as u32);
+            assert!(shader.contains(&constant), "{}", constant);
+        }
+    }
+}
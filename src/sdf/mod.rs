@@ -0,0 +1,1236 @@
+pub mod brush;
+pub mod edit;
+pub mod file;
+pub mod generate;
+pub mod history;
+pub mod import;
+pub mod jump_flood;
+pub mod material;
+pub mod query;
+pub mod tiles;
+
+use std::io;
+use std::path::Path;
+
+use glam::*;
+use wgpu::PipelineCompilationOptions;
+
+use crate::renderer::texture;
+
+use self::brush::{Brush, BrushData, BrushShape};
+use self::edit::{Edit, EditOp};
+use self::file::SdfFile;
+use self::generate::{Generator, Layer};
+use self::history::{History, HistoryConfig, Snapshot};
+use self::import::ImportOptions;
+use self::jump_flood::JumpFlood;
+use self::material::{Material, MaterialData, MAX_MATERIALS};
+use self::query::{Mirror, TerrainQuery};
+use self::tiles::{StoredTile, TileConfig, TileIndirection, Tiles, Topology};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct Uniforms {
+    pub world_pos: [f32; 2],
+    pub world_size: [f32; 2],
+    pub inv_world_size: [f32; 2],
+    pub stroke_delta: [f32; 2],
+    pub brush: BrushData,
+    pub stroke_steps: u32,
+    pub material: u32,
+    pub height: f32,
+    pub dummy: u32,
+}
+
+impl Default for Uniforms {
+    fn default() -> Uniforms {
+        Uniforms {
+            world_pos: [0.0, 0.0],
+            world_size: [1.0, 1.0],
+            inv_world_size: [1.0, 1.0],
+            stroke_delta: [0.0, 0.0],
+            brush: BrushData::default(),
+            stroke_steps: 0,
+            material: 0,
+            height: 0.0,
+            dummy: 0,
+        }
+    }
+}
+
+pub struct SDF {
+    textures: [texture::Texture; 2],
+    pipeline: wgpu::RenderPipeline,
+    subtract_pipeline: wgpu::RenderPipeline,
+    height_pipeline: wgpu::RenderPipeline,
+    uniforms: Uniforms,
+    uniform_stride: u64,
+    uniform_capacity: usize,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group_layout: wgpu::BindGroupLayout,
+    uniform_bind_group: wgpu::BindGroup,
+    // Queued edits with the history step they belong to
+    edits: Vec<(u64, Edit)>,
+    history: History,
+    // Step of the stroke in progress, the step being recorded into history and the last step handed out
+    stroke: Option<u64>,
+    recording: Option<u64>,
+    last_step: u64,
+    jump_flood: JumpFlood,
+    generator: Generator,
+    tiles: Tiles,
+    indirection: TileIndirection,
+    mirror: Mirror,
+    redistance_bind_groups: [wgpu::BindGroup; 2],
+    redistance_pending: bool,
+    redistance_interval: usize,
+    edits_since_redistance: usize,
+    texture_index: usize,
+    pub sdf_bind_group_layout: wgpu::BindGroupLayout,
+    sdf_bind_groups: [wgpu::BindGroup; 2],
+    sampler: wgpu::Sampler,
+    palette_buffer: wgpu::Buffer,
+}
+
+// Red holds the distance, green the material palette index and blue the terrain height, as an
+// offset from the full height in -1..0 so that zero is untouched terrain
+const TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const TEXEL_BYTES: u32 = 8;
+
+pub(crate) const SHADER: &str = concat!(include_str!("../renderer/tiles.wgsl"), include_str!("sdf.wgsl"));
+
+const MAX_STROKE_STEPS: u32 = 64;
+const INITIAL_EDIT_CAPACITY: usize = 16;
+
+impl SDF {
+    // `size` and `world_size` describe the resident window around the camera, the whole world
+    // is `tile_config.world / tile_config.resident` times larger at the same texel density
+    pub fn new(size: UVec2, world_size: Vec2, tile_config: TileConfig, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let textures = [
+            Self::create_texture(device, size),
+            Self::create_texture(device, size),
+        ];
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Init SDF"),
+        });
+
+        {
+            let l = Self::empty_distance(size) as f64;
+            encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &textures[0].view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color {
+                                r: l,
+                                g: 0.0,
+                                b: 0.0,
+                                a: 0.0,
+                            }),
+                            store: wgpu::StoreOp::Store,
+                        }
+                    })
+                ],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });                    
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        // The textures are a toroidal ring of tiles whatever the world topology is
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("SDF"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let uniform_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        count: None,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<Uniforms>() as u64),
+                        },
+                    }
+                ],
+            });
+
+        let sdf_bind_group_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Sint,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("sdf_texture_bind_group_layout"),
+            }
+        );
+    
+        let tile_size = world_size / tile_config.resident.as_vec2();
+        let tiles = Tiles::new(tile_config, size, world_size);
+        let indirection = TileIndirection::new(&tiles, tile_size.min_element(), device, queue);
+        let mirror = Mirror::new(size, Self::empty_distance(size), tiles.num_slots());
+
+        let palette_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Terrain palette"),
+            size: (MAX_MATERIALS * std::mem::size_of::<MaterialData>()) as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&palette_buffer, 0, bytemuck::cast_slice(&material::palette_data(&material::default_palette())));
+
+        let sdf_bind_groups = [
+            Self::create_output_bind_group(device, &sdf_bind_group_layout, &textures[0].view, &sampler, &indirection, &palette_buffer),
+            Self::create_output_bind_group(device, &sdf_bind_group_layout, &textures[1].view, &sampler, &indirection, &palette_buffer),
+        ];
+    
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SDF"),
+            bind_group_layouts: &[&uniform_bind_group_layout, &sdf_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let mut uniforms = Uniforms::default();
+        uniforms.world_size = [world_size.x, world_size.y];
+        uniforms.inv_world_size = [1. / world_size.x, 1. / world_size.y];
+
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+        let uniform_stride = uniform_stride(alignment);
+        let (uniform_buffer, uniform_bind_group) = Self::create_uniform_buffer(device, &uniform_bind_group_layout, uniform_stride, INITIAL_EDIT_CAPACITY);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("SDF Shader"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("SDF"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "main_vert",
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "main_frag",
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: TEXTURE_FORMAT,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        let subtract_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("SDF"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "main_vert",
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "main_frag_subtract",
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: TEXTURE_FORMAT,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        let height_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("SDF"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "main_vert",
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "main_frag_height",
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: TEXTURE_FORMAT,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        let jump_flood = JumpFlood::new(size, world_size, TEXTURE_FORMAT, device);
+        let redistance_bind_groups = [
+            jump_flood.create_field_bind_group(device, &textures[0].view),
+            jump_flood.create_field_bind_group(device, &textures[1].view),
+        ];
+        let generator = Generator::new(size, world_size, &jump_flood.field_bind_group_layout, device);
+
+        return Self {
+            textures,
+            pipeline,
+            subtract_pipeline,
+            height_pipeline,
+            uniforms,
+            uniform_stride,
+            uniform_capacity: INITIAL_EDIT_CAPACITY,
+            uniform_buffer,
+            uniform_bind_group_layout,
+            uniform_bind_group,
+            edits: Vec::new(),
+            history: History::new(HistoryConfig::default()),
+            stroke: None,
+            recording: None,
+            last_step: 0,
+            jump_flood,
+            generator,
+            tiles,
+            indirection,
+            mirror,
+            redistance_bind_groups,
+            redistance_pending: false,
+            redistance_interval: 0,
+            edits_since_redistance: 0,
+            texture_index: 0,
+            sdf_bind_group_layout,
+            sdf_bind_groups,
+            sampler,
+            palette_buffer,
+        }
+    }
+
+    fn create_output_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, view: &wgpu::TextureView, sampler: &wgpu::Sampler, indirection: &TileIndirection, palette_buffer: &wgpu::Buffer) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: indirection.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(indirection.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: palette_buffer.as_entire_binding(),
+                },
+            ],
+            label: None,
+        })
+    }
+
+    fn wrap(&self, p: Vec2) -> Vec2 {
+        if !self.topology().wraps() {
+            return p;
+        }
+        let world_size = Vec2::from(self.uniforms.world_size);
+        let s = (p / world_size).abs().ceil() + 0.5;
+        (p + s * world_size) % world_size - 0.5 * world_size
+    }
+
+    // Distance stored in texels that have never been edited
+    fn empty_distance(size: UVec2) -> f32 {
+        size.as_vec2().length()
+    }
+
+    fn create_texture(device: &wgpu::Device, size: UVec2) -> texture::Texture {
+        texture::Texture::new_intermediate4(device, size, TEXTURE_FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST)
+    }
+
+    // Holds one tile outside the SDF textures, for history and the tile store
+    fn create_tile_texture(device: &wgpu::Device, texels: UVec2) -> texture::Texture {
+        texture::Texture::new_intermediate4(device, texels, TEXTURE_FORMAT, wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST)
+    }
+
+    fn create_uniform_buffer(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, stride: u64, capacity: usize) -> (wgpu::Buffer, wgpu::BindGroup) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("SDF edits"),
+            size: stride * capacity as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<Uniforms>() as u64),
+                }),
+            }],
+            label: None,
+        });
+        (buffer, bind_group)
+    }
+
+    fn edit_uniforms(&self, edit: &Edit) -> Uniforms {
+        let delta = self.wrap(edit.to - edit.from);
+        Uniforms {
+            world_pos: edit.to.into(),
+            stroke_delta: delta.into(),
+            brush: edit.brush.to_data(),
+            stroke_steps: ((delta.length() / edit.brush.stroke_spacing()).ceil() as u32).min(MAX_STROKE_STEPS),
+            material: edit.brush.material,
+            height: edit.height_change(),
+            ..self.uniforms
+        }
+    }
+
+    // World space bounds of everything an edit can touch, unwrapped around `edit.to`
+    fn edit_bounds(&self, edit: &Edit) -> (Vec2, Vec2) {
+        let r = Vec2::splat(edit.brush.bounding_radius());
+        let delta = self.wrap(edit.to - edit.from);
+        let from = edit.to - delta;
+        (from.min(edit.to) - r, from.max(edit.to) + r)
+    }
+
+    // Texel regions of the SDF textures covering a world space rectangle, see `texel_regions`
+    fn texel_regions(&self, min: Vec2, max: Vec2) -> Vec<(UVec2, UVec2)> {
+        let edges = (!self.topology().wraps()).then(|| self.world_size());
+        texel_regions(min, max, self.size(), self.window_size(), edges)
+    }
+
+    pub fn add(&mut self, from: Vec2, to: Vec2, brush: &Brush) {
+        self.push_edit(Edit::new(EditOp::Add, from, to, brush));
+    }
+
+    pub fn subtract(&mut self, from: Vec2, to: Vec2, brush: &Brush) {
+        self.push_edit(Edit::new(EditOp::Subtract, from, to, brush));
+    }
+
+    pub fn raise(&mut self, from: Vec2, to: Vec2, brush: &Brush) {
+        self.push_edit(Edit::new(EditOp::Raise, from, to, brush));
+    }
+
+    pub fn lower(&mut self, from: Vec2, to: Vec2, brush: &Brush) {
+        self.push_edit(Edit::new(EditOp::Lower, from, to, brush));
+    }
+
+    // Other brushes than the circle are sampled along the stroke, long strokes are split so that
+    // every piece gets all the samples it needs
+    pub fn push_edit(&mut self, edit: Edit) {
+        let max_length = match edit.brush.shape {
+            BrushShape::Circle { .. } => f32::INFINITY,
+            _ => MAX_STROKE_STEPS as f32 * edit.brush.stroke_spacing(),
+        };
+        let step = match self.stroke {
+            Some(step) => step,
+            None => self.next_step(),
+        };
+        for edit in edit.split(self.wrap(edit.to - edit.from), max_length) {
+            self.edits.push((step, edit));
+        }
+    }
+
+    fn next_step(&mut self) -> u64 {
+        self.last_step += 1;
+        self.last_step
+    }
+
+    // Edits pushed until `end_stroke` are undone together, other edits one by one
+    pub fn begin_stroke(&mut self) {
+        if self.stroke.is_none() {
+            self.stroke = Some(self.next_step());
+        }
+    }
+
+    pub fn end_stroke(&mut self) {
+        self.stroke = None;
+    }
+
+    pub fn in_stroke(&self) -> bool {
+        self.stroke.is_some()
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn set_history_config(&mut self, config: HistoryConfig) {
+        self.history.set_config(config);
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.history.can_undo()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.history.can_redo()
+    }
+
+    // Pending edits are applied first so that they are part of the history
+    pub fn undo(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        self.swap_step(false, device, queue)
+    }
+
+    pub fn redo(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        self.swap_step(true, device, queue)
+    }
+
+    // Swaps the snapshots of the previous or next history step with the current contents of
+    // their tiles. Resident tiles are swapped in the active texture, the others in the tile store.
+    fn swap_step(&mut self, redo: bool, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some(if redo { "SDF redo" } else { "SDF undo" }),
+        });
+        self.record_edits(device, queue, &mut encoder);
+        self.recording = None;
+
+        let empty = self.empty_tile();
+        let target = &self.textures[self.texture_index];
+        let tiles = &mut self.tiles;
+        let mirror = &mut self.mirror;
+        let texels = tiles.tile_texels();
+        let extent = wgpu::Extent3d { width: texels.x, height: texels.y, depth_or_array_layers: 1 };
+        let swap = |snapshot: &mut Snapshot<texture::Texture>| {
+            match tiles.resident_slot(snapshot.tile) {
+                Some(slot) => {
+                    let origin = tiles.slot_origin(slot);
+                    let slot_copy = wgpu::ImageCopyTexture {
+                        texture: &target.texture,
+                        mip_level: 0,
+                        origin: wgpu::Origin3d { x: origin.x, y: origin.y, z: 0 },
+                        aspect: wgpu::TextureAspect::All,
+                    };
+                    let current = Self::create_tile_texture(device, texels);
+                    encoder.copy_texture_to_texture(slot_copy, current.texture.as_image_copy(), extent);
+                    let other = std::mem::replace(&mut snapshot.texture, current);
+                    encoder.copy_texture_to_texture(other.texture.as_image_copy(), slot_copy, extent);
+                    tiles.mark_slot_dirty(slot);
+                    mirror.invalidate_region(origin, texels);
+                }
+                None => {
+                    let current = match tiles.take_stored(snapshot.tile) {
+                        Some(StoredTile::Texture(pending)) => pending.texture,
+                        stored => {
+                            let texture = Self::create_tile_texture(device, texels);
+                            let data = match &stored {
+                                Some(StoredTile::Bytes(bytes)) => bytes,
+                                _ => &empty,
+                            };
+                            Self::write_tile(queue, texture.texture.as_image_copy(), texels, data);
+                            texture
+                        }
+                    };
+                    let other = std::mem::replace(&mut snapshot.texture, current);
+                    tiles.store_texture(snapshot.tile, other, device, &mut encoder);
+                }
+            }
+        };
+        let done = if redo { self.history.redo(swap) } else { self.history.undo(swap) };
+        queue.submit(std::iter::once(encoder.finish()));
+        done
+    }
+
+    // Rebuilds a true distance field from the zero level set on the next `apply_edits` outside
+    // of a stroke. Smooth unions and subtractions over-estimate distances away from the surface,
+    // which slows down or breaks the sphere tracing in the light map.
+    pub fn redistance(&mut self) {
+        self.redistance_pending = true;
+    }
+
+    // Redistances automatically after every `interval` applied edits, 0 disables it
+    pub fn set_redistance_interval(&mut self, interval: usize) {
+        self.redistance_interval = interval;
+    }
+
+    pub fn redistance_interval(&self) -> usize {
+        self.redistance_interval
+    }
+
+    // Applies the queued edits and redistances when requested or due. Redistancing waits for
+    // the current stroke to end and is a history step of its own.
+    pub fn apply_edits(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder) {
+        self.edits_since_redistance += self.edits.len();
+        self.record_edits(device, queue, encoder);
+        if self.recording != self.stroke {
+            self.history.end_step();
+            self.recording = None;
+        }
+        if self.redistance_interval > 0 && self.edits_since_redistance >= self.redistance_interval {
+            self.redistance_pending = true;
+        }
+        if self.redistance_pending && self.stroke.is_none() {
+            self.snapshot_window(device, encoder);
+            let field_bind_group = &self.redistance_bind_groups[self.texture_index];
+            self.texture_index = (self.texture_index + 1) % 2;
+            self.jump_flood.run(encoder, queue, field_bind_group, &self.textures[self.texture_index].view, &self.tiles);
+            self.redistance_pending = false;
+            self.edits_since_redistance = 0;
+            self.mirror.invalidate();
+        }
+        self.mirror.record(&self.textures[self.texture_index], &self.tiles, device, encoder);
+    }
+
+    // Copies the contents of `slot` in the active texture into a new tile texture
+    fn copy_slot(&self, slot: usize, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) -> texture::Texture {
+        let texels = self.tiles.tile_texels();
+        let origin = self.tiles.slot_origin(slot);
+        let texture = Self::create_tile_texture(device, texels);
+        encoder.copy_texture_to_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.textures[self.texture_index].texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x: origin.x, y: origin.y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            texture.texture.as_image_copy(),
+            texture.size,
+        );
+        texture
+    }
+
+    // Keeps the contents of the resident tiles in `slots` from before the current history step
+    // and marks them modified
+    fn snapshot_slots(&mut self, slots: impl Iterator<Item = usize>, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        for slot in slots {
+            let Some(tile) = self.tiles.resident(slot) else {
+                continue;
+            };
+            if self.history.needs_snapshot(tile) {
+                let texels = self.tiles.tile_texels();
+                let texture = self.copy_slot(slot, device, encoder);
+                self.history.record(tile, texture, (TEXEL_BYTES * texels.x * texels.y) as usize);
+            }
+            self.tiles.mark_slot_dirty(slot);
+        }
+    }
+
+    // Records a history step for a pass that rewrites the whole window, before recording the pass
+    fn snapshot_window(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        self.history.end_step();
+        self.recording = None;
+        self.history.begin_step();
+        self.snapshot_slots(0..self.tiles.num_slots(), device, encoder);
+        self.history.end_step();
+    }
+
+    // Picks up finished CPU mirror and tile store readbacks, call after submitting the
+    // `apply_edits` encoder
+    pub fn sync_mirror(&mut self, device: &wgpu::Device) {
+        self.mirror.poll(device);
+        self.tiles.poll_store(device, false);
+    }
+
+    // Terrain queries against the CPU mirror, which lags the GPU by a few frames
+    pub fn query(&self) -> TerrainQuery<'_> {
+        TerrainQuery::new(&self.mirror, &self.tiles, self.window_size(), Self::empty_distance(self.size()))
+    }
+
+    // Records every queued edit into `encoder` in queue order. Each edit gets its own slot in
+    // the uniform buffer, selected with a dynamic offset, so all of them can share one submit.
+    // Edits only touch the texels under the brush: those regions are copied into the inactive
+    // texture, which the edit then samples while rendering back into the active one with
+    // scissor rects. The inactive texture is scratch space, everything else stays in place.
+    fn record_edits(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder) {
+        if self.edits.is_empty() {
+            return;
+        }
+        if self.edits.len() > self.uniform_capacity {
+            self.uniform_capacity = self.edits.len().next_power_of_two();
+            let (uniform_buffer, uniform_bind_group) = Self::create_uniform_buffer(device, &self.uniform_bind_group_layout, self.uniform_stride, self.uniform_capacity);
+            self.uniform_buffer = uniform_buffer;
+            self.uniform_bind_group = uniform_bind_group;
+        }
+
+        let stride = self.uniform_stride as usize;
+        let data = pack_uniforms(self.edits.iter().map(|(_, edit)| self.edit_uniforms(edit)), stride);
+        queue.write_buffer(&self.uniform_buffer, 0, &data);
+
+        let edits = std::mem::take(&mut self.edits);
+        for (i, (step, edit)) in edits.iter().enumerate() {
+            if self.recording != Some(*step) {
+                self.history.end_step();
+                self.history.begin_step();
+                self.recording = Some(*step);
+            }
+            let (min, max) = self.edit_bounds(edit);
+            self.snapshot_slots(self.tiles.slots_in(min, max).into_iter(), device, encoder);
+
+            let target = &self.textures[self.texture_index];
+            let scratch = &self.textures[1 - self.texture_index];
+            let sdf_bind_group = &self.sdf_bind_groups[1 - self.texture_index];
+            let regions = self.texel_regions(min, max);
+            for (origin, extent) in &regions {
+                let origin = wgpu::Origin3d { x: origin.x, y: origin.y, z: 0 };
+                encoder.copy_texture_to_texture(
+                    wgpu::ImageCopyTexture { texture: &target.texture, mip_level: 0, origin, aspect: wgpu::TextureAspect::All },
+                    wgpu::ImageCopyTexture { texture: &scratch.texture, mip_level: 0, origin, aspect: wgpu::TextureAspect::All },
+                    wgpu::Extent3d { width: extent.x, height: extent.y, depth_or_array_layers: 1 },
+                );
+                self.mirror.invalidate_region(UVec2::new(origin.x, origin.y), *extent);
+            }
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("SDF edit"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &target.view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        }
+                    })
+                ],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(match edit.op {
+                EditOp::Add => &self.pipeline,
+                EditOp::Subtract => &self.subtract_pipeline,
+                EditOp::Raise | EditOp::Lower => &self.height_pipeline,
+            });
+            render_pass.set_bind_group(0, &self.uniform_bind_group, &[(i * stride) as u32]);
+            render_pass.set_bind_group(1, sdf_bind_group, &[]);
+            for (origin, extent) in &regions {
+                render_pass.set_scissor_rect(origin.x, origin.y, extent.x, extent.y);
+                render_pass.draw(0..3, 0..1);
+            }
+        }
+    }
+
+    pub fn size(&self) -> UVec2 {
+        let size = self.textures[self.texture_index].size;
+        UVec2::new(size.width, size.height)
+    }
+
+    // Size of the resident window
+    pub fn window_size(&self) -> Vec2 {
+        self.uniforms.world_size.into()
+    }
+
+    // Size of the whole world
+    pub fn world_size(&self) -> Vec2 {
+        self.tiles.world_size()
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.tiles.config().topology
+    }
+
+    // Uploads up to MAX_MATERIALS entries, the rest of the palette uses the default material
+    pub fn set_palette(&self, queue: &wgpu::Queue, palette: &[Material]) {
+        queue.write_buffer(&self.palette_buffer, 0, bytemuck::cast_slice(&material::palette_data(palette)));
+    }
+
+    pub fn tiles(&self) -> &Tiles {
+        &self.tiles
+    }
+
+    fn empty_tile(&self) -> Vec<u8> {
+        let texels = self.tiles.tile_texels();
+        let distance = half::f16::from_f32(Self::empty_distance(self.size())).to_bits().to_le_bytes();
+        let zero = half::f16::ZERO.to_bits().to_le_bytes();
+        [distance, zero, zero, zero].concat().repeat((texels.x * texels.y) as usize)
+    }
+
+    fn write_tile(queue: &wgpu::Queue, target: wgpu::ImageCopyTexture, texels: UVec2, data: &[u8]) {
+        queue.write_texture(
+            target,
+            data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(TEXEL_BYTES * texels.x),
+                rows_per_image: Some(texels.y),
+            },
+            wgpu::Extent3d { width: texels.x, height: texels.y, depth_or_array_layers: 1 },
+        );
+    }
+
+    fn slot_copy(&self, slot: usize) -> wgpu::ImageCopyTexture<'_> {
+        let origin = self.tiles.slot_origin(slot);
+        wgpu::ImageCopyTexture {
+            texture: &self.textures[self.texture_index].texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x: origin.x, y: origin.y, z: 0 },
+            aspect: wgpu::TextureAspect::All,
+        }
+    }
+
+    // Moves copies of the contents of `slots` into the tile store, their bytes arrive later
+    fn flush_slots(&mut self, slots: &[usize], device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        for slot in slots {
+            let texture = self.copy_slot(*slot, device, encoder);
+            self.tiles.store_slot(*slot, texture, device, encoder);
+        }
+    }
+
+    // Writes the stored contents of the tile resident in `slot` into the active texture. Byte
+    // uploads happen at the start of the next submit, before the commands of `encoder`.
+    fn upload_slot(&self, slot: usize, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder) {
+        let texels = self.tiles.tile_texels();
+        match self.tiles.resident(slot).and_then(|tile| self.tiles.stored_tile(tile)) {
+            Some(StoredTile::Texture(pending)) => encoder.copy_texture_to_texture(pending.texture.texture.as_image_copy(), self.slot_copy(slot), pending.texture.size),
+            Some(StoredTile::Bytes(bytes)) => Self::write_tile(queue, self.slot_copy(slot), texels, bytes),
+            None => Self::write_tile(queue, self.slot_copy(slot), texels, &self.empty_tile()),
+        }
+    }
+
+    // Empties the slots beyond the edges of a world that does not wrap, after passes that
+    // write the whole window
+    fn clear_outside_world(&self, queue: &wgpu::Queue) {
+        let empty = self.empty_tile();
+        for slot in 0..self.tiles.num_slots() {
+            if self.tiles.resident(slot).is_none() {
+                Self::write_tile(queue, self.slot_copy(slot), self.tiles.tile_texels(), &empty);
+            }
+        }
+    }
+
+    // Streams tiles in and out so that the resident window stays centered on `center`. Modified
+    // tiles leaving the window are copied into the tile store first, nothing waits for their
+    // readback. Returns whether residency changed.
+    pub fn stream(&mut self, center: Vec2, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        let (first, changes) = self.tiles.residency_changes(center);
+        if changes.is_empty() {
+            return false;
+        }
+        let evicted: Vec<usize> = changes.iter().map(|(slot, _)| *slot).filter(|slot| self.tiles.is_dirty(*slot)).collect();
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("SDF eviction"),
+        });
+        self.flush_slots(&evicted, device, &mut encoder);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("SDF streaming"),
+        });
+        for (slot, tile) in changes {
+            self.tiles.make_resident(slot, tile);
+            self.upload_slot(slot, queue, &mut encoder);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        self.tiles.set_first(first);
+        self.indirection.write(&self.tiles, queue);
+        self.mirror.invalidate();
+        true
+    }
+
+    // Gathers the whole world, flushing modified resident tiles to the tile store first and
+    // waiting for every readback
+    fn world_file(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> io::Result<SdfFile> {
+        let dirty: Vec<usize> = (0..self.tiles.num_slots()).filter(|slot| self.tiles.is_dirty(*slot)).collect();
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("SDF readback"),
+        });
+        self.flush_slots(&dirty, device, &mut encoder);
+        queue.submit(std::iter::once(encoder.finish()));
+        self.tiles.poll_store(device, true);
+
+        let world = self.tiles.config().world;
+        let texels = self.tiles.tile_texels();
+        let world_texels = self.tiles.world_texels();
+        let row_bytes = (TEXEL_BYTES * texels.x) as usize;
+        let empty = self.empty_tile();
+        let mut bytes = vec![0u8; (TEXEL_BYTES * world_texels.x * world_texels.y) as usize];
+        for y in 0..world.y as i32 {
+            for x in 0..world.x as i32 {
+                let tile = IVec2::new(x, y);
+                let data = match self.tiles.stored_tile(tile) {
+                    Some(StoredTile::Bytes(bytes)) => bytes,
+                    Some(StoredTile::Texture(_)) => return Err(io::Error::other(format!("Failed to read back terrain tile {}", tile))),
+                    None => &empty,
+                };
+                let origin = self.tiles.file_tile(tile).as_uvec2() * texels;
+                for row in 0..texels.y {
+                    let src = row as usize * row_bytes;
+                    let dst = (TEXEL_BYTES * ((origin.y + row) * world_texels.x + origin.x)) as usize;
+                    bytes[dst..dst + row_bytes].copy_from_slice(&data[src..src + row_bytes]);
+                }
+            }
+        }
+        Ok(SdfFile::from_texels(world_texels, self.world_size(), &bytes))
+    }
+
+    // Replaces the whole world with `file`, resampled to the world texel count and size when
+    // they differ. History starts over from the new state.
+    fn set_world_file(&mut self, file: SdfFile, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let file = file.resample(self.tiles.world_texels(), self.world_size());
+        let bytes = file.to_texels();
+
+        let world = self.tiles.config().world;
+        let texels = self.tiles.tile_texels();
+        let row_bytes = (TEXEL_BYTES * texels.x) as usize;
+        self.tiles.clear_store();
+        for y in 0..world.y as i32 {
+            for x in 0..world.x as i32 {
+                let tile = IVec2::new(x, y);
+                let origin = self.tiles.file_tile(tile).as_uvec2() * texels;
+                let mut data = Vec::with_capacity(row_bytes * texels.y as usize);
+                for row in 0..texels.y {
+                    let src = (TEXEL_BYTES * ((origin.y + row) * file.size.x + origin.x)) as usize;
+                    data.extend_from_slice(&bytes[src..src + row_bytes]);
+                }
+                self.tiles.set_stored(tile, data);
+            }
+        }
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("SDF load"),
+        });
+        for slot in 0..self.tiles.num_slots() {
+            self.tiles.make_resident(slot, self.tiles.resident(slot));
+            self.upload_slot(slot, queue, &mut encoder);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+        self.mirror.invalidate();
+        self.reset_history();
+    }
+
+    // Writes the whole world
+    pub fn save(&mut self, path: &Path, device: &wgpu::Device, queue: &wgpu::Queue) -> io::Result<()> {
+        self.world_file(device, queue)?.write(path)
+    }
+
+    // Replaces the whole world with the contents of `path`, resampled to the world texel count
+    // and size when they differ. History starts over from the loaded state.
+    pub fn load(&mut self, path: &Path, device: &wgpu::Device, queue: &wgpu::Queue) -> io::Result<()> {
+        let file = SdfFile::read(path)?;
+        self.set_world_file(file, device, queue);
+        Ok(())
+    }
+
+    // Changes the resident window resolution to `size` texels and the world to `tile_config`,
+    // keeping the window size in world units. The terrain is resampled to the new resolution
+    // and cropped or padded around the world origin to the new world size. Every texture and
+    // bind group that depends on either is rebuilt, the bind group layout stays the same.
+    // History starts over.
+    pub fn reconfigure(&mut self, size: UVec2, tile_config: TileConfig, device: &wgpu::Device, queue: &wgpu::Queue) -> io::Result<()> {
+        let window_size = self.window_size();
+        let tile_size = window_size / tile_config.resident.as_vec2();
+        let file = self.world_file(device, queue)?.recenter(tile_size * tile_config.world.as_vec2(), Self::empty_distance(size));
+
+        self.textures = [
+            Self::create_texture(device, size),
+            Self::create_texture(device, size),
+        ];
+        self.texture_index = 0;
+        self.tiles = Tiles::new(tile_config, size, window_size);
+        self.indirection = TileIndirection::new(&self.tiles, tile_size.min_element(), device, queue);
+        self.sdf_bind_groups = [
+            Self::create_output_bind_group(device, &self.sdf_bind_group_layout, &self.textures[0].view, &self.sampler, &self.indirection, &self.palette_buffer),
+            Self::create_output_bind_group(device, &self.sdf_bind_group_layout, &self.textures[1].view, &self.sampler, &self.indirection, &self.palette_buffer),
+        ];
+        self.jump_flood = JumpFlood::new(size, window_size, TEXTURE_FORMAT, device);
+        self.redistance_bind_groups = [
+            self.jump_flood.create_field_bind_group(device, &self.textures[0].view),
+            self.jump_flood.create_field_bind_group(device, &self.textures[1].view),
+        ];
+        self.generator = Generator::new(size, window_size, &self.jump_flood.field_bind_group_layout, device);
+        self.mirror = Mirror::new(size, Self::empty_distance(size), self.tiles.num_slots());
+        self.redistance_pending = false;
+        self.edits_since_redistance = 0;
+        self.set_world_file(file, device, queue);
+        Ok(())
+    }
+
+    // Replaces the resident window with the distance field of an image mask, see
+    // `ImportOptions`. It is one history step, edits still queued are applied before it.
+    pub fn import_image(&mut self, img: &image::DynamicImage, options: &ImportOptions, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let size = self.size();
+        let window_field = import::mask_field(img, size, options);
+
+        // The window starts at the first resident tile, which is not necessarily texel 0
+        let first = self.tiles.first_texel();
+        let mut field = vec![0.; window_field.len()];
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let p = (first + UVec2::new(x, y).as_ivec2()).rem_euclid(size.as_ivec2()).as_uvec2();
+                field[(p.y * size.x + p.x) as usize] = window_field[(y * size.x + x) as usize];
+            }
+        }
+
+        let field_texture = texture::Texture::new_intermediate4(device, size, wgpu::TextureFormat::R32Float, wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST);
+        queue.write_texture(
+            field_texture.texture.as_image_copy(),
+            bytemuck::cast_slice(&field),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * size.x),
+                rows_per_image: Some(size.y),
+            },
+            field_texture.size,
+        );
+        let field_bind_group = self.jump_flood.create_field_bind_group(device, &field_texture.view);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("SDF import"),
+        });
+        self.record_edits(device, queue, &mut encoder);
+        self.snapshot_window(device, &mut encoder);
+        self.texture_index = (self.texture_index + 1) % 2;
+        self.jump_flood.run(&mut encoder, queue, &field_bind_group, &self.textures[self.texture_index].view, &self.tiles);
+        queue.submit(std::iter::once(encoder.finish()));
+        self.clear_outside_world(queue);
+        self.mirror.invalidate();
+    }
+
+    // Replaces the resident tiles with procedural terrain built from `layers`, starting from
+    // the current terrain when `keep_terrain` is set. Every tile gets the same terrain wherever
+    // the window is when it is generated. It is one history step, edits still queued are
+    // applied before it.
+    pub fn generate(&mut self, layers: &[Layer], keep_terrain: bool, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("SDF generate"),
+        });
+        self.record_edits(device, queue, &mut encoder);
+        self.snapshot_window(device, &mut encoder);
+        let terrain = keep_terrain.then_some(&self.redistance_bind_groups[self.texture_index]);
+        let field_bind_group = self.generator.run(&mut encoder, layers, terrain, &self.tiles, device, queue);
+        self.texture_index = (self.texture_index + 1) % 2;
+        self.jump_flood.run(&mut encoder, queue, field_bind_group, &self.textures[self.texture_index].view, &self.tiles);
+        queue.submit(std::iter::once(encoder.finish()));
+        self.clear_outside_world(queue);
+        self.mirror.invalidate();
+    }
+
+    // Forgets every history step, the world was replaced as a whole
+    fn reset_history(&mut self) {
+        self.edits.clear();
+        self.stroke = None;
+        self.recording = None;
+        self.history.clear();
+    }
+
+    pub fn output_bind_group(&self) -> &wgpu::BindGroup {
+        &self.sdf_bind_groups[self.texture_index]
+    }
+}
+
+// Texel regions of SDF textures of `size` texels over a window of `window_size` covering a
+// world space rectangle, as (origin, extent) pairs. Rectangles crossing the edges of the
+// toroidal textures are split at the seams, in a world of size `edges` that does not wrap they
+// are clipped to the edges of the world.
+fn texel_regions(min: Vec2, max: Vec2, size: UVec2, window_size: Vec2, edges: Option<Vec2>) -> Vec<(UVec2, UVec2)> {
+    let to_texel = |p: Vec2| (Vec2::new(p.x, -p.y) / window_size + 0.5) * size.as_vec2();
+    let mut a = to_texel(Vec2::new(min.x, max.y)).floor().as_ivec2() - 1;
+    let mut b = to_texel(Vec2::new(max.x, min.y)).ceil().as_ivec2() + 1;
+    if let Some(world_size) = edges {
+        let half = 0.5 * world_size;
+        a = a.max(to_texel(Vec2::new(-half.x, half.y)).round().as_ivec2());
+        b = b.min(to_texel(Vec2::new(half.x, -half.y)).round().as_ivec2());
+        if a.cmpge(b).any() {
+            return Vec::new();
+        }
+    }
+    let extent = (b - a).as_uvec2().min(size);
+    let start = a.rem_euclid(size.as_ivec2()).as_uvec2();
+    let spans = |start: u32, extent: u32, size: u32| {
+        if start + extent <= size {
+            vec![(start, extent)]
+        } else {
+            vec![(start, size - start), (0, start + extent - size)]
+        }
+    };
+    let mut regions = Vec::new();
+    for (y, h) in spans(start.y, extent.y, size.y) {
+        for (x, w) in spans(start.x, extent.x, size.x) {
+            regions.push((UVec2::new(x, y), UVec2::new(w, h)));
+        }
+    }
+    regions
+}
+
+// Size of one edit's uniforms in the dynamic offset buffer
+fn uniform_stride(alignment: u64) -> u64 {
+    (std::mem::size_of::<Uniforms>() as u64).div_ceil(alignment) * alignment
+}
+
+// Every edit gets its own slot so that queued edits don't overwrite each other's uniforms
+fn pack_uniforms(uniforms: impl Iterator<Item = Uniforms>, stride: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    for uniforms in uniforms {
+        data.extend_from_slice(bytemuck::bytes_of(&uniforms));
+        data.resize(data.len().div_ceil(stride) * stride, 0);
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_stride_is_aligned() {
+        for alignment in [16, 64, 256] {
+            let stride = uniform_stride(alignment);
+            assert_eq!(stride % alignment, 0);
+            assert!(stride >= std::mem::size_of::<Uniforms>() as u64);
+            assert!(stride < std::mem::size_of::<Uniforms>() as u64 + alignment);
+        }
+    }
+
+    #[test]
+    fn queued_edits_get_their_own_slots() {
+        let stride = uniform_stride(256) as usize;
+        let edits = (0..3).map(|i| Uniforms { world_pos: [i as f32, 0.], material: i, ..Uniforms::default() });
+        let data = pack_uniforms(edits, stride);
+        assert_eq!(data.len(), 3 * stride);
+        for i in 0..3 {
+            let uniforms: Uniforms = bytemuck::pod_read_unaligned(&data[i * stride..i * stride + std::mem::size_of::<Uniforms>()]);
+            assert_eq!(uniforms.world_pos, [i as f32, 0.]);
+            assert_eq!(uniforms.material, i as u32);
+        }
+    }
+
+    const SIZE: UVec2 = UVec2::splat(64);
+    const WINDOW_SIZE: Vec2 = Vec2::splat(128.);
+
+    fn area(regions: &[(UVec2, UVec2)]) -> u32 {
+        regions.iter().map(|(_, extent)| extent.x * extent.y).sum()
+    }
+
+    #[test]
+    fn regions_cover_the_rectangle_with_a_texel_of_margin() {
+        let regions = texel_regions(Vec2::new(-4., -4.), Vec2::new(4., 4.), SIZE, WINDOW_SIZE, None);
+        // 8 units are 4 texels, the texel center is at 32
+        assert_eq!(regions, vec![(UVec2::splat(29), UVec2::splat(6))]);
+    }
+
+    #[test]
+    fn edit_cost_follows_the_brush_size() {
+        let small = texel_regions(Vec2::splat(-2.), Vec2::splat(2.), SIZE, WINDOW_SIZE, None);
+        let large = texel_regions(Vec2::splat(-20.), Vec2::splat(20.), SIZE, WINDOW_SIZE, None);
+        assert!(area(&small) < area(&large));
+        assert!(area(&large) < SIZE.x * SIZE.y / 4);
+    }
+
+    #[test]
+    fn regions_are_split_at_the_seams() {
+        // Crosses the right and bottom edges of the window
+        let regions = texel_regions(Vec2::new(60., -68.), Vec2::new(68., -60.), SIZE, WINDOW_SIZE, None);
+        assert_eq!(regions.len(), 4);
+        assert_eq!(area(&regions), 36);
+        for (origin, extent) in &regions {
+            assert!((*origin + *extent).cmple(SIZE).all(), "{:?}", regions);
+        }
+        assert!(regions.contains(&(UVec2::ZERO, UVec2::splat(3))));
+    }
+
+    #[test]
+    fn regions_are_clipped_to_the_edges_of_the_world() {
+        let regions = texel_regions(Vec2::new(60., -4.), Vec2::new(68., 4.), SIZE, WINDOW_SIZE, Some(WINDOW_SIZE));
+        assert_eq!(regions, vec![(UVec2::new(61, 29), UVec2::new(3, 6))]);
+        assert!(texel_regions(Vec2::new(70., -4.), Vec2::new(80., 4.), SIZE, WINDOW_SIZE, Some(WINDOW_SIZE)).is_empty());
+    }
+
+    #[test]
+    fn huge_rectangles_cover_the_window_once() {
+        let regions = texel_regions(Vec2::splat(-500.), Vec2::splat(500.), SIZE, WINDOW_SIZE, None);
+        assert_eq!(area(&regions), SIZE.x * SIZE.y);
+    }
+}
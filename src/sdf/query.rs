@@ -0,0 +1,367 @@
+use std::sync::mpsc;
+
+use glam::*;
+
+use crate::renderer::texture;
+
+use super::tiles::Tiles;
+use super::TEXEL_BYTES;
+
+const MAX_RAY_STEPS: usize = 256;
+
+struct Readback {
+    // (origin, extent, buffer offset) of every copied region
+    regions: Vec<(UVec2, UVec2, u64)>,
+    resident: Vec<Option<IVec2>>,
+    receiver: Option<mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+fn padded_row_bytes(width: u32) -> u32 {
+    (TEXEL_BYTES * width).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
+}
+
+// CPU copy of the distances in the resident window. It is refreshed with asynchronous
+// readbacks, one in flight at a time, so it lags the GPU by a few frames but never stalls it.
+// Edits only read back the regions they touched. The residency at copy time is kept with the
+// data, tiles that were not resident then are answered from the tile store.
+pub struct Mirror {
+    size: UVec2,
+    // Created with the first readback
+    buffer: Option<wgpu::Buffer>,
+    distances: Vec<f32>,
+    resident: Vec<Option<IVec2>>,
+    stale: bool,
+    stale_regions: Vec<(UVec2, UVec2)>,
+    readback: Option<Readback>,
+}
+
+impl Mirror {
+    pub fn new(size: UVec2, empty_dist: f32, num_slots: usize) -> Self {
+        Self {
+            size,
+            buffer: None,
+            distances: vec![empty_dist; (size.x * size.y) as usize],
+            resident: vec![None; num_slots],
+            stale: true,
+            stale_regions: Vec::new(),
+            readback: None,
+        }
+    }
+
+    // The terrain changed on the GPU, a new copy is taken with the next `record`
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+        self.stale_regions.clear();
+    }
+
+    // Only a texel region changed, residency must not have changed since the last copy
+    pub fn invalidate_region(&mut self, origin: UVec2, extent: UVec2) {
+        if !self.stale {
+            self.stale_regions.push((origin, extent));
+        }
+    }
+
+    // Records a copy of the stale parts of `source` into `encoder`, unless a readback is in flight
+    pub fn record(&mut self, source: &texture::Texture, tiles: &Tiles, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        if self.readback.is_some() || (!self.stale && self.stale_regions.is_empty()) {
+            return;
+        }
+        let size = self.size;
+        let buffer = self.buffer.get_or_insert_with(|| device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("SDF mirror readback"),
+            size: (padded_row_bytes(size.x) * size.y) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+        let mut regions = Vec::new();
+        let mut offset = 0;
+        if !self.stale {
+            for (origin, extent) in self.stale_regions.drain(..) {
+                regions.push((origin, extent, offset));
+                offset += (padded_row_bytes(extent.x) * extent.y) as u64;
+            }
+        }
+        // Copy everything when that is smaller than the regions combined
+        if self.stale || offset > buffer.size() {
+            regions = vec![(UVec2::ZERO, self.size, 0)];
+        }
+        for (origin, extent, offset) in &regions {
+            encoder.copy_texture_to_buffer(
+                wgpu::ImageCopyTexture {
+                    texture: &source.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x: origin.x, y: origin.y, z: 0 },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::ImageCopyBuffer {
+                    buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset: *offset,
+                        bytes_per_row: Some(padded_row_bytes(extent.x)),
+                        rows_per_image: Some(extent.y),
+                    },
+                },
+                wgpu::Extent3d { width: extent.x, height: extent.y, depth_or_array_layers: 1 },
+            );
+        }
+        self.readback = Some(Readback {
+            regions,
+            resident: tiles.resident_slots().to_vec(),
+            receiver: None,
+        });
+        self.stale = false;
+        self.stale_regions.clear();
+    }
+
+    // Maps the recorded copy and takes it over once the GPU is done, call after the submit
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        let (Some(readback), Some(buffer)) = (&mut self.readback, &self.buffer) else {
+            return;
+        };
+        let receiver = readback.receiver.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel();
+            buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| { let _ = sender.send(result); });
+            receiver
+        });
+        device.poll(wgpu::Maintain::Poll);
+        let result = match receiver.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return,
+            Err(mpsc::TryRecvError::Disconnected) => Err(wgpu::BufferAsyncError),
+        };
+        let readback = self.readback.take().unwrap();
+        if result.is_err() {
+            self.invalidate();
+            return;
+        }
+        {
+            let mapped = buffer.slice(..).get_mapped_range();
+            for (origin, extent, offset) in &readback.regions {
+                let row_bytes = padded_row_bytes(extent.x) as usize;
+                let data = &mapped[*offset as usize..*offset as usize + row_bytes * extent.y as usize];
+                for (y, row) in data.chunks_exact(row_bytes).enumerate() {
+                    let start = ((origin.y + y as u32) * self.size.x + origin.x) as usize;
+                    let distances = &mut self.distances[start..start + extent.x as usize];
+                    for (texel, d) in row.chunks_exact(TEXEL_BYTES as usize).zip(distances) {
+                        *d = half::f16::from_bits(u16::from_le_bytes([texel[0], texel[1]])).to_f32();
+                    }
+                }
+            }
+        }
+        buffer.unmap();
+        self.resident = readback.resident;
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RayHit {
+    pub t: f32,
+    pub point: Vec2,
+    pub normal: Vec2,
+}
+
+// Read-only terrain queries in world space, honouring the world topology like the shaders do.
+// Distances are negative inside solid terrain. Smooth edits over-estimate distances away from
+// the surface until the terrain is redistanced.
+pub struct TerrainQuery<'a> {
+    mirror: &'a Mirror,
+    tiles: &'a Tiles,
+    window_size: Vec2,
+    empty_dist: f32,
+}
+
+impl<'a> TerrainQuery<'a> {
+    pub fn new(mirror: &'a Mirror, tiles: &'a Tiles, window_size: Vec2, empty_dist: f32) -> Self {
+        Self {
+            mirror,
+            tiles,
+            window_size,
+            empty_dist,
+        }
+    }
+
+    fn wrap(&self, p: Vec2) -> Vec2 {
+        self.tiles.config().topology.wrap(p, self.tiles.world_size())
+    }
+
+    fn texel_size(&self) -> Vec2 {
+        self.window_size / self.mirror.size.as_vec2()
+    }
+
+    // Distance at texel `g` in unwrapped texel coordinates of the whole world
+    fn texel(&self, g: IVec2) -> f32 {
+        let tile_texels = self.tiles.tile_texels().as_ivec2();
+        let t = g.div_euclid(tile_texels);
+        let Some(tile) = self.tiles.world_tile(t) else {
+            return self.empty_dist;
+        };
+        if self.mirror.resident[self.tiles.slot(t)] == Some(tile) {
+            let r = g.rem_euclid(self.mirror.size.as_ivec2()).as_uvec2();
+            return self.mirror.distances[(r.y * self.mirror.size.x + r.x) as usize];
+        }
+        match self.tiles.stored(tile) {
+            Some(data) => {
+                let local = (g - t * tile_texels).as_uvec2();
+                let i = ((local.y * tile_texels.x as u32 + local.x) * TEXEL_BYTES) as usize;
+                half::f16::from_bits(u16::from_le_bytes([data[i], data[i + 1]])).to_f32()
+            }
+            None => self.empty_dist,
+        }
+    }
+
+    // Bilinearly filtered distance, like the shaders see it, edges of the world included
+    pub fn distance(&self, p: Vec2) -> f32 {
+        let p = self.wrap(p);
+        let g = (Vec2::new(p.x, -p.y) / self.window_size + 0.5) * self.mirror.size.as_vec2() - 0.5;
+        let i = g.floor().as_ivec2();
+        let f = g - g.floor();
+        let top = self.texel(i) + f.x * (self.texel(i + IVec2::X) - self.texel(i));
+        let bottom = self.texel(i + IVec2::Y) + f.x * (self.texel(i + IVec2::ONE) - self.texel(i + IVec2::Y));
+        let d = top + f.y * (bottom - top);
+        self.tiles.config().topology.bound_distance(p, self.tiles.world_size(), d)
+    }
+
+    // Central difference gradient, not normalized
+    pub fn gradient(&self, p: Vec2) -> Vec2 {
+        let h = self.texel_size();
+        Vec2::new(
+            (self.distance(p + Vec2::new(h.x, 0.)) - self.distance(p - Vec2::new(h.x, 0.))) / (2. * h.x),
+            (self.distance(p + Vec2::new(0., h.y)) - self.distance(p - Vec2::new(0., h.y))) / (2. * h.y),
+        )
+    }
+
+    // Sphere traces from `origin` along `dir` up to `max_t`. Rays starting inside solid terrain
+    // hit at t = 0.
+    pub fn raycast(&self, origin: Vec2, dir: Vec2, max_t: f32) -> Option<RayHit> {
+        let dir = dir.normalize_or_zero();
+        if dir == Vec2::ZERO {
+            return None;
+        }
+        let epsilon = 0.5 * self.texel_size().min_element();
+        let mut t = 0.;
+        for _ in 0..MAX_RAY_STEPS {
+            let p = origin + t * dir;
+            let d = self.distance(p);
+            if d < epsilon {
+                return Some(RayHit {
+                    t,
+                    point: self.wrap(p),
+                    normal: self.gradient(p).normalize_or_zero(),
+                });
+            }
+            t += d.max(epsilon);
+            if t > max_t {
+                break;
+            }
+        }
+        None
+    }
+
+    // Whether a circle touches solid terrain
+    pub fn circle_overlaps(&self, p: Vec2, radius: f32) -> bool {
+        self.distance(p) < radius
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf::tiles::{TileConfig, Topology};
+
+    const SIZE: UVec2 = UVec2::splat(64);
+    const EMPTY: f32 = 100.;
+
+    // One world unit per texel, the window is centered on the origin
+    fn tiles(world: u32, topology: Topology) -> Tiles {
+        let config = TileConfig { resident: UVec2::splat(2), world: UVec2::splat(world), topology };
+        Tiles::new(config, SIZE, SIZE.as_vec2())
+    }
+
+    // Mirror of the resident window with distances `f` at the texel centers
+    fn mirror(tiles: &Tiles, f: impl Fn(Vec2) -> f32) -> Mirror {
+        let mut mirror = Mirror::new(SIZE, EMPTY, tiles.num_slots());
+        for y in 0..SIZE.y {
+            for x in 0..SIZE.x {
+                let p = Vec2::new(x as f32 + 0.5 - 32., 32. - (y as f32 + 0.5));
+                mirror.distances[(y * SIZE.x + x) as usize] = f(p);
+            }
+        }
+        mirror.resident = tiles.resident_slots().to_vec();
+        mirror
+    }
+
+    // Solid to the right of x = 20
+    fn wall(p: Vec2) -> f32 {
+        20. - p.x
+    }
+
+    fn query<'a>(mirror: &'a Mirror, tiles: &'a Tiles) -> TerrainQuery<'a> {
+        TerrainQuery::new(mirror, tiles, SIZE.as_vec2(), EMPTY)
+    }
+
+    #[test]
+    fn distances_are_interpolated_between_texels() {
+        let tiles = tiles(2, Topology::Torus);
+        let mirror = mirror(&tiles, |p| p.x + 2. * p.y);
+        let query = query(&mirror, &tiles);
+        for p in [Vec2::ZERO, Vec2::new(3.25, -7.5), Vec2::new(-10.1, 12.7)] {
+            assert!((query.distance(p) - (p.x + 2. * p.y)).abs() < 1e-3, "{}", p);
+        }
+        let gradient = query.gradient(Vec2::new(1.3, 2.1));
+        assert!((gradient - Vec2::new(1., 2.)).length() < 1e-3, "{}", gradient);
+    }
+
+    #[test]
+    fn tiles_outside_the_window_come_from_the_store() {
+        let tiles = {
+            let mut tiles = tiles(4, Topology::Torus);
+            let texel = [&half::f16::from_f32(5.).to_le_bytes()[..], &[0; 6]].concat();
+            tiles.set_stored(IVec2::new(2, 0), texel.repeat(32 * 32));
+            tiles
+        };
+        let mirror = mirror(&tiles, |_| 1.);
+        let query = query(&mirror, &tiles);
+        assert_eq!(query.distance(Vec2::new(10., 10.)), 1.);
+        assert_eq!(query.distance(Vec2::new(48., 16.)), 5.);
+        assert_eq!(query.distance(Vec2::new(-48., 16.)), EMPTY);
+        // The world wraps around after 128 units
+        assert_eq!(query.distance(Vec2::new(48. - 128., 16.)), 5.);
+    }
+
+    #[test]
+    fn world_edges_bound_the_distance() {
+        for (topology, expected) in [(Topology::Torus, 1.), (Topology::Clamped, 8.), (Topology::Bounded, -8.)] {
+            let tiles = tiles(2, topology);
+            let mirror = mirror(&tiles, |_| 1.);
+            let query = query(&mirror, &tiles);
+            assert_eq!(query.distance(Vec2::new(40., 0.)), expected, "{:?}", topology);
+            assert_eq!(query.circle_overlaps(Vec2::new(40., 0.), 2.), expected < 2., "{:?}", topology);
+        }
+    }
+
+    #[test]
+    fn rays_stop_at_the_surface() {
+        let tiles = tiles(2, Topology::Clamped);
+        let mirror = mirror(&tiles, wall);
+        let query = query(&mirror, &tiles);
+        let hit = query.raycast(Vec2::new(0., 5.), Vec2::new(2., 0.), 30.).unwrap();
+        assert!(hit.t > 19. && hit.t <= 20., "{:?}", hit);
+        assert!((hit.point - Vec2::new(hit.t, 5.)).length() < 1e-4, "{:?}", hit);
+        assert!((hit.normal - Vec2::new(-1., 0.)).length() < 1e-3, "{:?}", hit);
+
+        assert_eq!(query.raycast(Vec2::new(0., 5.), Vec2::new(1., 0.), 10.), None);
+        assert_eq!(query.raycast(Vec2::new(0., 5.), Vec2::new(-1., 0.), 30.), None);
+        assert_eq!(query.raycast(Vec2::new(0., 5.), Vec2::ZERO, 30.), None);
+        assert_eq!(query.raycast(Vec2::new(25., 5.), Vec2::new(-1., 0.), 30.).map(|hit| hit.t), Some(0.));
+    }
+
+    #[test]
+    fn circles_overlap_within_their_radius() {
+        let tiles = tiles(2, Topology::Torus);
+        let mirror = mirror(&tiles, wall);
+        let query = query(&mirror, &tiles);
+        assert!(!query.circle_overlaps(Vec2::new(10., 0.), 9.));
+        assert!(query.circle_overlaps(Vec2::new(10., 0.), 11.));
+        assert!(query.circle_overlaps(Vec2::new(22., 0.), 0.5));
+    }
+}
@@ -0,0 +1,228 @@
+struct Brush {
+    kind: u32,
+    num_points: u32,
+    rotation: f32,
+    smoothness: f32,
+    params: vec4<f32>,
+    points: array<vec4<f32>, 8>,
+}
+
+struct Uniforms {
+    world_pos: vec2<f32>,
+    world_size: vec2<f32>,
+    inv_world_size: vec2<f32>,
+    stroke_delta: vec2<f32>,
+    brush: Brush,
+    stroke_steps: u32,
+    material: u32,
+    // Change of the height channel at the center of a raise or lower stroke
+    height: f32,
+}
+
+@group(0) @binding(0)
+var<uniform> uniforms: Uniforms;
+
+// Vertex shader
+
+struct VertexOutput {
+    @location(0) world_pos: vec2<f32>,
+    @builtin(position) position: vec4<f32>,
+}
+
+@vertex
+fn main_vert(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
+    var vertices: array<vec2<f32>, 3> = array<vec2<f32>, 3>(
+        vec2<f32>(-1., -3.0),
+        vec2<f32>(3.0, 1.),
+        vec2<f32>(-1., 1.),
+    );
+    var out: VertexOutput;
+    out.position = vec4<f32>(vertices[in_vertex_index], 0.0, 1.0);
+    out.world_pos = 0.5 * out.position.xy * uniforms.world_size;
+    return out;
+}
+
+// Fragment shader
+
+fn packSdf(v: f32) -> f32 {
+    return v;
+}
+
+// Edits only touch the resident window, they never reach the edges of a world that doesn't wrap
+fn boundDist(world_pos: vec2<f32>, dist: f32) -> f32 {
+    return dist;
+}
+
+// Distance, material and height of the texel being written, edits render at texture resolution
+// and only the texels under the brush are valid in the source texture
+fn texel(position: vec4<f32>) -> vec4<f32> {
+    return textureLoad(t_sdf, vec2<i32>(floor(position.xy)), 0);
+}
+
+fn smoothUnion(d1: f32, d2: f32) -> f32 {
+    let k = uniforms.brush.smoothness;
+    let h = max(k-abs(d1-d2),0.0);
+    return min(d1, d2) - h*h*0.25/k;
+}
+
+fn smoothSubtract(d1: f32, d2: f32) -> f32 {
+    return -smoothUnion(-d1, d2);
+}
+
+// Brushes
+
+const BRUSH_CIRCLE: u32 = 0u;
+const BRUSH_BOX: u32 = 1u;
+const BRUSH_CAPSULE: u32 = 2u;
+const BRUSH_NGON: u32 = 3u;
+const BRUSH_POLYGON: u32 = 4u;
+const BRUSH_ANNULUS: u32 = 5u;
+
+const PI: f32 = 3.14159265359;
+
+fn rotation(angle: f32) -> mat2x2<f32> {
+    let cs = cos(angle);
+    let sn = sin(angle);
+    return mat2x2<f32>(vec2<f32>(cs, -sn), vec2<f32>(sn, cs));
+}
+
+fn sdBox(p: vec2<f32>, b: vec2<f32>) -> f32 {
+    let d = abs(p) - b;
+    return length(max(d, vec2<f32>(0.))) + min(max(d.x, d.y), 0.);
+}
+
+fn sdCapsule(p: vec2<f32>, h: f32, r: f32) -> f32 {
+    let q = vec2<f32>(p.x - clamp(p.x, -h, h), p.y);
+    return length(q) - r;
+}
+
+// https://iquilezles.org/articles/distfunctions2d/
+fn sdRegularPolygon(p: vec2<f32>, r: f32, n: f32) -> f32 {
+    let an = PI / n;
+    let acs = vec2<f32>(cos(an), sin(an));
+    let a = atan2(p.x, p.y);
+    let bn = a - 2. * an * floor(a / (2. * an)) - an;
+    var q = length(p) * vec2<f32>(cos(bn), abs(sin(bn)));
+    q = q - r * acs;
+    q.y = q.y + clamp(-q.y, 0.0, r * acs.y);
+    return length(q) * sign(q.x);
+}
+
+fn polygonPoint(i: u32) -> vec2<f32> {
+    let v = uniforms.brush.points[i / 2u];
+    return select(v.xy, v.zw, i % 2u == 1u);
+}
+
+// https://iquilezles.org/articles/distfunctions2d/
+fn sdPolygon(p: vec2<f32>) -> f32 {
+    let n = uniforms.brush.num_points;
+    if n < 3u {
+        return 1e10;
+    }
+    let v0 = polygonPoint(0u);
+    var d = dot(p - v0, p - v0);
+    var s = 1.0;
+    var j = n - 1u;
+    for (var i = 0u; i < n; i = i + 1u) {
+        let vi = polygonPoint(i);
+        let vj = polygonPoint(j);
+        let e = vj - vi;
+        let w = p - vi;
+        let b = w - e * clamp(dot(w, e) / dot(e, e), 0.0, 1.0);
+        d = min(d, dot(b, b));
+        let c = vec3<bool>(p.y >= vi.y, p.y < vj.y, e.x * w.y > e.y * w.x);
+        if all(c) || !any(c) {
+            s = -s;
+        }
+        j = i;
+    }
+    return s * sqrt(d);
+}
+
+fn sdAnnulus(p: vec2<f32>, r: f32, h: f32) -> f32 {
+    return abs(length(p) - r) - h;
+}
+
+fn brushDist(p: vec2<f32>) -> f32 {
+    let brush = uniforms.brush;
+    let q = rotation(brush.rotation) * p;
+    switch brush.kind {
+        case BRUSH_BOX: {
+            return sdBox(q, brush.params.xy);
+        }
+        case BRUSH_CAPSULE: {
+            return sdCapsule(q, brush.params.x, brush.params.y);
+        }
+        case BRUSH_NGON: {
+            return sdRegularPolygon(q, brush.params.x, brush.params.y);
+        }
+        case BRUSH_POLYGON: {
+            return sdPolygon(q);
+        }
+        case BRUSH_ANNULUS: {
+            return sdAnnulus(q, brush.params.x, brush.params.y);
+        }
+        default: {
+            return length(q) - brush.params.x;
+        }
+    }
+}
+
+// Brush swept from `world_pos - stroke_delta` to `world_pos`
+fn strokeDist(q: vec2<f32>) -> f32 {
+    let delta = uniforms.stroke_delta;
+    if uniforms.brush.kind == BRUSH_CIRCLE {
+        let pa = q + delta;
+        let h = clamp(dot(pa, delta) / max(dot(delta, delta), 1e-8), 0., 1.);
+        return length(pa - h * delta) - uniforms.brush.params.x;
+    }
+    var d = brushDist(q);
+    let n = uniforms.stroke_steps;
+    for (var i = 1u; i <= n; i = i + 1u) {
+        d = min(d, brushDist(q + delta * (f32(i) / f32(n))));
+    }
+    return d;
+}
+
+// Material of an edited texel. The material shows where the distance is positive, so the brush
+// paints the texels it covers there and the ones the edit newly exposes, e.g. in the smoothing
+// band. Texels that end up inside keep theirs.
+fn editedMaterial(current: vec4<f32>, dist: f32, stroke: f32) -> f32 {
+    let painted = dist >= 0. && (stroke < 0. || unpackSdf(current.x) < 0.);
+    return select(current.y, f32(uniforms.material), painted);
+}
+
+@fragment
+fn main_frag(in: VertexOutput) -> @location(0) vec4<f32> {
+    let p = in.world_pos - uniforms.world_pos;
+    let q = p - uniforms.world_size * round(p * uniforms.inv_world_size);
+    let current = texel(in.position);
+    let stroke = strokeDist(q);
+    let dist = smoothUnion(unpackSdf(current.x), stroke);
+    let edited = vec4<f32>(packSdf(dist), editedMaterial(current, dist, stroke), current.zw);
+    return select(current, edited, terrainResident(uniforms.world_pos + q));
+}
+
+@fragment
+fn main_frag_subtract(in: VertexOutput) -> @location(0) vec4<f32> {
+    let p = in.world_pos - uniforms.world_pos;
+    let q = p - uniforms.world_size * round(p * uniforms.inv_world_size);
+    let current = texel(in.position);
+    let stroke = strokeDist(q);
+    let dist = smoothSubtract(unpackSdf(current.x), stroke);
+    let edited = vec4<f32>(packSdf(dist), editedMaterial(current, dist, stroke), current.zw);
+    return select(current, edited, terrainResident(uniforms.world_pos + q));
+}
+
+// Raises or lowers the height channel under the stroke, fading out over the smoothing band.
+// The distance and material stay as they are.
+@fragment
+fn main_frag_height(in: VertexOutput) -> @location(0) vec4<f32> {
+    let p = in.world_pos - uniforms.world_pos;
+    let q = p - uniforms.world_size * round(p * uniforms.inv_world_size);
+    let current = texel(in.position);
+    let weight = saturate(-strokeDist(q) / uniforms.brush.smoothness);
+    let height = clamp(current.z + weight * uniforms.height, -1., 0.);
+    let edited = vec4<f32>(current.xy, height, current.w);
+    return select(current, edited, terrainResident(uniforms.world_pos + q));
+}
@@ -0,0 +1,581 @@
+use std::collections::HashMap;
+use std::sync::mpsc;
+
+use glam::*;
+
+use crate::renderer::texture;
+
+use super::TEXEL_BYTES;
+
+// What lies beyond the edges of the world
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Topology {
+    // The world wraps around, leaving one edge enters at the opposite one
+    #[default]
+    Torus,
+    // Empty space beyond the edges
+    Clamped,
+    // Solid terrain beyond the edges
+    Bounded,
+}
+
+impl Topology {
+    pub const ALL: [Topology; 3] = [Topology::Torus, Topology::Clamped, Topology::Bounded];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Topology::Torus => "torus",
+            Topology::Clamped => "clamped",
+            Topology::Bounded => "bounded",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|t| t.name() == name)
+    }
+
+    pub fn wraps(&self) -> bool {
+        *self == Topology::Torus
+    }
+
+    // Wraps positions and offsets into the world centered on the origin, only on a torus
+    pub fn wrap(&self, p: Vec2, world_size: Vec2) -> Vec2 {
+        if !self.wraps() {
+            return p;
+        }
+        let s = (p / world_size).abs().ceil() + 0.5;
+        (p + s * world_size) % world_size - 0.5 * world_size
+    }
+
+    // Keeps a position inside the world, wrapping on a torus and clamping otherwise
+    pub fn confine(&self, p: Vec2, world_size: Vec2) -> Vec2 {
+        if self.wraps() {
+            self.wrap(p, world_size)
+        } else {
+            p.clamp(-0.5 * world_size, 0.5 * world_size)
+        }
+    }
+
+    // Terrain distance `d` at `p` with the world edges applied, like the shaders do it
+    pub fn bound_distance(&self, p: Vec2, world_size: Vec2, d: f32) -> f32 {
+        let q = p.abs() - 0.5 * world_size;
+        let edge = q.max(Vec2::ZERO).length() + q.max_element().min(0.);
+        match self {
+            Topology::Torus => d,
+            Topology::Clamped if edge > 0. => edge,
+            Topology::Clamped => d,
+            Topology::Bounded => d.min(-edge),
+        }
+    }
+
+    // Value the shaders see
+    pub fn index(&self) -> u32 {
+        match self {
+            Topology::Torus => 0,
+            Topology::Clamped => 1,
+            Topology::Bounded => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TileConfig {
+    // Resident tiles along each axis, the SDF textures are split evenly between them
+    pub resident: UVec2,
+    // Tiles along each axis of the whole world, a multiple of `resident`
+    pub world: UVec2,
+    pub topology: Topology,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct TerrainUniforms {
+    pub inv_window_size: [f32; 2],
+    pub inv_tile_size: [f32; 2],
+    pub tiles: [i32; 2],
+    pub world_tiles: [i32; 2],
+    pub empty_dist: f32,
+    pub topology: u32,
+    pub dummy: [f32; 2],
+}
+
+const INDIRECTION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Sint;
+const NOT_RESIDENT: [i32; 2] = [-1, -1];
+
+// Contents of a tile outside the window, as raw little-endian half float texels row by row.
+// Tiles copied out of the SDF textures stay on the GPU until their readback lands, nothing
+// waits for it.
+pub enum StoredTile {
+    Bytes(Vec<u8>),
+    Texture(Box<PendingTile>),
+}
+
+pub struct PendingTile {
+    pub texture: texture::Texture,
+    buffer: wgpu::Buffer,
+    receiver: Option<mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+fn padded_row_bytes(width: u32) -> u32 {
+    (TEXEL_BYTES * width).div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT) * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT
+}
+
+// Drops the row padding of a texture readback
+fn unpad_rows(padded: &[u8], width: u32) -> Vec<u8> {
+    let row_bytes = (TEXEL_BYTES * width) as usize;
+    padded
+        .chunks_exact(padded_row_bytes(width) as usize)
+        .flat_map(|row| row[..row_bytes].iter().copied())
+        .collect()
+}
+
+// Residency bookkeeping for the tiled terrain. The SDF textures are used as a toroidal ring:
+// world tile `t` can only live in slot `t mod resident`, so neighbouring tiles stay neighbours
+// in the texture and the repeating sampler filters across tile borders for free. The
+// indirection table, see `TileIndirection`, records which world tile each slot currently
+// holds, so shaders can tell resident texels from stale ones. Tiles that leave the window are
+// kept in the store.
+// The ring wraps whatever the world topology is, in a world with edges the slots beyond them
+// hold no tile and stay empty.
+pub struct Tiles {
+    config: TileConfig,
+    tile_texels: UVec2,
+    tile_size: Vec2,
+    first: IVec2,
+    resident: Vec<Option<IVec2>>,
+    dirty: Vec<bool>,
+    store: HashMap<IVec2, StoredTile>,
+}
+
+impl Tiles {
+    pub fn new(config: TileConfig, size: UVec2, window_size: Vec2) -> Self {
+        assert!(size % config.resident == UVec2::ZERO, "SDF size must be a multiple of the resident tiles");
+        assert!(config.world % config.resident == UVec2::ZERO, "World tiles must be a multiple of the resident tiles");
+        let tile_texels = size / config.resident;
+        let tile_size = window_size / config.resident.as_vec2();
+
+        let num_slots = (config.resident.x * config.resident.y) as usize;
+        let mut tiles = Self {
+            config,
+            tile_texels,
+            tile_size,
+            first: IVec2::ZERO,
+            resident: vec![None; num_slots],
+            dirty: vec![false; num_slots],
+            store: HashMap::new(),
+        };
+        let (first, changes) = tiles.residency_changes(Vec2::ZERO);
+        tiles.first = first;
+        for (slot, tile) in changes {
+            tiles.resident[slot] = tile;
+        }
+        tiles
+    }
+
+    pub fn uniforms(&self, empty_dist: f32) -> TerrainUniforms {
+        TerrainUniforms {
+            inv_window_size: (1. / (self.tile_size * self.config.resident.as_vec2())).into(),
+            inv_tile_size: (1. / self.tile_size).into(),
+            tiles: self.config.resident.as_ivec2().into(),
+            world_tiles: self.config.world.as_ivec2().into(),
+            empty_dist,
+            topology: self.config.topology.index(),
+            dummy: [0.; 2],
+        }
+    }
+
+    pub fn config(&self) -> TileConfig {
+        self.config
+    }
+
+    pub fn tile_texels(&self) -> UVec2 {
+        self.tile_texels
+    }
+
+    pub fn world_size(&self) -> Vec2 {
+        self.tile_size * self.config.world.as_vec2()
+    }
+
+    pub fn world_texels(&self) -> UVec2 {
+        self.tile_texels * self.config.world
+    }
+
+    // Unwrapped coordinates of the resident tile in the top left corner of the window
+    pub fn first(&self) -> IVec2 {
+        self.first
+    }
+
+    // Texel of the SDF textures holding the top left corner of the window
+    pub fn first_texel(&self) -> IVec2 {
+        (self.first * self.tile_texels.as_ivec2()).rem_euclid((self.tile_texels * self.config.resident).as_ivec2())
+    }
+
+    // Whether the window is the whole world and wraps around, so that its opposite edges are
+    // neighbours
+    pub fn window_wraps(&self) -> bool {
+        self.config.topology.wraps() && self.config.world == self.config.resident
+    }
+
+    pub fn num_stored(&self) -> usize {
+        self.store.len()
+    }
+
+    // Largest view that stays inside the resident tiles wherever the camera is in its tile
+    pub fn view_limit(&self) -> Vec2 {
+        (self.config.resident.as_vec2() - 2.).max(Vec2::ONE) * self.tile_size
+    }
+
+    // Unwrapped tile under `p`, tile rows grow downwards like texture rows
+    pub fn tile_at(&self, p: Vec2) -> IVec2 {
+        (Vec2::new(p.x, -p.y) / self.tile_size + 0.5 * self.config.resident.as_vec2()).floor().as_ivec2()
+    }
+
+    // World position of the top left corner of the resident window
+    pub fn window_origin(&self) -> Vec2 {
+        let corner = (self.first.as_vec2() - 0.5 * self.config.resident.as_vec2()) * self.tile_size;
+        Vec2::new(corner.x, -corner.y)
+    }
+
+    pub fn wrap_tile(&self, t: IVec2) -> IVec2 {
+        t.rem_euclid(self.config.world.as_ivec2())
+    }
+
+    // Wrapped tile at unwrapped tile `t`, none beyond the edges of a world that does not wrap
+    pub fn world_tile(&self, t: IVec2) -> Option<IVec2> {
+        let f = t + (self.config.world.as_ivec2() - self.config.resident.as_ivec2()) / 2;
+        let inside = f.cmpge(IVec2::ZERO).all() && f.cmplt(self.config.world.as_ivec2()).all();
+        (self.config.topology.wraps() || inside).then(|| self.wrap_tile(t))
+    }
+
+    // Slot that unwrapped tile `t` lives in when resident
+    pub fn slot(&self, t: IVec2) -> usize {
+        let s = t.rem_euclid(self.config.resident.as_ivec2());
+        (s.y as u32 * self.config.resident.x + s.x as u32) as usize
+    }
+
+    // Texel origin of `slot` in the SDF textures
+    pub fn slot_origin(&self, slot: usize) -> UVec2 {
+        UVec2::new(slot as u32 % self.config.resident.x, slot as u32 / self.config.resident.x) * self.tile_texels
+    }
+
+    // Tiles around `center` that are not resident yet, as (slot, wrapped tile) pairs. Slots
+    // beyond the edges of the world get no tile.
+    pub fn residency_changes(&self, center: Vec2) -> (IVec2, Vec<(usize, Option<IVec2>)>) {
+        let resident = self.config.resident.as_ivec2();
+        let first = self.tile_at(center) - resident / 2;
+        let mut changes = Vec::new();
+        for y in 0..resident.y {
+            for x in 0..resident.x {
+                let t = first + IVec2::new(x, y);
+                let slot = self.slot(t);
+                let tile = self.world_tile(t);
+                if self.resident[slot] != tile {
+                    changes.push((slot, tile));
+                }
+            }
+        }
+        (first, changes)
+    }
+
+    pub fn set_first(&mut self, first: IVec2) {
+        self.first = first;
+    }
+
+    pub fn resident(&self, slot: usize) -> Option<IVec2> {
+        self.resident[slot]
+    }
+
+    pub fn resident_slots(&self) -> &[Option<IVec2>] {
+        &self.resident
+    }
+
+    // Slot holding world tile `tile`, if it is resident
+    pub fn resident_slot(&self, tile: IVec2) -> Option<usize> {
+        self.resident.iter().position(|t| *t == Some(tile))
+    }
+
+    pub fn is_dirty(&self, slot: usize) -> bool {
+        self.dirty[slot]
+    }
+
+    pub fn num_slots(&self) -> usize {
+        self.resident.len()
+    }
+
+    // Slots overlapping the world space rectangle, each once
+    pub fn slots_in(&self, min: Vec2, max: Vec2) -> Vec<usize> {
+        let a = self.tile_at(Vec2::new(min.x, max.y));
+        let b = self.tile_at(Vec2::new(max.x, min.y));
+        let resident = self.config.resident.as_ivec2();
+        let b = b.min(a + resident - 1);
+        let mut slots = Vec::new();
+        for y in a.y..=b.y {
+            for x in a.x..=b.x {
+                slots.push(self.slot(IVec2::new(x, y)));
+            }
+        }
+        slots
+    }
+
+    pub fn mark_slot_dirty(&mut self, slot: usize) {
+        self.dirty[slot] = true;
+    }
+
+    // Keeps `texture`, a copy of the contents of `slot`, in the store and marks the slot clean.
+    // The readback is recorded into `encoder` and mapped by `poll_store` once submitted.
+    pub fn store_slot(&mut self, slot: usize, texture: texture::Texture, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        if let Some(tile) = self.resident[slot] {
+            self.store_texture(tile, texture, device, encoder);
+        }
+        self.dirty[slot] = false;
+    }
+
+    // Keeps `texture` as the contents of `tile`, see `store_slot`
+    pub fn store_texture(&mut self, tile: IVec2, texture: texture::Texture, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tile readback"),
+            size: (padded_row_bytes(self.tile_texels.x) * self.tile_texels.y) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            texture.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes(self.tile_texels.x)),
+                    rows_per_image: Some(self.tile_texels.y),
+                },
+            },
+            texture.size,
+        );
+        self.store.insert(tile, StoredTile::Texture(Box::new(PendingTile { texture, buffer, receiver: None })));
+    }
+
+    // Takes over the stored tiles whose readback landed, call after submitting the encoders
+    // passed to `store_slot`. With `wait` it blocks until all of them did.
+    pub fn poll_store(&mut self, device: &wgpu::Device, wait: bool) {
+        let mut in_flight = false;
+        for stored in self.store.values_mut() {
+            if let StoredTile::Texture(pending) = stored {
+                let buffer = &pending.buffer;
+                pending.receiver.get_or_insert_with(|| {
+                    let (sender, receiver) = mpsc::channel();
+                    buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| { let _ = sender.send(result); });
+                    receiver
+                });
+                in_flight = true;
+            }
+        }
+        if !in_flight {
+            return;
+        }
+        device.poll(if wait { wgpu::Maintain::Wait } else { wgpu::Maintain::Poll });
+        let width = self.tile_texels.x;
+        for stored in self.store.values_mut() {
+            let StoredTile::Texture(pending) = stored else {
+                continue;
+            };
+            let Some(r) = &pending.receiver else {
+                continue;
+            };
+            let result = if wait {
+                r.recv().unwrap_or(Err(wgpu::BufferAsyncError))
+            } else {
+                match r.try_recv() {
+                    Ok(result) => result,
+                    Err(mpsc::TryRecvError::Empty) => continue,
+                    Err(mpsc::TryRecvError::Disconnected) => Err(wgpu::BufferAsyncError),
+                }
+            };
+            if result.is_err() {
+                // The texture still holds the tile, it is mapped again with the next poll
+                pending.receiver = None;
+                continue;
+            }
+            let bytes = unpad_rows(&pending.buffer.slice(..).get_mapped_range(), width);
+            pending.buffer.unmap();
+            *stored = StoredTile::Bytes(bytes);
+        }
+    }
+
+    // Bytes of a stored tile, none while its readback is in flight
+    pub fn stored(&self, tile: IVec2) -> Option<&[u8]> {
+        match self.store.get(&tile) {
+            Some(StoredTile::Bytes(bytes)) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn stored_tile(&self, tile: IVec2) -> Option<&StoredTile> {
+        self.store.get(&tile)
+    }
+
+    pub fn take_stored(&mut self, tile: IVec2) -> Option<StoredTile> {
+        self.store.remove(&tile)
+    }
+
+    pub fn set_stored(&mut self, tile: IVec2, data: Vec<u8>) {
+        self.store.insert(tile, StoredTile::Bytes(data));
+    }
+
+    pub fn clear_store(&mut self) {
+        self.store.clear();
+    }
+
+    pub fn make_resident(&mut self, slot: usize, tile: Option<IVec2>) {
+        self.resident[slot] = tile;
+        self.dirty[slot] = false;
+    }
+
+    // Tile position in a whole world file, which is centered on the world origin
+    pub fn file_tile(&self, tile: IVec2) -> IVec2 {
+        let offset = (self.config.world.as_ivec2() - self.config.resident.as_ivec2()) / 2;
+        (tile + offset).rem_euclid(self.config.world.as_ivec2())
+    }
+
+}
+
+// GPU side of the tiles: the indirection table with the world tile each slot holds, and the
+// terrain uniforms the shaders read it with
+pub struct TileIndirection {
+    texture: texture::Texture,
+    pub uniform_buffer: wgpu::Buffer,
+}
+
+impl TileIndirection {
+    pub fn new(tiles: &Tiles, empty_dist: f32, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let texture = texture::Texture::new_intermediate4(device, tiles.config.resident, INDIRECTION_FORMAT, wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST);
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Terrain uniforms"),
+            size: std::mem::size_of::<TerrainUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&uniform_buffer, 0, bytemuck::bytes_of(&tiles.uniforms(empty_dist)));
+        let indirection = Self { texture, uniform_buffer };
+        indirection.write(tiles, queue);
+        indirection
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.texture.view
+    }
+
+    pub fn write(&self, tiles: &Tiles, queue: &wgpu::Queue) {
+        let data: Vec<[i32; 2]> = tiles.resident.iter().map(|t| t.map_or(NOT_RESIDENT, |t| t.into())).collect();
+        queue.write_texture(
+            self.texture.texture.as_image_copy(),
+            bytemuck::cast_slice(&data),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(8 * tiles.config.resident.x),
+                rows_per_image: Some(tiles.config.resident.y),
+            },
+            self.texture.size,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readback_rows_are_padded_to_the_copy_alignment() {
+        assert_eq!(padded_row_bytes(32), 256);
+        assert_eq!(padded_row_bytes(33), 512);
+        assert_eq!(padded_row_bytes(1), 256);
+    }
+
+    #[test]
+    fn unpadding_keeps_the_texels_of_every_row() {
+        let width = 3;
+        let row_bytes = (TEXEL_BYTES * width) as usize;
+        let mut padded = Vec::new();
+        for row in 0..2u8 {
+            let mut bytes = vec![row + 1; row_bytes];
+            bytes.resize(padded_row_bytes(width) as usize, 0xff);
+            padded.extend(bytes);
+        }
+        let rows = unpad_rows(&padded, width);
+        assert_eq!(rows.len(), 2 * row_bytes);
+        assert!(rows[..row_bytes].iter().all(|b| *b == 1));
+        assert!(rows[row_bytes..].iter().all(|b| *b == 2));
+    }
+
+    const WORLD: Vec2 = Vec2::new(100., 50.);
+
+    #[test]
+    fn topologies_are_named() {
+        for topology in Topology::ALL {
+            assert_eq!(Topology::from_name(topology.name()), Some(topology));
+        }
+        assert_eq!(Topology::from_name("sphere"), None);
+    }
+
+    #[test]
+    fn indices_match_the_shader_constants() {
+        let shader = include_str!("../renderer/topology.wgsl");
+        for (topology, name) in [(Topology::Torus, "TORUS"), (Topology::Clamped, "CLAMPED"), (Topology::Bounded, "BOUNDED")] {
+            assert!(shader.contains(&format!("const TOPOLOGY_{}: u32 = {}u;", name, topology.index())), "{:?}", topology);
+        }
+    }
+
+    // Parses and validates a shader the way creating its module would
+    fn validate(source: &str) {
+        let module = naga::front::wgsl::parse_str(source).unwrap_or_else(|e| panic!("{}", e.emit_to_string(source)));
+        naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::empty())
+            .validate(&module)
+            .unwrap_or_else(|e| panic!("{}", e.emit_to_string(source)));
+    }
+
+    #[test]
+    fn shaders_sharing_the_tiles_validate() {
+        for shader in [crate::sdf::SHADER, crate::renderer::SHADER, crate::renderer::geometry::TERRAIN_SHADER, crate::renderer::geometry::SHAPE_SHADER, crate::renderer::light_map::SHADER] {
+            validate(shader);
+        }
+    }
+
+    #[test]
+    fn only_a_torus_wraps() {
+        let p = Vec2::new(60., -30.);
+        assert_eq!(Topology::Torus.wrap(p, WORLD), Vec2::new(-40., 20.));
+        assert_eq!(Topology::Torus.wrap(Vec2::new(-250., 10.), WORLD), Vec2::new(-50., 10.));
+        assert_eq!(Topology::Clamped.wrap(p, WORLD), p);
+        assert_eq!(Topology::Bounded.wrap(p, WORLD), p);
+    }
+
+    #[test]
+    fn positions_are_confined_to_the_world() {
+        let p = Vec2::new(60., -30.);
+        assert_eq!(Topology::Torus.confine(p, WORLD), Vec2::new(-40., 20.));
+        assert_eq!(Topology::Clamped.confine(p, WORLD), Vec2::new(50., -25.));
+        assert_eq!(Topology::Bounded.confine(Vec2::new(10., 5.), WORLD), Vec2::new(10., 5.));
+    }
+
+    #[test]
+    fn edges_bound_distances_outside_the_world() {
+        let outside = Vec2::new(53., 29.);
+        assert_eq!(Topology::Torus.bound_distance(outside, WORLD, 1.), 1.);
+        assert_eq!(Topology::Clamped.bound_distance(outside, WORLD, 1.), 5.);
+        assert_eq!(Topology::Bounded.bound_distance(outside, WORLD, 1.), -5.);
+        // Inside, clamped worlds keep the terrain and bounded worlds are solid near the edges
+        let inside = Vec2::new(48., 0.);
+        assert_eq!(Topology::Clamped.bound_distance(inside, WORLD, 3.), 3.);
+        assert_eq!(Topology::Bounded.bound_distance(inside, WORLD, 3.), 2.);
+    }
+
+    #[test]
+    fn slots_beyond_the_edges_hold_no_tile() {
+        let config = |topology| TileConfig { resident: UVec2::splat(2), world: UVec2::splat(2), topology };
+        let clamped = Tiles::new(config(Topology::Clamped), UVec2::splat(64), Vec2::splat(64.));
+        let (_, changes) = clamped.residency_changes(Vec2::new(40., 0.));
+        assert_eq!(changes.iter().filter(|(_, tile)| tile.is_none()).count(), 2);
+
+        let torus = Tiles::new(config(Topology::Torus), UVec2::splat(64), Vec2::splat(64.));
+        let (_, changes) = torus.residency_changes(Vec2::new(40., 0.));
+        assert!(changes.iter().all(|(_, tile)| tile.is_some()));
+    }
+}